@@ -1,18 +1,44 @@
-use log::{error, warn};
+use log::warn;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use z80::disassembler::{ByteSource, DecodeError, Disassembler};
+use z80::instruction::Instruction;
 
+use crate::decode_cache::{self, CacheKey, DecodeCache};
 use crate::error::GgError;
 use crate::io::Controller;
+#[cfg(test)]
+use crate::io::PortDevice;
 use crate::joystick::{self, Joystick, JoystickPort};
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, MapperType, Page, PAGE_SIZE};
 use crate::memory::Memory;
 use crate::sdsc::{self, DebugConsole};
+use crate::symbols::SymbolTable;
+
+const PAGE_COUNT: usize = 0x10000 / PAGE_SIZE;
+const BIOS_SIZE: usize = 0x400;
+// Mirrored at 0xe000 - 0xffff
+const WORK_RAM_SIZE: usize = 0x2000;
+// The CPU tests see 0xc000 - 0xffff as plain memory
+const FLAT_RAM_SIZE: usize = 0x4000;
 
 pub(crate) const MEMORY_CONTROL_PORT: u8 = 0x3e;
+pub(crate) const IO_CONTROL_PORT: u8 = 0x3f;
 pub(crate) const MEMORY_REGISTER_RAM_MAPPING: u16 = 0xfffc;
 pub const MEMORY_REGISTER_CR_BANK_SELECT_0: u16 = 0xfffd;
 pub const MEMORY_REGISTER_CR_BANK_SELECT_1: u16 = 0xfffe;
 pub const MEMORY_REGISTER_CR_BANK_SELECT_2: u16 = 0xffff;
 
+// Port $3E bits, set to disable
+const MEMORY_CONTROL_EXPANSION: u8 = 0b1000_0000;
+const MEMORY_CONTROL_CARTRIDGE: u8 = 0b0100_0000;
+const MEMORY_CONTROL_CARD: u8 = 0b0010_0000;
+const MEMORY_CONTROL_WORK_RAM: u8 = 0b0001_0000;
+const MEMORY_CONTROL_BIOS: u8 = 0b0000_1000;
+const MEMORY_CONTROL_IO_CHIP: u8 = 0b0000_0100;
+// BIOS, cartridge, work RAM and I/O chip enabled
+const MEMORY_CONTROL_POWER_ON: u8 = MEMORY_CONTROL_EXPANSION | MEMORY_CONTROL_CARD;
+
 #[derive(PartialEq)]
 pub enum Passthrough {
     Bios,
@@ -20,6 +46,13 @@ pub enum Passthrough {
     Ram,
 }
 
+/// Media besides the cartridge, only seen by the CPU while enabled through the memory control port
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Media {
+    Card,
+    Expansion,
+}
+
 pub enum BankSelect {
     Bank0,
     Bank1,
@@ -33,153 +66,184 @@ pub enum RomWriteProtection {
     Allow,
 }
 
+/// What the work RAM holds at power-on. Real consoles come up with garbage, some games depend on it by accident.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum RamFill {
+    Zero,
+    Ones,
+    Random,    // Different on every run
+    Seed(u64), // Random but reproducible
+}
+
+impl FromStr for RamFill {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<RamFill, String> {
+        match value {
+            "zero" => Ok(RamFill::Zero),
+            "ff" => Ok(RamFill::Ones),
+            "random" => Ok(RamFill::Random),
+            seed => seed
+                .parse()
+                .map(RamFill::Seed)
+                .map_err(|_| format!("Expected zero, ff, random or a seed: {}", seed)),
+        }
+    }
+}
+
 pub struct Bus {
-    pub rom: Box<dyn Mapper>,       // 0x0000 - 0xbfff
-    pub ram: Memory<u16>,           // 0xc000 - 0xffff
-    pub sram: Memory<u16>,          // TODO: Depends on cartridge
-    pub bios_rom: Memory<u16>,      // Only for BIOS. Enabled on startup, disabled by end of BIOS
-    pub bios_enabled: bool,         // BIOS is enabled by default
+    pub rom: Box<dyn Mapper>,      // 0x0000 - 0xbfff
+    pub ram: Memory<u16>,          // 0xc000 - 0xdfff, mirrored at 0xe000 - 0xffff
+    pub bios_rom: Memory<u16>,     // Only for BIOS. Enabled on startup, disabled by end of BIOS
+    card: Option<Box<dyn Mapper>>, // Sega Card slot
+    expansion: Option<Box<dyn Mapper>>,
+    memory_control: u8,             // Last value written to the memory control port, BIOS is enabled by default
     gear_to_gear_cache: Option<u8>, // Cache for Gear to Gear communication (ports 0..6)
     pub joysticks: [Joystick; 2],
-    joysticks_enabled: bool,
     pub sdsc_console: DebugConsole,
+    pub symbols: SymbolTable,                 // Labels for the debugger and trace output
     rom_write_protection: RomWriteProtection, // Useful for unit tests that are not SMS/GG specific
     disable_bank_behavior: bool,              // Useful for unit tests that are not SMS/GG specific
+    io_control: u8,                           // Last value written to the I/O control port
+    pub(crate) h_counter_latch_pending: bool, // Set on TH transitions, the VDP latches its H counter
+    pub(crate) decode_cache: DecodeCache,
+    // Value put on the data bus during an interrupt acknowledge. Nothing drives it on SMS/GG so it floats to 0xff.
+    pub data_bus: u8,
+    // Replaces the whole I/O space when set, used by the tests to feed and record port traffic
+    #[cfg(test)]
+    pub(crate) port_device: Option<Box<dyn PortDevice>>,
+    pages: [Page; PAGE_COUNT], // What each 1 KB of the CPU's address space maps to
 }
 
 impl Bus {
     pub(crate) fn new(rom: impl Mapper + 'static) -> Bus {
-        Bus {
+        let mut bus = Bus {
             rom: Box::new(rom),
-            ram: Memory::new(0x1024 * 16, 0x0000), /* changed from 0xc000 */
-            sram: Memory::new(0xffff, 0x0000),     // todo: lol
+            ram: Memory::new(WORK_RAM_SIZE, 0x0000),
             bios_rom: Memory::new(0x400, 0x0000),
-            bios_enabled: true,
+            card: None,
+            expansion: None,
+            memory_control: MEMORY_CONTROL_POWER_ON,
             gear_to_gear_cache: None,
             joysticks: [Joystick::new(JoystickPort::Player1), Joystick::new(JoystickPort::Player2)],
-            joysticks_enabled: true,
             sdsc_console: DebugConsole::new(),
+            symbols: SymbolTable::new(),
             rom_write_protection: RomWriteProtection::Warn,
             disable_bank_behavior: false,
-        }
+            io_control: 0xff,
+            h_counter_latch_pending: false,
+            decode_cache: DecodeCache::new(),
+            data_bus: 0xff,
+            #[cfg(test)]
+            port_device: None,
+            pages: [Page::WorkRam(0); PAGE_COUNT],
+        };
+        bus.update_pages();
+        bus
     }
 
-    #[allow(unused_comparisons)]
+    /// A read by the emulated CPU. Nothing on the bus reacts to reads yet, hardware that latches on them (mappers,
+    /// serial devices) hooks in here. Debuggers and scripts use `peek` instead.
     pub fn read(&self, address: u16) -> Result<u8, GgError> {
-        if self.bios_enabled && address >= 0x0000 && address < 0x0400 {
-            return Ok(self.bios_rom.read(address));
-        }
-
-        if address >= 0x0000 && address < 0x4000 {
-            let bank = if address < 0x400 { 0 } else { self.fetch_bank(BankSelect::Bank0) };
-            return Ok(self.rom.read_from_bank(bank, address));
-        }
+        Ok(self.peek(address))
+    }
 
-        if address >= 0x4000 && address < 0x8000 {
-            let bank = self.fetch_bank(BankSelect::Bank1);
-            return Ok(self.rom.read_from_bank(bank, address - 0x4000));
+    /// What the CPU would read at an address, without anything on the bus noticing
+    pub fn peek(&self, address: u16) -> u8 {
+        let offset = address as usize % PAGE_SIZE;
+        match self.pages[address as usize / PAGE_SIZE] {
+            Page::Bios(base) => self.bios_rom.read((base + offset) as u16),
+            Page::Rom(base) => self.rom.memory().buffer.get(base + offset).copied().unwrap_or(0xff),
+            Page::CartridgeRam(base) => self.rom.read_cartridge_ram(base + offset),
+            Page::WorkRam(base) => self.ram.read((base + offset) as u16),
+            Page::Card(base) => read_media(&self.card, base + offset).unwrap_or(self.data_bus),
+            Page::Expansion(base) => read_media(&self.expansion, base + offset).unwrap_or(self.data_bus),
+            Page::OpenBus => self.data_bus,
         }
+    }
 
-        if address >= 0x8000 && address < 0xc000 {
-            let bank = self.fetch_bank(BankSelect::Bank2);
+    pub fn peek_word(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.peek(address), self.peek(address.wrapping_add(1))])
+    }
 
-            if self.is_sram_bank_active() {
-                let addr = ((bank * 0x4000) + (address - 0x8000) as usize) as u16;
-                return Ok(self.sram.read(addr));
+    /// Changes the memory behind an address without going through the mapper registers or the write protection.
+    /// Devices behind the bus (EEPROM serial ports) are left alone.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        let offset = address as usize % PAGE_SIZE;
+        match self.pages[address as usize / PAGE_SIZE] {
+            Page::Bios(base) => self.bios_rom.write((base + offset) as u16, value),
+            Page::Rom(base) => {
+                self.rom.write(base + offset, value);
+                self.decode_cache.invalidate(CacheKey::Rom(base + offset));
             }
-
-            return Ok(self.rom.read_from_bank(bank, address - 0x8000));
-        }
-
-        if address >= 0xc000 && address <= 0xffff {
-            return Ok(self.ram.read(address - 0xc000));
+            Page::CartridgeRam(base) => self.rom.poke_cartridge_ram(base + offset, value),
+            Page::WorkRam(base) => {
+                self.decode_cache.invalidate(CacheKey::Ram(ram_address(base + offset)));
+                self.ram.write((base + offset) as u16, value);
+            }
+            Page::Card(base) => self.card.iter_mut().for_each(|card| card.write(base + offset, value)),
+            Page::Expansion(base) => self
+                .expansion
+                .iter_mut()
+                .for_each(|expansion| expansion.write(base + offset, value)),
+            Page::OpenBus => {}
         }
-
-        Err(GgError::BusRequestOutOfBounds { address: address as usize })
     }
 
-    #[allow(unused_comparisons)]
     pub fn read_word(&self, address: u16) -> Result<u16, GgError> {
         let low = self.read(address)?;
         let high = self.read(address + 1)?;
         Ok((high as u16) << 8 | low as u16)
     }
 
-    #[allow(unused_comparisons)]
     pub fn write(&mut self, address: u16, value: u8) -> Result<(), GgError> {
-        if self.bios_enabled && address >= 0x0000 && address < 0x0400 {
-            if self.rom_write_protection == RomWriteProtection::Abort {
-                return Err(GgError::WriteToReadOnlyMemory { address: address as usize });
-            }
-
-            if self.rom_write_protection == RomWriteProtection::Warn {
-                warn!("Ignored write to ROM at address {:04x}", address);
-            } else {
-                self.bios_rom.write(address, value);
+        // Every inserted medium sees the write, the slot enables only gate what drives the data bus
+        let mut control_hit = false;
+        if !self.disable_bank_behavior {
+            control_hit = self.rom.write_control(address, value);
+            for media in [&mut self.card, &mut self.expansion].into_iter().flatten() {
+                control_hit |= media.write_control(address, value);
             }
-
-            return Ok(());
         }
 
-        if address >= 0x0000 && address < 0x4000 {
-            if self.rom_write_protection == RomWriteProtection::Abort {
-                return Err(GgError::WriteToReadOnlyMemory { address: address as usize });
-            }
+        if control_hit {
+            self.update_pages();
 
-            if self.rom_write_protection == RomWriteProtection::Warn {
-                warn!("Ignored write to ROM at address {:04x}", address);
-            } else {
-                let bank = if address < 0x400 { 0 } else { self.fetch_bank(BankSelect::Bank0) };
-                self.rom.write_to_bank(bank, address, value);
+            // Registers in the cartridge area don't reach the ROM
+            if address < 0xc000 {
+                return Ok(());
             }
-
-            return Ok(());
         }
 
-        if address >= 0x4000 && address < 0x8000 {
-            if self.rom_write_protection == RomWriteProtection::Abort {
+        let offset = address as usize % PAGE_SIZE;
+        match self.pages[address as usize / PAGE_SIZE] {
+            Page::Bios(_) | Page::Rom(_) | Page::Card(_) | Page::Expansion(_) if self.rom_write_protection == RomWriteProtection::Abort => {
                 return Err(GgError::WriteToReadOnlyMemory { address: address as usize });
             }
-
-            if self.rom_write_protection == RomWriteProtection::Warn {
+            Page::Bios(_) | Page::Rom(_) | Page::Card(_) | Page::Expansion(_) if self.rom_write_protection == RomWriteProtection::Warn => {
                 warn!("Ignored write to ROM at address {:04x}", address);
-            } else {
-                let bank = self.fetch_bank(BankSelect::Bank1);
-                self.rom.write_to_bank(bank, address - 0x4000, value);
             }
-
-            return Ok(());
-        }
-
-        if address >= 0x8000 && address < 0xc000 {
-            if self.is_sram_bank_active() {
-                let bank = self.fetch_bank(BankSelect::Bank2);
-                let addr = ((bank * 0x4000) + (address - 0x8000) as usize) as u16;
-                return Ok(self.sram.write(addr, value));
+            Page::Bios(base) => self.bios_rom.write((base + offset) as u16, value),
+            Page::Rom(base) => {
+                self.rom.write(base + offset, value);
+                self.decode_cache.invalidate(CacheKey::Rom(base + offset));
             }
-
-            if self.rom_write_protection == RomWriteProtection::Abort {
-                return Err(GgError::WriteToReadOnlyMemory { address: address as usize });
+            Page::CartridgeRam(base) => self.rom.write_cartridge_ram(base + offset, value),
+            Page::WorkRam(base) => {
+                self.decode_cache.invalidate(CacheKey::Ram(ram_address(base + offset)));
+                self.ram.write((base + offset) as u16, value);
             }
-
-            if self.rom_write_protection == RomWriteProtection::Warn {
-                warn!("Ignored write to ROM at address {:04x}", address);
-            } else {
-                let bank = self.fetch_bank(BankSelect::Bank2);
-                self.rom.write_to_bank(bank, address - 0x8000, value);
-            }
-
-            return Ok(());
-        }
-
-        if address >= 0xc000 && address <= 0xffff {
-            return Ok(self.ram.write(address - 0xc000, value));
+            Page::Card(base) => self.card.iter_mut().for_each(|card| card.write(base + offset, value)),
+            Page::Expansion(base) => self
+                .expansion
+                .iter_mut()
+                .for_each(|expansion| expansion.write(base + offset, value)),
+            Page::OpenBus => {}
         }
 
-        Err(GgError::BusRequestOutOfBounds { address: address as usize })
+        Ok(())
     }
 
-    #[allow(unused_comparisons)]
     pub fn write_word(&mut self, address: u16, value: u16) -> Result<(), GgError> {
         let low = (value & 0xff) as u8;
         let high = ((value >> 8) & 0xff) as u8;
@@ -192,92 +256,172 @@ impl Bus {
     pub(crate) fn write_passthrough(&mut self, destination: &Passthrough, address: usize, value: u8) {
         match destination {
             Passthrough::Bios => self.bios_rom.write(address as u16, value),
-            Passthrough::Rom => self.rom.write(address, value),
-            Passthrough::Ram => self.ram.write(address as u16, value),
+            Passthrough::Rom => {
+                self.rom.write(address, value);
+                self.decode_cache.invalidate(CacheKey::Rom(address));
+            }
+            Passthrough::Ram => {
+                self.ram.write(address as u16, value);
+                self.decode_cache.invalidate(CacheKey::Ram(ram_address(address)));
+            }
         }
     }
 
-    /// Translate a 16-bit CPU address to a 32-bit ROM address
-    #[allow(unused_comparisons)]
-    pub fn translate_address_to_real(&self, address: u16) -> Result<usize, GgError> {
-        let address = address as usize;
+    // Rebuilt whenever the mapping changes so that every access is a single lookup. With more than one slot enabled
+    // the cartridge wins over the card and the card over the expansion port.
+    fn update_pages(&mut self) {
+        let enabled = |bit: u8| self.memory_control & bit == 0;
+
+        let mut pages = self.pages;
+        for (page, entry) in pages.iter_mut().enumerate() {
+            let address = page * PAGE_SIZE;
+            *entry = if address >= 0xc000 {
+                if enabled(MEMORY_CONTROL_WORK_RAM) {
+                    Page::WorkRam((address - 0xc000) % self.ram.buffer.len())
+                } else {
+                    Page::OpenBus
+                }
+            } else if enabled(MEMORY_CONTROL_BIOS) && address < BIOS_SIZE {
+                Page::Bios(address)
+            } else if self.disable_bank_behavior {
+                Page::Rom(address)
+            } else if enabled(MEMORY_CONTROL_CARTRIDGE) {
+                self.rom.page(page)
+            } else if let Some(card) = self.card.as_ref().filter(|_| enabled(MEMORY_CONTROL_CARD)) {
+                match card.page(page) {
+                    Page::Rom(offset) => Page::Card(offset),
+                    _ => Page::OpenBus,
+                }
+            } else if let Some(expansion) = self.expansion.as_ref().filter(|_| enabled(MEMORY_CONTROL_EXPANSION)) {
+                match expansion.page(page) {
+                    Page::Rom(offset) => Page::Expansion(offset),
+                    _ => Page::OpenBus,
+                }
+            } else {
+                Page::OpenBus
+            };
+        }
+        self.pages = pages;
+    }
+
+    pub fn bios_enabled(&self) -> bool {
+        self.memory_control & MEMORY_CONTROL_BIOS == 0
+    }
+
+    pub fn set_bios_enabled(&mut self, enabled: bool) {
+        self.set_memory_control(match enabled {
+            true => self.memory_control & !MEMORY_CONTROL_BIOS,
+            false => self.memory_control | MEMORY_CONTROL_BIOS,
+        });
+    }
+
+    pub fn memory_control(&self) -> u8 {
+        self.memory_control
+    }
+
+    fn set_memory_control(&mut self, value: u8) {
+        self.memory_control = value;
+        self.update_pages();
+    }
 
-        if address >= 0x0000 && address < 0x4000 {
-            let bank = if address < 0x400 { 0 } else { self.fetch_bank(BankSelect::Bank0) };
-            return Ok(bank * 0x4000 + address);
+    fn io_chip_enabled(&self) -> bool {
+        self.memory_control & MEMORY_CONTROL_IO_CHIP == 0
+    }
+
+    /// Puts a Sega Card or expansion port image into its slot, the Sega mapper's bank registers apply to it
+    pub(crate) fn insert_media(&mut self, media: Media, data: &[u8]) {
+        let mut mapper = MapperType::Sega { ram: None }.create(data.len());
+        mapper.memory_mut().buffer.copy_from_slice(data);
+
+        match media {
+            Media::Card => self.card = Some(mapper),
+            Media::Expansion => self.expansion = Some(mapper),
         }
+        self.update_pages();
+    }
 
-        if address >= 0x4000 && address < 0x8000 {
-            let bank = self.fetch_bank(BankSelect::Bank1);
-            return Ok(bank * 0x4000 + address - 0x4000);
+    /// Swaps the cartridge's mapper, the ROM has to be loaded again afterwards
+    pub(crate) fn set_mapper(&mut self, mapper: Box<dyn Mapper>) {
+        self.rom = mapper;
+        self.update_pages();
+    }
+
+    /// Translate a 16-bit CPU address to a 32-bit ROM address, an error if no ROM bank is paged in there
+    pub fn translate_address_to_real(&self, address: u16) -> Result<usize, GgError> {
+        match self.pages[address as usize / PAGE_SIZE] {
+            Page::Rom(base) => Ok(base + address as usize % PAGE_SIZE),
+            _ => Err(GgError::BusRequestOutOfBounds { address: address as usize }),
         }
+    }
 
-        if address >= 0x8000 && address < 0xc000 {
-            let bank = self.fetch_bank(BankSelect::Bank2);
-            return Ok(bank * 0x4000 + address - 0x8000);
+    pub(crate) fn decode(&mut self, address: u16) -> Result<Instruction, DecodeError> {
+        let key = self.decode_cache_key(address);
+        if let Some(instruction) = key.and_then(|key| self.decode_cache.get(key)) {
+            // The same bytes run at another CPU address once their bank is paged into another slot
+            let mut instruction = instruction.clone();
+            instruction.offset = address as usize;
+            return Ok(instruction);
         }
 
-        if address >= 0xc000 && address <= 0xffff {
-            return Ok(address);
+        // Reads go through the CPU's view of memory and wrap around at 0xffff like the PC does.
+        // Important: For the JSMoo unit tests we must run this with disabled banking behavior as that can trip up
+        // the ROM address calculations
+        let instruction = Disassembler::from_source(&*self).decode(address as usize)?;
+        if let Some(key) = key {
+            self.decode_cache.insert(key, instruction.clone());
         }
 
-        Err(GgError::BusRequestOutOfBounds { address })
+        Ok(instruction)
     }
 
-    pub fn is_sram_bank_active(&self) -> bool {
-        if self.disable_bank_behavior {
-            return false;
+    /// Key under which the instruction at a CPU address can be cached, if it can be cached at all
+    pub(crate) fn decode_cache_key(&self, address: u16) -> Option<CacheKey> {
+        let rom_offset = |address: u16| match self.pages[address as usize / PAGE_SIZE] {
+            Page::Rom(base) => Some(base + address as usize % PAGE_SIZE),
+            _ => None,
+        };
+
+        // Instructions running into the mirror aren't cached, a write to their tail wouldn't find them
+        if address >= 0xc000 {
+            let Page::WorkRam(base) = self.pages[address as usize / PAGE_SIZE] else {
+                return None;
+            };
+            let offset = base + address as usize % PAGE_SIZE;
+            if offset + decode_cache::MAX_INSTRUCTION_LENGTH > self.ram.buffer.len() {
+                return None;
+            }
+            return Some(CacheKey::Ram(ram_address(offset)));
         }
 
-        let ram_mapping = self.read(MEMORY_REGISTER_RAM_MAPPING).unwrap();
-        ram_mapping & 0b0000_1000 > 0
+        // The instruction may straddle two pages that map non-adjacent parts of the ROM, or RAM
+        let last = address.checked_add(decode_cache::MAX_INSTRUCTION_LENGTH as u16 - 1)?;
+        let real = rom_offset(address)?;
+        if last >= 0xc000 || rom_offset(last)? != real + (last - address) as usize {
+            return None;
+        }
+
+        Some(CacheKey::Rom(real))
     }
 
-    pub fn fetch_bank(&self, bank: BankSelect) -> usize {
-        if self.disable_bank_behavior {
-            return match bank {
-                BankSelect::Bank0 => 0,
-                BankSelect::Bank1 => 1,
-                BankSelect::Bank2 => 2,
-            };
-        }
+    /// Peeks the battery backed RAM (or EEPROM) without going through the memory map, 0xff if the cartridge has none
+    pub fn peek_cartridge_ram(&self, offset: usize) -> u8 {
+        self.rom.save_ram().map_or(0xff, |ram| ram.read(offset))
+    }
 
-        // Depending on the mapper revision, the number of significant bits in the bank selection register may vary -
-        // for example, some revisions have 3 significant bits (supporting 8 banks, 128KB total size), others have 5 (512KB),
-        // with the largest known supporting 6 bits (1MB). Some software may also set higher-order bits than those that are
-        // relevant to its ROM size. Provided the ROM is a power-of-two size, these issues do not cause problems,
-        // because ROM mirroring will nullify the effect.
-
-        let bank = (match bank {
-            BankSelect::Bank0 => self.read(MEMORY_REGISTER_CR_BANK_SELECT_0).unwrap(),
-            BankSelect::Bank1 => self.read(MEMORY_REGISTER_CR_BANK_SELECT_1).unwrap(),
-            BankSelect::Bank2 => {
-                if self.is_sram_bank_active() {
-                    let ram_mapping = self.read(MEMORY_REGISTER_RAM_MAPPING).unwrap();
-                    if ram_mapping & 0b0000_0100 == 0 {
-                        0
-                    } else {
-                        1
-                    }
-                } else {
-                    self.read(MEMORY_REGISTER_CR_BANK_SELECT_2).unwrap()
-                }
-            }
-        }) as usize;
-
-        let rom_size = self.rom.memory().buffer.len();
-        let bank = match rom_size {
-            0x20000 => bank & 0b0000_0111,
-            0x40000 => bank & 0b0000_1111,
-            0x80000 => bank & 0b0001_1111,
-            0x100000 => bank & 0b0011_1111,
-            _ => {
-                error!("Unsupported ROM size: {}", rom_size);
-                bank
-            }
+    pub fn is_sram_bank_active(&self) -> bool {
+        matches!(self.pages[0x8000 / PAGE_SIZE], Page::CartridgeRam(_))
+    }
+
+    /// Bank paged into a slot, the SRAM bank while SRAM is active in slot 2
+    pub fn fetch_bank(&self, bank: BankSelect) -> usize {
+        // The second page, the first KB of slot 0 is fixed
+        let page = match bank {
+            BankSelect::Bank0 => 1,
+            BankSelect::Bank1 => 0x4000 / PAGE_SIZE,
+            BankSelect::Bank2 => 0x8000 / PAGE_SIZE,
         };
 
-        bank % (rom_size / 0x4000)
+        self.pages[page].offset() / 0x4000
     }
 
     pub(crate) fn powerup_reset_banks(&mut self) -> Result<(), GgError> {
@@ -293,6 +437,32 @@ impl Bus {
 
     pub fn disable_bank_behavior(&mut self, value: bool) {
         self.disable_bank_behavior = value;
+        self.ram.resize(if value { FLAT_RAM_SIZE } else { WORK_RAM_SIZE });
+        self.update_pages();
+    }
+
+    /// Fills the work RAM like the console would at power-on
+    pub fn fill_ram(&mut self, fill: RamFill) {
+        // Whatever code was cached from RAM is gone
+        self.decode_cache = DecodeCache::new();
+
+        let mut state = match fill {
+            RamFill::Zero => return self.ram.buffer.fill(0x00),
+            RamFill::Ones => return self.ram.buffer.fill(0xff),
+            RamFill::Random => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64),
+            RamFill::Seed(seed) => seed,
+        };
+
+        // xorshift64, the state must not be zero
+        state |= 1;
+        for byte in self.ram.buffer.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = (state >> 32) as u8;
+        }
     }
 }
 
@@ -307,21 +477,21 @@ impl Controller for Bus {
                 }
             }
             joystick::JOYSTICK_AB_PORT => {
-                if self.joysticks_enabled {
+                if self.io_chip_enabled() {
                     self.joysticks[0].read_io(port)
                 } else {
                     Err(GgError::JoystickDisabled)
                 }
             }
             joystick::JOYSTICK_B_MISC_PORT => {
-                if self.joysticks_enabled {
+                if self.io_chip_enabled() {
                     self.joysticks[1].read_io(port)
                 } else {
                     Err(GgError::JoystickDisabled)
                 }
             }
             joystick::JOYSTICK_START_PORT => {
-                if self.joysticks_enabled {
+                if self.io_chip_enabled() {
                     self.joysticks[0].read_io(port)
                 } else {
                     Err(GgError::JoystickDisabled)
@@ -346,12 +516,24 @@ impl Controller for Bus {
 
         match port {
             0x00..=0x06 => self.gear_to_gear_cache = Some(value),
-            MEMORY_CONTROL_PORT => {
-                self.bios_enabled = (value & 0b0000_1000) == 0;
-                self.joysticks_enabled = (value & 0b0000_0100) == 0;
+            MEMORY_CONTROL_PORT => self.set_memory_control(value),
+            IO_CONTROL_PORT => {
+                /*
+                   Port $3F : I/O port control
+                   D7 : Port B TH pin output level (1=high, 0=low)
+                   D5 : Port A TH pin output level (1=high, 0=low)
+                   D3 : Port B TH pin direction (1=input, 0=output)
+                   D1 : Port A TH pin direction (1=input, 0=output)
+                */
+
+                let changed = self.io_control ^ value;
+                let port_a_th_changed = value & 0b0000_0010 == 0 && changed & 0b0010_0000 > 0;
+                let port_b_th_changed = value & 0b0000_1000 == 0 && changed & 0b1000_0000 > 0;
+                self.h_counter_latch_pending |= port_a_th_changed || port_b_th_changed;
+                self.io_control = value;
             }
             sdsc::CONTROL_PORT | sdsc::DATA_PORT => {
-                if !self.joysticks_enabled {
+                if !self.io_chip_enabled() {
                     self.sdsc_console.write_io(port, value)?;
                 }
             }
@@ -361,3 +543,19 @@ impl Controller for Bus {
         Ok(())
     }
 }
+
+// The key RAM-resident code is cached under, mirrors share it
+fn ram_address(offset: usize) -> u16 {
+    0xc000 + offset as u16
+}
+
+fn read_media(media: &Option<Box<dyn Mapper>>, offset: usize) -> Option<u8> {
+    media.as_ref()?.memory().buffer.get(offset).copied()
+}
+
+// Lets the decoder peek at the CPU's view of memory, offsets wrap around like the PC does
+impl ByteSource for Bus {
+    fn read_byte(&self, offset: usize) -> Option<u8> {
+        Some(self.peek(offset as u16))
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use z80::instruction::Instruction;
+
+// Longest Z80 instruction, a write to any of its bytes invalidates it
+pub(crate) const MAX_INSTRUCTION_LENGTH: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) enum CacheKey {
+    Rom(usize), // Real ROM offset, stays valid across bank switches
+    Ram(u16),   // CPU address of RAM-resident code
+}
+
+pub(crate) struct DecodeCache {
+    rom: HashMap<usize, Instruction>,
+    ram: HashMap<u16, Instruction>,
+}
+
+impl DecodeCache {
+    pub(crate) fn new() -> DecodeCache {
+        DecodeCache {
+            rom: HashMap::new(),
+            ram: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: CacheKey) -> Option<&Instruction> {
+        match key {
+            CacheKey::Rom(offset) => self.rom.get(&offset),
+            CacheKey::Ram(address) => self.ram.get(&address),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: CacheKey, instruction: Instruction) {
+        match key {
+            CacheKey::Rom(offset) => self.rom.insert(offset, instruction),
+            CacheKey::Ram(address) => self.ram.insert(address, instruction),
+        };
+    }
+
+    pub(crate) fn invalidate(&mut self, key: CacheKey) {
+        // Most writes go to RAM that never held code, don't pay for the lookups then
+        match key {
+            CacheKey::Rom(offset) if !self.rom.is_empty() => {
+                for idx in 0..MAX_INSTRUCTION_LENGTH {
+                    if let Some(offset) = offset.checked_sub(idx) {
+                        self.rom.remove(&offset);
+                    }
+                }
+            }
+            CacheKey::Ram(address) if !self.ram.is_empty() => {
+                for idx in 0..MAX_INSTRUCTION_LENGTH {
+                    self.ram.remove(&address.wrapping_sub(idx as u16));
+                }
+            }
+            _ => (),
+        }
+    }
+}
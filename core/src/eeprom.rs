@@ -0,0 +1,177 @@
+use crate::save_ram::SaveRam;
+
+// 64 words of 16 bits
+const EEPROM_SIZE: usize = 128;
+
+const DATA: u8 = 0b0000_0001; // DI when written, DO when read
+const CLOCK: u8 = 0b0000_0010;
+const CHIP_SELECT: u8 = 0b0000_0100;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum State {
+    Start,   // Waiting for the start bit
+    Command, // Shifting in the 2 bit opcode and the 6 bit word address
+    Write,   // Shifting in 16 bits for WRITE/WRAL
+    Read,    // Shifting out 16 bits, continues with the next word
+    Standby, // Command done, waiting for CS to go low
+}
+
+/// 93C46 serial EEPROM in 16 bit mode. The game bit-bangs CS, CLK and DI by writing to it and reads DO back, bits are
+/// latched on the rising edge of CLK while CS is high.
+pub struct Eeprom93c46 {
+    storage: SaveRam,
+    state: State,
+    command: u8,
+    buffer: u16,
+    bits: u8,
+    write_enabled: bool,
+    chip_select: bool,
+    clock: bool,
+    data_out: bool,
+}
+
+impl Eeprom93c46 {
+    pub(crate) fn new() -> Eeprom93c46 {
+        let mut storage = SaveRam::new(EEPROM_SIZE);
+        storage.load(&[0xff; EEPROM_SIZE]); // Erased cells read back as ones
+
+        Eeprom93c46 {
+            storage,
+            state: State::Start,
+            command: 0,
+            buffer: 0,
+            bits: 0,
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_out: true,
+        }
+    }
+
+    /// Puts the serial interface back into standby, the contents are kept
+    pub(crate) fn reset(&mut self) {
+        self.state = State::Start;
+        self.write_enabled = false;
+        self.chip_select = false;
+        self.clock = false;
+        self.data_out = true;
+    }
+
+    pub(crate) fn storage(&self) -> &SaveRam {
+        &self.storage
+    }
+
+    pub(crate) fn storage_mut(&mut self) -> &mut SaveRam {
+        &mut self.storage
+    }
+
+    pub(crate) fn read(&self) -> u8 {
+        // CLK always reads back high
+        ((self.chip_select as u8) * CHIP_SELECT) | CLOCK | self.data_out as u8
+    }
+
+    pub(crate) fn write(&mut self, value: u8) {
+        let chip_select = value & CHIP_SELECT != 0;
+        let clock = value & CLOCK != 0;
+
+        if !chip_select {
+            if self.chip_select {
+                // Deselecting ends the command, DO signals ready
+                self.state = State::Start;
+                self.data_out = true;
+            }
+        } else if clock && !self.clock {
+            self.clock_in(value & DATA != 0);
+        }
+
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn clock_in(&mut self, bit: bool) {
+        match self.state {
+            State::Start if bit => {
+                self.command = 0;
+                self.bits = 0;
+                self.state = State::Command;
+            }
+            State::Command => {
+                self.command = self.command << 1 | bit as u8;
+                self.bits += 1;
+                if self.bits == 8 {
+                    self.execute();
+                }
+            }
+            State::Write => {
+                self.buffer = self.buffer << 1 | bit as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.write_enabled {
+                        match self.command >> 6 {
+                            0b01 => self.write_word(self.address(), self.buffer),
+                            _ => (0..EEPROM_SIZE / 2).for_each(|address| self.write_word(address, self.buffer)),
+                        }
+                    }
+                    self.state = State::Standby;
+                }
+            }
+            State::Read => {
+                self.data_out = self.buffer & 0x8000 != 0;
+                self.buffer <<= 1;
+                self.bits += 1;
+                if self.bits == 16 {
+                    // Reads run on into the following words as long as CS stays high
+                    self.command = (self.command & 0b1100_0000) | ((self.address() as u8 + 1) & 0b0011_1111);
+                    self.buffer = self.read_word(self.address());
+                    self.bits = 0;
+                }
+            }
+            State::Start | State::Standby => {}
+        }
+    }
+
+    fn execute(&mut self) {
+        self.bits = 0;
+        self.buffer = 0;
+        self.state = State::Standby;
+
+        match (self.command >> 6, (self.command >> 4) & 0b11) {
+            // READ, DO goes low for the dummy bit before the data
+            (0b10, _) => {
+                self.buffer = self.read_word(self.address());
+                self.data_out = false;
+                self.state = State::Read;
+            }
+            // WRITE and WRAL
+            (0b01, _) | (0b00, 0b01) => self.state = State::Write,
+            // ERASE
+            (0b11, _) => {
+                if self.write_enabled {
+                    self.write_word(self.address(), 0xffff);
+                }
+            }
+            // ERAL
+            (0b00, 0b10) => {
+                if self.write_enabled {
+                    (0..EEPROM_SIZE / 2).for_each(|address| self.write_word(address, 0xffff));
+                }
+            }
+            // EWEN and EWDS
+            (_, enable) => self.write_enabled = enable == 0b11,
+        }
+    }
+
+    fn address(&self) -> usize {
+        (self.command & 0b0011_1111) as usize
+    }
+
+    fn read_word(&self, address: usize) -> u16 {
+        u16::from_le_bytes([self.storage.read(address * 2), self.storage.read(address * 2 + 1)])
+    }
+
+    fn write_word(&mut self, address: usize, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.storage.write(address * 2, low);
+        self.storage.write(address * 2 + 1, high);
+    }
+}
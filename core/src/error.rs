@@ -1,4 +1,5 @@
 use snafu::prelude::*;
+use z80::disassembler::DecodeError;
 use z80::instruction::Opcode;
 
 #[derive(Debug, Snafu, PartialEq, Clone)]
@@ -9,8 +10,8 @@ pub enum GgError {
     BusRequestOutOfBounds { address: usize },
     #[snafu(display("Opcode not implemented {opcode}"))]
     OpcodeNotImplemented { opcode: Opcode },
-    #[snafu(display("Decoder errored with message: {msg}"))]
-    DecoderError { msg: String },
+    #[snafu(display("Decoder error: {error}"))]
+    DecoderError { error: DecodeError },
     #[snafu(display("Jump not taken"))]
     JumpNotTaken,
     #[snafu(display("Breakpoint hit"))]
@@ -23,8 +24,6 @@ pub enum GgError {
     IoControllerInvalidPort,
     #[snafu(display("Invalid VDP I/O mode set"))]
     VdpInvalidIoMode,
-    #[snafu(display("CPU halted"))]
-    CpuHalted,
     #[snafu(display("Joystick disabled"))]
     JoystickDisabled,
     #[snafu(display("Repeat not fulfilled"))]
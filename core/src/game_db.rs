@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::mapper::MapperType;
+use crate::vdp::Mode;
+
+const EMBEDDED_DATABASE: &str = include_str!("game_db.txt");
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum GameSystem {
+    GameGear,
+    MasterSystem,
+    GameGearSmsMode, // Game Gear carts that switch the console into Master System mode
+}
+
+impl GameSystem {
+    pub fn mode(&self) -> Mode {
+        match self {
+            GameSystem::GameGear => Mode::GameGear,
+            GameSystem::MasterSystem | GameSystem::GameGearSmsMode => Mode::SegaMasterSystem,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum GameRegion {
+    Japan,
+    Usa,
+    Europe,
+    World,
+    Brazil,
+    Korea,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SaveType {
+    None,
+    Sram(usize), // Size in bytes
+    Eeprom,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Peripheral {
+    LightPhaser,
+    Paddle,
+    SportsPad,
+    GearToGear,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GameEntry {
+    pub crc32: u32,
+    pub title: String,
+    pub system: GameSystem,
+    pub region: GameRegion,
+    pub mapper: MapperType,
+    pub save: SaveType,
+    pub peripherals: Vec<Peripheral>,
+}
+
+impl GameEntry {
+    /// The mapper to create, the save column picks the EEPROM variant and the cartridge RAM
+    pub fn mapper_type(&self) -> MapperType {
+        match (self.mapper, self.save) {
+            (MapperType::Sega { .. }, SaveType::Eeprom) => MapperType::SegaEeprom,
+            (MapperType::Sega { .. }, save) => MapperType::Sega {
+                ram: match save {
+                    SaveType::Sram(size) => Some(size),
+                    _ => None,
+                },
+            },
+            (MapperType::Codemasters { .. }, save) => MapperType::Codemasters {
+                ram: matches!(save, SaveType::Sram(_)),
+            },
+            (mapper, _) => mapper,
+        }
+    }
+}
+
+/// Per-title settings keyed by CRC32, for what the header and the heuristics can't tell
+pub struct GameDatabase {
+    games: HashMap<u32, GameEntry>,
+}
+
+impl GameDatabase {
+    /// The database built into the emulator
+    pub fn new() -> GameDatabase {
+        let mut database = GameDatabase { games: HashMap::new() };
+        database.load(EMBEDDED_DATABASE);
+        database
+    }
+
+    /// Adds the entries of a database file, replacing known ones. Returns how many were found.
+    pub fn load(&mut self, text: &str) -> usize {
+        let mut count = 0;
+
+        for line in text.lines() {
+            // Only whole lines are comments, titles can contain a '#'
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match parse_entry(line) {
+                Some(entry) => {
+                    self.games.insert(entry.crc32, entry);
+                    count += 1;
+                }
+                None => warn!("Invalid game database entry: {}", line),
+            }
+        }
+
+        count
+    }
+
+    pub fn find(&self, crc32: u32) -> Option<&GameEntry> {
+        self.games.get(&crc32)
+    }
+}
+
+impl Default for GameDatabase {
+    fn default() -> GameDatabase {
+        GameDatabase::new()
+    }
+}
+
+// `crc32 system region mapper save peripherals title`, the title takes the rest of the line
+fn parse_entry(line: &str) -> Option<GameEntry> {
+    let mut fields = [""; 6];
+    let mut rest = line;
+    for field in fields.iter_mut() {
+        let (value, tail) = rest.split_once(char::is_whitespace)?;
+        *field = value;
+        rest = tail.trim_start();
+    }
+    let [crc32, system, region, mapper, save, peripherals] = fields;
+
+    Some(GameEntry {
+        crc32: u32::from_str_radix(crc32, 16).ok()?,
+        title: rest.to_string(),
+        system: match system {
+            "gg" => GameSystem::GameGear,
+            "sms" => GameSystem::MasterSystem,
+            "gg-sms" => GameSystem::GameGearSmsMode,
+            _ => return None,
+        },
+        region: match region {
+            "japan" => GameRegion::Japan,
+            "usa" => GameRegion::Usa,
+            "europe" => GameRegion::Europe,
+            "world" => GameRegion::World,
+            "brazil" => GameRegion::Brazil,
+            "korea" => GameRegion::Korea,
+            _ => return None,
+        },
+        mapper: match mapper {
+            "sega" => MapperType::Sega { ram: None },
+            "codemasters" => MapperType::Codemasters { ram: false },
+            "korean" => MapperType::Korean,
+            "msx" => MapperType::Msx,
+            "msx-nemesis" => MapperType::MsxNemesis,
+            _ => return None,
+        },
+        save: match save {
+            "-" => SaveType::None,
+            "sram" => SaveType::Sram(0x2000),
+            "sram16" => SaveType::Sram(0x4000),
+            "sram32" => SaveType::Sram(0x8000),
+            "eeprom" => SaveType::Eeprom,
+            _ => return None,
+        },
+        peripherals: match peripherals {
+            "-" => Vec::new(),
+            list => list
+                .split(',')
+                .map(|peripheral| match peripheral {
+                    "light-phaser" => Some(Peripheral::LightPhaser),
+                    "paddle" => Some(Peripheral::Paddle),
+                    "sports-pad" => Some(Peripheral::SportsPad),
+                    "gear-to-gear" => Some(Peripheral::GearToGear),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?,
+        },
+    })
+}
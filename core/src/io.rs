@@ -4,3 +4,10 @@ pub(crate) trait Controller {
     fn read_io(&mut self, port: u8) -> Result<u8, GgError>; // in
     fn write_io(&mut self, port: u8, value: u8) -> Result<(), GgError>; // out
 }
+
+// Sees the whole address bus during I/O, the upper half is driven by A or B depending on the instruction
+#[cfg(test)]
+pub(crate) trait PortDevice {
+    fn read_io(&mut self, port: u16) -> Result<u8, GgError>; // in
+    fn write_io(&mut self, port: u16, value: u8) -> Result<(), GgError>; // out
+}
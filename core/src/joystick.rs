@@ -25,6 +25,7 @@ pub struct Joystick {
     pub(crate) port: JoystickPort,
     input: u8,
     start: bool,
+    pub(crate) pause: bool, // SMS only, wired to the CPU's NMI line instead of an I/O port
 }
 
 impl Joystick {
@@ -33,6 +34,7 @@ impl Joystick {
             port,
             input: 0b1111_1111,
             start: false,
+            pause: false,
         }
     }
 
@@ -87,6 +89,10 @@ impl Joystick {
     pub fn set_start(&mut self, start: bool) {
         self.start = start;
     }
+
+    pub fn set_pause(&mut self, pause: bool) {
+        self.pause = pause;
+    }
 }
 
 impl io::Controller for Joystick {
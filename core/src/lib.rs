@@ -4,17 +4,25 @@
 
 mod tests;
 
+mod decode_cache;
+mod eeprom;
 mod error;
-mod handlers;
 mod io;
 mod lua_engine;
+mod machine;
 mod mapper;
 mod memory;
+mod save_ram;
+mod scheduler;
 mod sdsc;
 
 pub mod bus;
-pub mod cpu;
+pub mod game_db;
 pub mod joystick;
 pub mod psg;
+pub mod rom_disassembler;
+pub mod rom_info;
+pub mod symbols;
 pub mod system;
 pub mod vdp;
+pub mod zex;
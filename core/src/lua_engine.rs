@@ -4,10 +4,10 @@ use lazy_static::lazy_static;
 use log::{debug, info};
 use mlua::prelude::*;
 use std::sync::Mutex;
+use z80::cpu::Cpu;
 use z80::instruction::{Reg16, Reg8};
 
 use crate::bus::Bus;
-use crate::cpu::Cpu;
 use crate::vdp::Vdp;
 
 lazy_static! {
@@ -156,8 +156,14 @@ impl LuaEngine {
 
             if self.features.contains(&"vdp".to_string()) {
                 let vdp_table = lua.create_table().unwrap();
-                vdp_table.set("vram", vdp.vram.buffer.clone()).unwrap();
-                vdp_table.set("cram", vdp.cram.buffer.clone()).unwrap();
+                let vram: Vec<u8> = (0..vdp.vram.buffer.len() as u16)
+                    .map(|address| vdp.peek_vram(address))
+                    .collect();
+                let cram: Vec<u8> = (0..vdp.cram.buffer.len() as u16)
+                    .map(|address| vdp.peek_cram(address))
+                    .collect();
+                vdp_table.set("vram", vram).unwrap();
+                vdp_table.set("cram", cram).unwrap();
                 vdp_table.set("h_counter", vdp.h).unwrap();
                 vdp_table.set("v_counter", vdp.v).unwrap();
                 globals.set("vdp", vdp_table).unwrap();
@@ -172,7 +178,9 @@ impl LuaEngine {
             if self.features.contains(&"memory".to_string()) {
                 let memory_table = lua.create_table().unwrap();
                 memory_table.set("rom", bus.rom.memory().buffer.clone()).unwrap();
-                memory_table.set("ram", bus.ram.buffer.clone()).unwrap();
+                // The work RAM as the CPU sees it, mirror included
+                let ram: Vec<u8> = (0xc000..=0xffff).map(|address| bus.peek(address)).collect();
+                memory_table.set("ram", ram).unwrap();
                 memory_table.set("bios_rom", bus.bios_rom.buffer.clone()).unwrap();
                 globals.set("memory", memory_table).unwrap();
             }
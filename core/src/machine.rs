@@ -0,0 +1,182 @@
+use log::{error, trace};
+use z80::bus::Z80Bus;
+use z80::cpu::{Cpu, CpuError};
+use z80::disassembler::DecodeError;
+use z80::instruction::Instruction;
+
+use crate::bus::{self, BankSelect, Bus};
+use crate::error::GgError;
+use crate::io::Controller;
+use crate::psg::Psg;
+use crate::scheduler::CPU_CLOCK_DIVIDER;
+use crate::vdp::{self, Vdp};
+use crate::{joystick, sdsc};
+
+/// The SMS/GG as the Z80 sees it: memory and mapper through the bus, VDP, PSG and the I/O chip on the ports.
+pub(crate) struct Machine<'a> {
+    bus: &'a mut Bus,
+    vdp: &'a mut Vdp,
+    psg: &'a mut Psg,
+    port_clock: usize,              // Master clock cycle the current instruction accesses its port at
+    h_counter_latch: Option<usize>, // Master clock cycle of the last TH transition
+}
+
+impl<'a> Machine<'a> {
+    pub(crate) fn new(bus: &'a mut Bus, vdp: &'a mut Vdp, psg: &'a mut Psg) -> Machine<'a> {
+        Machine {
+            bus,
+            vdp,
+            psg,
+            port_clock: 0,
+            h_counter_latch: None,
+        }
+    }
+
+    /// Master clock cycle at which the VDP has to latch its H counter, if a TH pin changed during the instruction
+    pub(crate) fn h_counter_latch(&self) -> Option<usize> {
+        self.h_counter_latch
+    }
+}
+
+impl Z80Bus for Machine<'_> {
+    type Error = GgError;
+
+    fn read(&mut self, address: u16) -> Result<u8, GgError> {
+        self.bus.read(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), GgError> {
+        self.bus.write(address, value)
+    }
+
+    fn read_word(&mut self, address: u16) -> Result<u16, GgError> {
+        self.bus.read_word(address)
+    }
+
+    fn write_word(&mut self, address: u16, value: u16) -> Result<(), GgError> {
+        self.bus.write_word(address, value)
+    }
+
+    // I/O errors are left to System::tick, which decides whether they abort emulation
+    fn is_fatal(&self, error: &GgError) -> bool {
+        matches!(error, GgError::BusRequestOutOfBounds { .. })
+    }
+
+    fn read_io(&mut self, port: u16) -> Result<u8, GgError> {
+        #[cfg(test)]
+        if let Some(device) = self.bus.port_device.as_mut() {
+            return device.read_io(port);
+        }
+
+        // Only the lower 8 bits of the address bus are decoded
+        let port = port as u8;
+
+        // The VDP only runs when an event is due, the counters and the status have to be current for the CPU
+        match port {
+            0x00..=0x06 => self.bus.read_io(port),
+            vdp::IO_DATA_CONTROL_START..=vdp::IO_DATA_CONTROL_END | 0x40..=0x7f => {
+                self.vdp.catch_up(self.port_clock);
+                self.vdp.read_io(port)
+            }
+            joystick::JOYSTICK_AB_PORT | joystick::JOYSTICK_B_MISC_PORT => self.bus.read_io(port),
+            _ => {
+                error!("Unassigned port (read): {:02x}", port);
+                Err(GgError::IoControllerInvalidPort)
+            }
+        }
+    }
+
+    fn write_io(&mut self, port: u16, value: u8) -> Result<(), GgError> {
+        #[cfg(test)]
+        if let Some(device) = self.bus.port_device.as_mut() {
+            return device.write_io(port, value);
+        }
+
+        let port = port as u8;
+
+        // Writes must not affect what the VDP and PSG should already have produced
+        match port {
+            0x00..=0x06 => self.bus.write_io(port, value)?,
+            vdp::IO_DATA_CONTROL_START..=vdp::IO_DATA_CONTROL_END => {
+                self.vdp.catch_up(self.port_clock);
+                self.vdp.write_io(port, value)?
+            }
+            sdsc::CONTROL_PORT | sdsc::DATA_PORT => self.bus.write_io(port, value)?,
+            bus::MEMORY_CONTROL_PORT => self.bus.write_io(port, value)?,
+            bus::IO_CONTROL_PORT => {
+                self.bus.write_io(port, value)?;
+                if self.bus.h_counter_latch_pending {
+                    self.bus.h_counter_latch_pending = false;
+                    self.h_counter_latch = Some(self.port_clock);
+                }
+            }
+            0x40..=0x7f => {
+                self.psg.catch_up(self.port_clock);
+                self.psg.write_io(port, value)?
+            }
+            _ => {
+                error!("Unassigned port (write): {:02x}", port);
+                return Err(GgError::IoControllerInvalidPort);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.vdp.vblank_irq_pending() || self.vdp.scanline_irq_pending()
+    }
+
+    fn acknowledge_irq(&mut self) -> u8 {
+        self.bus.data_bus
+    }
+
+    fn decode(&mut self, address: u16) -> Result<Instruction, DecodeError> {
+        self.bus.decode(address)
+    }
+
+    fn before_execute(&mut self, cpu: &Cpu, instruction: &Instruction) {
+        let port_cycle = instruction.info().and_then(|info| info.port_cycle).unwrap_or(0);
+        self.port_clock = (cpu.cycles + port_cycle) * CPU_CLOCK_DIVIDER;
+
+        let pc = cpu.registers.pc;
+        let prefix = if pc < 0xc000 { "rom" } else { "ram" };
+        let real_pc_addr = match self.bus.translate_address_to_real(pc) {
+            Ok(rom_addr) => rom_addr,
+            Err(_) => pc as usize, // Not running from ROM, example: code in RAM at the end of the BIOS
+        };
+        trace!(
+            "[{}:{:04x}->{:08x}] {:<24} {:<20} [{:?}]",
+            prefix,
+            pc,
+            real_pc_addr,
+            self.bus.symbols.symbolize(self.bus, pc).unwrap_or_default(),
+            self.bus.symbols.format_instruction(self.bus, pc, instruction),
+            cpu
+        );
+        trace!(
+            "Bank 1: {:02x}  Bank 2: {:02x}  Bank 3: {:02x}  SRAM: {}  V: {}  H: {}  VBlank: {}",
+            self.bus.fetch_bank(BankSelect::Bank0),
+            self.bus.fetch_bank(BankSelect::Bank1),
+            self.bus.fetch_bank(BankSelect::Bank2),
+            self.bus.is_sram_bank_active(),
+            self.vdp.v,
+            self.vdp.h,
+            self.vdp.vblank_irq_pending()
+        );
+    }
+}
+
+impl From<CpuError<GgError>> for GgError {
+    fn from(error: CpuError<GgError>) -> GgError {
+        match error {
+            CpuError::Bus(error) => error,
+            CpuError::Decoder(error) => GgError::DecoderError { error },
+            CpuError::OpcodeNotImplemented { opcode } => GgError::OpcodeNotImplemented { opcode },
+            CpuError::InvalidOpcodeImplementation { instruction } => GgError::InvalidOpcodeImplementation { instruction },
+            CpuError::InvalidInterruptMode { mode } => GgError::InvalidInterruptMode { mode },
+            CpuError::JumpNotTaken => GgError::JumpNotTaken,
+            CpuError::RepeatNotFulfilled => GgError::RepeatNotFulfilled,
+        }
+    }
+}
@@ -1,4 +1,47 @@
+use crate::bus::{MEMORY_REGISTER_CR_BANK_SELECT_0, MEMORY_REGISTER_CR_BANK_SELECT_2, MEMORY_REGISTER_RAM_MAPPING};
+use crate::eeprom::Eeprom93c46;
 use crate::memory::Memory;
+use crate::save_ram::SaveRam;
+
+pub(crate) const PAGE_SIZE: usize = 0x400;
+const BANK_SIZE: usize = 0x4000;
+const PAGE_SIZE_8K: usize = 0x2000;
+// The first KB of slot 0 is never paged out by the Sega mapper so the interrupt vectors stay in place
+const FIXED_AREA_END: usize = 0x400;
+
+const SRAM_ENABLE: u8 = 0b0000_1000;
+const SRAM_BANK_SELECT: u8 = 0b0000_0100;
+// Two 16 KB SRAM banks can be selected through 0xfffc
+pub(crate) const SEGA_SRAM_CAPACITY: usize = 2 * BANK_SIZE;
+
+const EEPROM_ENABLE: u8 = 0b0000_1000;
+const EEPROM_RESET: u8 = 0b1000_0000;
+
+/// What a 1 KB page of the CPU's address space is backed by, with the offset into that memory
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Page {
+    Bios(usize),
+    Rom(usize),
+    CartridgeRam(usize), // RAM on the cartridge that the mapper owns
+    WorkRam(usize),
+    Card(usize),      // Sega Card ROM
+    Expansion(usize), // ROM in the expansion port
+    OpenBus,          // Nothing enabled drives the data bus
+}
+
+impl Page {
+    pub fn offset(&self) -> usize {
+        match self {
+            Page::Bios(offset)
+            | Page::Rom(offset)
+            | Page::CartridgeRam(offset)
+            | Page::WorkRam(offset)
+            | Page::Card(offset)
+            | Page::Expansion(offset) => *offset,
+            Page::OpenBus => 0,
+        }
+    }
+}
 
 pub trait Mapper {
     fn read_from_bank(&self, bank: usize, offset: u16) -> u8;
@@ -8,6 +51,39 @@ pub trait Mapper {
     fn memory_mut(&mut self) -> &mut Memory<usize>;
     fn name(&self) -> String;
 
+    /// What the 1 KB page `page` of 0x0000 - 0xbfff currently maps to
+    fn page(&self, page: usize) -> Page;
+
+    /// Sees every CPU write, returns true if it hit one of the mapper's registers. Register writes in the cartridge
+    /// area never reach the ROM, the ones in work RAM are stored there as well.
+    fn write_control(&mut self, address: u16, value: u8) -> bool;
+
+    /// The battery backed RAM on the cartridge, if it has any
+    fn save_ram(&self) -> Option<&SaveRam> {
+        None
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut SaveRam> {
+        None
+    }
+
+    fn read_cartridge_ram(&self, offset: usize) -> u8 {
+        self.save_ram().map_or(0xff, |ram| ram.read(offset))
+    }
+
+    fn write_cartridge_ram(&mut self, offset: usize, value: u8) {
+        if let Some(ram) = self.save_ram_mut() {
+            ram.write(offset, value);
+        }
+    }
+
+    /// Changes the cartridge RAM for a debugger, without the side effects of a CPU write
+    fn poke_cartridge_ram(&mut self, offset: usize, value: u8) {
+        if let Some(ram) = self.save_ram_mut() {
+            ram.write(offset, value);
+        }
+    }
+
     fn read(&self, address: usize) -> u8 {
         let bank = (address / 0x4000) as usize;
         let addr = (address % 0x4000) as u16;
@@ -21,14 +97,23 @@ pub trait Mapper {
     }
 }
 
+/// The standard Sega mapper: bank registers for the three slots at 0xfffd - 0xffff, 0xfffc pages the cartridge
+/// SRAM into slot 2 on carts that have it
 pub struct SegaMapper {
     pub rom: Memory<usize>,
+    ram: Option<SaveRam>,
+    ram_control: u8,
+    banks: [usize; 3],
 }
 
 impl SegaMapper {
-    pub fn new(size: usize) -> SegaMapper {
+    /// `ram` is the size of the cartridge's SRAM, None if it has none
+    pub fn new(size: usize, ram: Option<usize>) -> SegaMapper {
         SegaMapper {
             rom: Memory::new(size, 0x0000),
+            ram: ram.map(SaveRam::new),
+            ram_control: 0,
+            banks: [0, 1, 2],
         }
     }
 }
@@ -59,4 +144,406 @@ impl Mapper for SegaMapper {
     fn name(&self) -> String {
         String::from("Sega Mapper")
     }
+
+    fn page(&self, page: usize) -> Page {
+        let address = page * PAGE_SIZE;
+        let slot = address / BANK_SIZE;
+
+        // Without SRAM the ROM stays visible
+        if slot == 2 && self.ram.is_some() && self.ram_control & SRAM_ENABLE != 0 {
+            let bank = (self.ram_control & SRAM_BANK_SELECT != 0) as usize;
+            return Page::CartridgeRam(bank * BANK_SIZE + address % BANK_SIZE);
+        }
+
+        let bank = if address < FIXED_AREA_END { 0 } else { self.banks[slot] };
+        Page::Rom(mirror(&self.rom, bank, BANK_SIZE) * BANK_SIZE + address % BANK_SIZE)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            MEMORY_REGISTER_RAM_MAPPING => self.ram_control = value,
+            MEMORY_REGISTER_CR_BANK_SELECT_0..=MEMORY_REGISTER_CR_BANK_SELECT_2 => {
+                self.banks[(address - MEMORY_REGISTER_CR_BANK_SELECT_0) as usize] = value as usize
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn save_ram(&self) -> Option<&SaveRam> {
+        self.ram.as_ref()
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut SaveRam> {
+        self.ram.as_mut()
+    }
+}
+
+// Depending on the mapper revision the bank registers have 3 to 6 significant bits and software may set bits above
+// what its ROM needs. Wrapping around the ROM size has the same effect as long as the size is a power of two.
+fn mirror(rom: &Memory<usize>, bank: usize, bank_size: usize) -> usize {
+    match rom.buffer.len() / bank_size {
+        0 => bank, // Nothing loaded yet
+        banks => bank % banks,
+    }
+}
+
+/// Sega mapper carts that save to a 93C46 serial EEPROM instead of SRAM. 0xfffc maps the EEPROM's serial port to
+/// 0x8000 - 0x83ff, the bank registers behave like the Sega mapper's.
+pub struct EepromMapper {
+    sega: SegaMapper,
+    eeprom: Eeprom93c46,
+    eeprom_enabled: bool,
+}
+
+impl EepromMapper {
+    pub fn new(size: usize) -> EepromMapper {
+        EepromMapper {
+            sega: SegaMapper::new(size, None),
+            eeprom: Eeprom93c46::new(),
+            eeprom_enabled: false,
+        }
+    }
+}
+
+impl Mapper for EepromMapper {
+    fn read_from_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.sega.read_from_bank(bank, offset)
+    }
+
+    fn write_to_bank(&mut self, bank: usize, offset: u16, value: u8) {
+        self.sega.write_to_bank(bank, offset, value);
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.sega.resize(new_size);
+    }
+
+    fn memory(&self) -> &Memory<usize> {
+        self.sega.memory()
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory<usize> {
+        self.sega.memory_mut()
+    }
+
+    fn name(&self) -> String {
+        String::from("Sega Mapper (93C46 EEPROM)")
+    }
+
+    fn page(&self, page: usize) -> Page {
+        if self.eeprom_enabled && page == 0x8000 / PAGE_SIZE {
+            return Page::CartridgeRam(0);
+        }
+
+        self.sega.page(page)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) -> bool {
+        if address != MEMORY_REGISTER_RAM_MAPPING {
+            return self.sega.write_control(address, value);
+        }
+
+        self.eeprom_enabled = value & EEPROM_ENABLE != 0;
+        if value & EEPROM_RESET != 0 {
+            self.eeprom.reset();
+        }
+        true
+    }
+
+    fn save_ram(&self) -> Option<&SaveRam> {
+        Some(self.eeprom.storage())
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut SaveRam> {
+        Some(self.eeprom.storage_mut())
+    }
+
+    // The serial port is all there is in the mapped page
+    fn read_cartridge_ram(&self, _offset: usize) -> u8 {
+        self.eeprom.read()
+    }
+
+    fn write_cartridge_ram(&mut self, _offset: usize, value: u8) {
+        self.eeprom.write(value);
+    }
+
+    // Poking the serial port would clock the EEPROM, its contents are reachable through the save RAM instead
+    fn poke_cartridge_ram(&mut self, _offset: usize, _value: u8) {}
+}
+
+/// Codemasters carts: a bank register at the start of every slot (0x0000, 0x4000, 0x8000) and no fixed first KB.
+/// Setting bit 7 of the 0x4000 register maps 8 KB of on-cart RAM to 0xa000 - 0xbfff on carts that have it.
+pub struct CodemastersMapper {
+    pub rom: Memory<usize>,
+    banks: [usize; 3],
+    ram: Option<SaveRam>,
+    ram_enabled: bool,
+}
+
+impl CodemastersMapper {
+    pub fn new(size: usize, has_ram: bool) -> CodemastersMapper {
+        CodemastersMapper {
+            rom: Memory::new(size, 0x0000),
+            banks: [0, 1, 0],
+            ram: has_ram.then(|| SaveRam::new(PAGE_SIZE_8K)),
+            ram_enabled: false,
+        }
+    }
+}
+
+impl Mapper for CodemastersMapper {
+    fn read_from_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom.read(bank * BANK_SIZE + offset as usize)
+    }
+
+    fn write_to_bank(&mut self, bank: usize, offset: u16, value: u8) {
+        self.rom.write(bank * BANK_SIZE + offset as usize, value);
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.rom.resize(new_size);
+    }
+
+    fn memory(&self) -> &Memory<usize> {
+        &self.rom
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory<usize> {
+        &mut self.rom
+    }
+
+    fn name(&self) -> String {
+        String::from("Codemasters Mapper")
+    }
+
+    fn page(&self, page: usize) -> Page {
+        let address = page * PAGE_SIZE;
+        if self.ram_enabled && self.ram.is_some() && address >= 0xa000 {
+            return Page::CartridgeRam(address - 0xa000);
+        }
+
+        let bank = self.banks[address / BANK_SIZE];
+        Page::Rom(mirror(&self.rom, bank, BANK_SIZE) * BANK_SIZE + address % BANK_SIZE)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x0000 | 0x8000 => self.banks[address as usize / BANK_SIZE] = value as usize,
+            0x4000 => {
+                self.banks[1] = (value & 0b0111_1111) as usize;
+                self.ram_enabled = value & 0b1000_0000 != 0;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn save_ram(&self) -> Option<&SaveRam> {
+        self.ram.as_ref()
+    }
+
+    fn save_ram_mut(&mut self) -> Option<&mut SaveRam> {
+        self.ram.as_mut()
+    }
+}
+
+/// Korean carts with a single bank register at 0xa000 selecting the bank in slot 2, slots 0 and 1 are fixed
+pub struct KoreanMapper {
+    pub rom: Memory<usize>,
+    bank: usize,
+}
+
+impl KoreanMapper {
+    pub fn new(size: usize) -> KoreanMapper {
+        KoreanMapper {
+            rom: Memory::new(size, 0x0000),
+            bank: 2,
+        }
+    }
+}
+
+impl Mapper for KoreanMapper {
+    fn read_from_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom.read(bank * BANK_SIZE + offset as usize)
+    }
+
+    fn write_to_bank(&mut self, bank: usize, offset: u16, value: u8) {
+        self.rom.write(bank * BANK_SIZE + offset as usize, value);
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.rom.resize(new_size);
+    }
+
+    fn memory(&self) -> &Memory<usize> {
+        &self.rom
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory<usize> {
+        &mut self.rom
+    }
+
+    fn name(&self) -> String {
+        String::from("Korean Mapper")
+    }
+
+    fn page(&self, page: usize) -> Page {
+        let address = page * PAGE_SIZE;
+        let bank = [0, 1, self.bank][address / BANK_SIZE];
+        Page::Rom(mirror(&self.rom, bank, BANK_SIZE) * BANK_SIZE + address % BANK_SIZE)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) -> bool {
+        if address != 0xa000 {
+            return false;
+        }
+
+        self.bank = value as usize;
+        true
+    }
+}
+
+/// Korean conversions of MSX games: 8 KB pages selected through registers at 0x0000 - 0x0003 for 0x8000, 0xa000,
+/// 0x4000 and 0x6000 (in that order). 0x0000 - 0x3fff stays on the first two pages, except for the Nemesis variant
+/// which maps the last page of the ROM to 0x0000 - 0x1fff.
+pub struct MsxMapper {
+    pub rom: Memory<usize>,
+    pages: [usize; 4],
+    nemesis: bool,
+}
+
+impl MsxMapper {
+    pub fn new(size: usize, nemesis: bool) -> MsxMapper {
+        MsxMapper {
+            rom: Memory::new(size, 0x0000),
+            pages: [0; 4],
+            nemesis,
+        }
+    }
+}
+
+impl Mapper for MsxMapper {
+    fn read_from_bank(&self, bank: usize, offset: u16) -> u8 {
+        self.rom.read(bank * BANK_SIZE + offset as usize)
+    }
+
+    fn write_to_bank(&mut self, bank: usize, offset: u16, value: u8) {
+        self.rom.write(bank * BANK_SIZE + offset as usize, value);
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        self.rom.resize(new_size);
+    }
+
+    fn memory(&self) -> &Memory<usize> {
+        &self.rom
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory<usize> {
+        &mut self.rom
+    }
+
+    fn name(&self) -> String {
+        if self.nemesis {
+            String::from("MSX Mapper (Nemesis)")
+        } else {
+            String::from("MSX Mapper")
+        }
+    }
+
+    fn page(&self, page: usize) -> Page {
+        let address = page * PAGE_SIZE;
+        let rom_page = match address / PAGE_SIZE_8K {
+            0 if self.nemesis => (self.rom.buffer.len() / PAGE_SIZE_8K).saturating_sub(1),
+            0 => 0,
+            1 => 1,
+            2 => self.pages[2],
+            3 => self.pages[3],
+            4 => self.pages[0],
+            _ => self.pages[1],
+        };
+        Page::Rom(mirror(&self.rom, rom_page, PAGE_SIZE_8K) * PAGE_SIZE_8K + address % PAGE_SIZE_8K)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) -> bool {
+        if address > 0x0003 {
+            return false;
+        }
+
+        self.pages[address as usize] = value as usize;
+        true
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum MapperType {
+    Sega { ram: Option<usize> },
+    SegaEeprom,
+    Codemasters { ram: bool },
+    Korean,
+    Msx,
+    MsxNemesis,
+}
+
+impl MapperType {
+    pub fn create(&self, size: usize) -> Box<dyn Mapper> {
+        match self {
+            MapperType::Sega { ram } => Box::new(SegaMapper::new(size, *ram)),
+            MapperType::SegaEeprom => Box::new(EepromMapper::new(size)),
+            MapperType::Codemasters { ram } => Box::new(CodemastersMapper::new(size, *ram)),
+            MapperType::Korean => Box::new(KoreanMapper::new(size)),
+            MapperType::Msx => Box::new(MsxMapper::new(size, false)),
+            MapperType::MsxNemesis => Box::new(MsxMapper::new(size, true)),
+        }
+    }
+}
+
+// ROMs up to 48 KB fit into the three slots, no mapper needed
+const UNBANKED_SIZE: usize = 3 * BANK_SIZE;
+const CODEMASTERS_CHECKSUM: usize = 0x7fe6;
+const SEGA_UNKNOWN_RAM: MapperType = MapperType::Sega {
+    ram: Some(SEGA_SRAM_CAPACITY),
+};
+
+/// Guesses the mapper of a cartridge the game database doesn't know: the Codemasters header first, then whichever
+/// bank registers the code stores to most often. Whether a Sega mapper cart has SRAM isn't written anywhere, so it
+/// gets as much as the mapper can address and the .sav file only holds the part the game uses.
+pub fn detect(rom: &[u8]) -> MapperType {
+    if rom.len() <= UNBANKED_SIZE {
+        return SEGA_UNKNOWN_RAM;
+    }
+
+    // Codemasters games store a checksum and its complement, they add up to 0x10000
+    let word = |offset: usize| rom[offset] as usize | (rom[offset + 1] as usize) << 8;
+    let checksum = word(CODEMASTERS_CHECKSUM);
+    if checksum != 0 && checksum + word(CODEMASTERS_CHECKSUM + 2) == 0x10000 {
+        return MapperType::Codemasters { ram: false };
+    }
+
+    // Count `ld [nnnn], a` to the register addresses of each mapper, Sega wins ties
+    let mut votes = [
+        (SEGA_UNKNOWN_RAM, 0),
+        (MapperType::Codemasters { ram: false }, 0),
+        (MapperType::Korean, 0),
+        (MapperType::Msx, 0),
+    ];
+    for window in rom.windows(3).filter(|window| window[0] == 0x32) {
+        let target = window[1] as u16 | (window[2] as u16) << 8;
+        let mapper = match target {
+            0xfffd..=0xffff => 0,
+            0x4000 | 0x8000 => 1,
+            0xa000 => 2,
+            0x0000..=0x0003 => 3,
+            _ => continue,
+        };
+        votes[mapper].1 += 1;
+    }
+
+    votes
+        .iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(mapper, _)| *mapper)
+        .unwrap()
 }
@@ -2,6 +2,7 @@ use log::{debug, error};
 
 use crate::error::GgError;
 use crate::io::Controller;
+use crate::scheduler::PSG_CLOCK_DIVIDER;
 
 struct Latch {
     data: u16,
@@ -20,6 +21,7 @@ struct Channel {
 pub struct Psg {
     channels: [Channel; 4],
     latch: Option<Latch>,
+    clock: usize, // Master clock cycle the PSG has been caught up to
 }
 
 impl Psg {
@@ -32,6 +34,32 @@ impl Psg {
                 counter: 0,
             }; 4],
             latch: None,
+            clock: 0,
+        }
+    }
+
+    /// Tone (or noise control for channel 3) of a channel, reading it back isn't possible on hardware
+    pub fn peek_tone(&self, channel: usize) -> u16 {
+        self.channels[channel].tone_or_noise
+    }
+
+    pub fn peek_volume(&self, channel: usize) -> u8 {
+        self.channels[channel].volume
+    }
+
+    /// Sets a channel's tone without going through the latch or restarting its counter
+    pub fn poke_tone(&mut self, channel: usize, value: u16) {
+        self.channels[channel].tone_or_noise = value;
+    }
+
+    pub fn poke_volume(&mut self, channel: usize, value: u8) {
+        self.channels[channel].volume = value;
+    }
+
+    pub(crate) fn catch_up(&mut self, clock: usize) {
+        while self.clock < clock {
+            self.tick();
+            self.clock += PSG_CLOCK_DIVIDER;
         }
     }
 
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+
+use z80::assembler::Assembler;
+use z80::disassembler::Disassembler;
+use z80::instruction::{Condition, Immediate, Instruction, Opcode, Operand, Reg8, Register};
+
+use crate::bus::{MEMORY_REGISTER_CR_BANK_SELECT_0, MEMORY_REGISTER_CR_BANK_SELECT_2};
+
+const BANK_SIZE: usize = 0x4000;
+const SLOT_COUNT: usize = 3;
+// The first KB of slot 0 is never paged out so the vectors stay reachable
+const FIXED_AREA_END: u16 = 0x0400;
+const DATA_BYTES_PER_LINE: usize = 16;
+
+// Reset, maskable interrupt (IM 1) and NMI (pause button), every trace starts from these
+const VECTORS: [(u16, &str); 3] = [(0x0000, "reset"), (0x0038, "irq"), (0x0066, "nmi")];
+
+// Bank paged into each of the three slots, None once a game wrote a value we couldn't follow
+type SlotMapping = [Option<usize>; SLOT_COUNT];
+
+struct CodeEntry {
+    instruction: Instruction,
+    target: Option<(u16, usize)>, // CPU address and ROM offset of a jump/call target
+}
+
+/// Listing of a single ROM bank, assembles on its own back to the bank's bytes.
+pub struct BankListing {
+    pub bank: usize,
+    pub origin: u16, // Base of the slot the bank was first reached in
+    pub reached: bool,
+    pub code_bytes: usize,
+    pub source: String,
+}
+
+/// Banks in the order the trace reached them, followed by the ones it never reached
+pub struct Listing {
+    pub banks: Vec<BankListing>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bank in &self.banks {
+            writeln!(f, "{}", bank.source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Recursive-descent disassembler for Sega mapper cartridges.
+///
+/// Starts at the reset, IRQ and NMI vectors (the SEGA header carries no entry point of its own, further ones can be
+/// added with `add_entry_point`) and follows `jp`, `jr`, `djnz`, `call` and `rst` targets. Bank switches are tracked
+/// for the common `ld a, n` / `ld [#ffff], a` sequence so targets resolve to the bank paged into their slot at that
+/// point. Everything that's never reached is emitted as `db`.
+pub struct RomDisassembler<'a> {
+    rom: &'a [u8],
+    entry_points: Vec<(u16, String)>,
+}
+
+impl<'a> RomDisassembler<'a> {
+    pub fn new(rom: &'a [u8]) -> RomDisassembler<'a> {
+        RomDisassembler {
+            rom,
+            entry_points: Vec::new(),
+        }
+    }
+
+    /// Adds an entry point with the initial slot mapping (banks 0, 1 and 2)
+    pub fn add_entry_point(&mut self, address: u16, name: &str) {
+        self.entry_points.push((address, name.to_string()));
+    }
+
+    // The vectors first, then the entry points that were added
+    fn seeds(&self) -> impl Iterator<Item = (u16, &str)> {
+        VECTORS
+            .into_iter()
+            .chain(self.entry_points.iter().map(|(address, name)| (*address, name.as_str())))
+    }
+
+    pub fn disassemble(&self) -> Listing {
+        let (code, discovered) = self.trace();
+
+        // Each bank is listed at the slot it was first reached in, unreached banks default to their power-on slot
+        let origins: HashMap<usize, u16> = discovered.iter().copied().collect();
+        let origin_of = |bank: usize| -> u16 { *origins.get(&bank).unwrap_or(&((bank.min(SLOT_COUNT - 1) * BANK_SIZE) as u16)) };
+
+        let mut names: HashMap<usize, String> = HashMap::new();
+        for (address, name) in self.seeds() {
+            if let Some(offset) = self.resolve(address, &[Some(0), Some(1), Some(2)]) {
+                names.entry(offset).or_insert(name.to_string());
+            }
+        }
+
+        // A target only gets a label if it starts an instruction and the label's address matches the operand
+        let label_of = |target: Option<(u16, usize)>| -> Option<(String, u16)> {
+            let (address, offset) = target?;
+            if !code.contains_key(&offset) {
+                return None;
+            }
+            let bank = offset / BANK_SIZE;
+            let label_address = origin_of(bank) + (offset % BANK_SIZE) as u16;
+            if label_address != address {
+                return None;
+            }
+            let name = names
+                .get(&offset)
+                .cloned()
+                .unwrap_or_else(|| format!("l{:02x}_{:04x}", bank, label_address));
+            Some((name, label_address))
+        };
+
+        let mut labels: BTreeSet<usize> = code
+            .values()
+            .filter(|entry| label_of(entry.target).is_some())
+            .filter_map(|entry| entry.target.map(|(_, offset)| offset))
+            .collect();
+        labels.extend(names.keys().filter(|offset| code.contains_key(offset)));
+
+        let bank_count = self.rom.len().div_ceil(BANK_SIZE);
+        let unreached = (0..bank_count).filter(|bank| !origins.contains_key(bank));
+        let banks = discovered
+            .iter()
+            .map(|(bank, _)| *bank)
+            .chain(unreached)
+            .map(|bank| {
+                let origin = origin_of(bank);
+                let start = bank * BANK_SIZE;
+                let end = (start + BANK_SIZE).min(self.rom.len());
+
+                let mut body = String::new();
+                let mut externals = BTreeMap::new();
+                let mut data = Vec::new();
+                let mut code_bytes = 0;
+                let mut offset = start;
+
+                while offset < end {
+                    let address = origin + (offset - start) as u16;
+                    let entry = code.get(&offset);
+
+                    if entry.is_none() {
+                        data.push(self.rom[offset]);
+                    }
+                    if !data.is_empty() && (entry.is_some() || data.len() == DATA_BYTES_PER_LINE || offset + 1 == end) {
+                        body.push_str(&format!("        {}\n", data_directive(&data)));
+                        data.clear();
+                    }
+
+                    let Some(entry) = entry else {
+                        offset += 1;
+                        continue;
+                    };
+
+                    if labels.contains(&offset) {
+                        let (name, _) = label_of(Some((address, offset))).unwrap();
+                        body.push_str(&format!("{}:\n", name));
+                    }
+
+                    let bytes = &self.rom[offset..offset + entry.instruction.length];
+                    let text = source_text(&entry.instruction, address);
+
+                    // Undocumented duplicates would assemble to the documented encoding, keep their bytes
+                    if Assembler::assemble_instruction(&text, address).ok().as_deref() != Some(bytes) {
+                        body.push_str(&format!("        {} ; {}\n", data_directive(bytes), text));
+                    } else if let Some((name, label_address)) = label_of(entry.target).filter(|_| has_target_operand(&entry.instruction)) {
+                        let operand = text.rfind(' ').unwrap() + 1;
+                        body.push_str(&format!("        {}{}\n", &text[..operand], name));
+                        if entry.target.is_some_and(|(_, target)| target / BANK_SIZE != bank) {
+                            externals.insert(name, label_address);
+                        }
+                    } else {
+                        body.push_str(&format!("        {}\n", text));
+                    }
+
+                    code_bytes += bytes.len();
+                    offset += bytes.len();
+                }
+
+                let reached = origins.contains_key(&bank);
+                let mut source = match reached {
+                    true => format!("; bank {:02x}\n", bank),
+                    false => format!("; bank {:02x}, never reached\n", bank),
+                };
+                for (name, address) in externals {
+                    source.push_str(&format!("{} equ #{:04x}\n", name, address));
+                }
+                source.push_str(&format!("        org #{:04x}\n", origin));
+                source.push_str(&body);
+
+                BankListing {
+                    bank,
+                    origin,
+                    reached,
+                    code_bytes,
+                    source,
+                }
+            })
+            .collect();
+
+        Listing { banks }
+    }
+
+    // Follows the control flow from every entry point, returns the reached instructions by ROM offset and the banks
+    // with the slot they were first reached in, in the order they were reached
+    fn trace(&self) -> (BTreeMap<usize, CodeEntry>, Vec<(usize, u16)>) {
+        let mut code: BTreeMap<usize, CodeEntry> = BTreeMap::new();
+        let mut discovered: Vec<(usize, u16)> = Vec::new();
+        let mut covered = vec![false; self.rom.len()];
+        let mut queue: VecDeque<(u16, SlotMapping)> = self
+            .seeds()
+            .map(|(address, _)| (address, [Some(0), Some(1), Some(2)]))
+            .collect();
+
+        while let Some((mut pc, mut mapping)) = queue.pop_front() {
+            let mut accumulator = None;
+
+            while let Some(offset) = self.resolve(pc, &mapping) {
+                if code.contains_key(&offset) {
+                    break;
+                }
+                let Ok(instruction) = Disassembler::new(self.rom).decode(offset) else {
+                    break;
+                };
+
+                // Stop at instructions overlapping known code or running off the end of the bank
+                let length = instruction.length;
+                if (offset % BANK_SIZE) + length > BANK_SIZE || covered[offset..offset + length].iter().any(|c| *c) {
+                    break;
+                }
+                covered[offset..offset + length].iter_mut().for_each(|c| *c = true);
+
+                let next = pc.wrapping_add(length as u16);
+                let (target, flow_continues) = match instruction.opcode {
+                    Opcode::Jump(condition, Operand::Immediate(Immediate::U16(target), false), _) => {
+                        (Some(target), condition != Condition::None)
+                    }
+                    Opcode::Jump(_, _, _) => (None, false), // jp [hl] and friends
+                    Opcode::JumpRelative(condition, Immediate::S8(displacement), _) => {
+                        (Some(next.wrapping_add(displacement as u16)), condition != Condition::None)
+                    }
+                    Opcode::DecrementAndJumpRelative(Immediate::S8(displacement), _) => {
+                        (Some(next.wrapping_add(displacement as u16)), true)
+                    }
+                    Opcode::Call(_, Operand::Immediate(Immediate::U16(target), false), _) => (Some(target), true),
+                    Opcode::Restart(Immediate::U8(target), _) => (Some(target as u16), true),
+                    Opcode::Return(condition, _) => (None, condition != Condition::None),
+                    Opcode::ReturnFromIrq(_) | Opcode::ReturnFromNmi(_) => (None, false),
+                    _ => (None, true),
+                };
+
+                // Bank switches through the mapper registers
+                match instruction.opcode {
+                    Opcode::Load(Operand::Register(Register::Reg8(Reg8::A), false), Operand::Immediate(Immediate::U8(value), false), _) => {
+                        accumulator = Some(value)
+                    }
+                    Opcode::Load(
+                        Operand::Immediate(Immediate::U16(address), true),
+                        Operand::Register(Register::Reg8(Reg8::A), false),
+                        _,
+                    ) if (MEMORY_REGISTER_CR_BANK_SELECT_0..=MEMORY_REGISTER_CR_BANK_SELECT_2).contains(&address) => {
+                        let slot = (address - MEMORY_REGISTER_CR_BANK_SELECT_0) as usize;
+                        mapping[slot] = accumulator.map(|bank| bank as usize);
+                    }
+                    _ => accumulator = None,
+                }
+
+                let target = target.and_then(|target| self.resolve(target, &mapping).map(|offset| (target, offset)));
+                if let Some((address, _)) = target {
+                    queue.push_back((address, mapping));
+                }
+
+                let bank = offset / BANK_SIZE;
+                if !discovered.iter().any(|(known, _)| *known == bank) {
+                    discovered.push((bank, pc & !(BANK_SIZE as u16 - 1)));
+                }
+
+                code.insert(offset, CodeEntry { instruction, target });
+
+                if !flow_continues {
+                    break;
+                }
+                pc = next;
+            }
+        }
+
+        (code, discovered)
+    }
+
+    // ROM offset of a CPU address, None for RAM and slots paged to an unknown bank
+    fn resolve(&self, address: u16, mapping: &SlotMapping) -> Option<usize> {
+        let bank_count = self.rom.len().div_ceil(BANK_SIZE);
+        if bank_count == 0 {
+            return None;
+        }
+
+        let slot = address as usize / BANK_SIZE;
+        let bank = if address < FIXED_AREA_END {
+            0
+        } else if slot < SLOT_COUNT {
+            mapping[slot]? % bank_count
+        } else {
+            return None;
+        };
+
+        let offset = bank * BANK_SIZE + (address as usize % BANK_SIZE);
+        (offset < self.rom.len()).then_some(offset)
+    }
+}
+
+// The disassembler prints the displacement of jr and djnz, the assembler takes the target address
+fn source_text(instruction: &Instruction, address: u16) -> String {
+    let text = instruction.opcode.to_string();
+    match instruction.opcode {
+        Opcode::JumpRelative(_, Immediate::S8(displacement), _) | Opcode::DecrementAndJumpRelative(Immediate::S8(displacement), _) => {
+            let target = address
+                .wrapping_add(instruction.length as u16)
+                .wrapping_add(displacement as u16);
+            let operand = text.rfind(' ').unwrap() + 1;
+            format!("{}#{:04x}", &text[..operand], target)
+        }
+        _ => text,
+    }
+}
+
+fn has_target_operand(instruction: &Instruction) -> bool {
+    matches!(
+        instruction.opcode,
+        Opcode::Jump(_, _, _) | Opcode::JumpRelative(_, _, _) | Opcode::DecrementAndJumpRelative(_, _) | Opcode::Call(_, _, _)
+    )
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|value| format!("#{:02x}", value)).collect();
+    format!("db {}", values.join(", "))
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+const HEADER_MAGIC: &[u8; 8] = b"TMR SEGA";
+const HEADER_SIZE: usize = 0x10;
+// The BIOS looks at 0x7ff0 first, small ROMs carry the header at the end of their last 8 or 16 KB
+const HEADER_OFFSETS: [usize; 3] = [0x7ff0, 0x3ff0, 0x1ff0];
+// Anything above 32 KB is summed around the header
+const HEADER_BANK_END: usize = 0x8000;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Region {
+    SmsJapan,
+    SmsExport,
+    GgJapan,
+    GgExport,
+    GgInternational,
+    Unknown(u8),
+}
+
+impl Region {
+    fn from_code(code: u8) -> Region {
+        match code {
+            3 => Region::SmsJapan,
+            4 => Region::SmsExport,
+            5 => Region::GgJapan,
+            6 => Region::GgExport,
+            7 => Region::GgInternational,
+            code => Region::Unknown(code),
+        }
+    }
+
+    pub fn is_game_gear(&self) -> bool {
+        matches!(self, Region::GgJapan | Region::GgExport | Region::GgInternational)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::SmsJapan => write!(f, "SMS Japan"),
+            Region::SmsExport => write!(f, "SMS Export"),
+            Region::GgJapan => write!(f, "GG Japan"),
+            Region::GgExport => write!(f, "GG Export"),
+            Region::GgInternational => write!(f, "GG International"),
+            Region::Unknown(code) => write!(f, "Unknown ({:x})", code),
+        }
+    }
+}
+
+/// The "TMR SEGA" header
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RomHeader {
+    pub offset: usize,
+    pub checksum: u16,
+    pub product_code: u32,
+    pub version: u8,
+    pub region: Region,
+    pub declared_size: Option<usize>, // None for size codes that aren't defined
+    pub checksum_valid: bool,         // False as well if the declared size doesn't fit the ROM
+}
+
+/// What we know about a cartridge from its contents
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RomInfo {
+    pub size: usize,
+    pub crc32: u32,
+    pub header: Option<RomHeader>,
+}
+
+impl RomInfo {
+    pub fn parse(rom: &[u8]) -> RomInfo {
+        let header = HEADER_OFFSETS
+            .iter()
+            .find(|&&offset| rom.get(offset..offset + HEADER_MAGIC.len()) == Some(HEADER_MAGIC))
+            .map(|&offset| parse_header(rom, offset));
+
+        RomInfo {
+            size: rom.len(),
+            crc32: crc32fast::hash(rom),
+            header,
+        }
+    }
+
+    /// Whether the header says this is a Game Gear game, None without a header
+    pub fn is_game_gear(&self) -> Option<bool> {
+        self.header.as_ref().map(|header| header.region.is_game_gear())
+    }
+}
+
+fn parse_header(rom: &[u8], offset: usize) -> RomHeader {
+    let header = &rom[offset..offset + HEADER_SIZE];
+    let bcd = |value: u8| (value >> 4) as u32 * 10 + (value & 0x0f) as u32;
+
+    let declared_size = match header[0xf] & 0x0f {
+        0xa => Some(0x2000),
+        0xb => Some(0x4000),
+        0xc => Some(0x8000),
+        0xd => Some(0xc000),
+        0xe => Some(0x10000),
+        0xf => Some(0x20000),
+        0x0 => Some(0x40000),
+        0x1 => Some(0x80000),
+        0x2 => Some(0x100000),
+        _ => None,
+    };
+    let checksum = u16::from_le_bytes([header[0xa], header[0xb]]);
+
+    RomHeader {
+        offset,
+        checksum,
+        // Two BCD bytes, the upper nibble of the third one adds the ten thousands
+        product_code: (header[0xe] >> 4) as u32 * 10000 + bcd(header[0xd]) * 100 + bcd(header[0xc]),
+        version: header[0xe] & 0x0f,
+        region: Region::from_code(header[0xf] >> 4),
+        declared_size,
+        checksum_valid: declared_size.and_then(|size| compute_checksum(rom, size)) == Some(checksum),
+    }
+}
+
+// Sums the declared size, leaving out the header area at 0x7ff0 - 0x7fff
+fn compute_checksum(rom: &[u8], size: usize) -> Option<u16> {
+    let rom = rom.get(..size)?;
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
+    let header_start = size.min(HEADER_BANK_END) - HEADER_SIZE;
+    let tail = rom.get(HEADER_BANK_END..).unwrap_or_default();
+    Some(sum(&rom[..header_start]).wrapping_add(sum(tail)))
+}
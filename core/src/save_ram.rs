@@ -0,0 +1,64 @@
+// Battery backed RAM comes in 8, 16 or 32 KB on SMS/GG carts
+const MIN_SIZE: usize = 0x2000;
+
+/// Battery backed cartridge RAM. It only grows to the part the game actually uses (rounded up to 8, 16 or 32 KB),
+/// which is what ends up in the .sav file, and remembers whether it changed since it was last saved.
+pub struct SaveRam {
+    buffer: Vec<u8>,
+    size: usize,
+    dirty: bool,
+}
+
+impl SaveRam {
+    /// `capacity` is the most the mapper can address, the buffer mirrors beyond it
+    pub(crate) fn new(capacity: usize) -> SaveRam {
+        SaveRam {
+            buffer: vec![0; capacity],
+            size: 0,
+            dirty: false,
+        }
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.buffer[offset % self.buffer.len()]
+    }
+
+    pub fn write(&mut self, offset: usize, value: u8) {
+        let offset = offset % self.buffer.len();
+        if self.buffer[offset] == value {
+            return;
+        }
+
+        self.buffer[offset] = value;
+        self.size = self.size.max(round_up(offset + 1)).min(self.buffer.len());
+        self.dirty = true;
+    }
+
+    /// Restores the contents of a .sav file, anything beyond the capacity is dropped
+    pub fn load(&mut self, data: &[u8]) {
+        let length = data.len().min(self.buffer.len());
+        self.buffer[..length].copy_from_slice(&data[..length]);
+        self.size = round_up(length).min(self.buffer.len());
+        self.dirty = false;
+    }
+
+    /// The part of the RAM that is in use, empty if the game never touched it
+    pub fn contents(&self) -> &[u8] {
+        &self.buffer[..self.size]
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+    }
+}
+
+fn round_up(length: usize) -> usize {
+    match length {
+        0 => 0,
+        length => length.next_power_of_two().max(MIN_SIZE),
+    }
+}
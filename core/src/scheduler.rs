@@ -0,0 +1,44 @@
+// All deadlines are expressed in master clock cycles. The CPU runs at a third and the VDP at half
+// of the master clock, the PSG divides the CPU clock by 16 internally.
+pub(crate) const MASTER_CLOCK_HZ: usize = 10_738_635;
+pub(crate) const CPU_CLOCK_DIVIDER: usize = 3;
+pub(crate) const VDP_CLOCK_DIVIDER: usize = 2;
+pub(crate) const PSG_CLOCK_DIVIDER: usize = CPU_CLOCK_DIVIDER * 16;
+pub(crate) const PSG_SAMPLE_RATE: usize = 44_100;
+pub(crate) const PSG_SAMPLE_PERIOD: usize = MASTER_CLOCK_HZ / PSG_SAMPLE_RATE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Event {
+    LineIrq,
+    HCounterLatch,
+    PsgSample,
+}
+
+pub(crate) struct Scheduler {
+    // Sorted by deadline in descending order, the next event due is always the last one
+    events: Vec<(usize, Event)>,
+}
+
+impl Scheduler {
+    pub(crate) fn new() -> Scheduler {
+        Scheduler { events: Vec::new() }
+    }
+
+    pub(crate) fn schedule(&mut self, deadline: usize, event: Event) {
+        // Events sharing a deadline are dispatched in the order they were scheduled
+        let idx = self.events.partition_point(|(pending, _)| *pending > deadline);
+        self.events.insert(idx, (deadline, event));
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<usize> {
+        self.events.last().map(|(deadline, _)| *deadline)
+    }
+
+    pub(crate) fn pop_due(&mut self, clock: usize) -> Option<(usize, Event)> {
+        if self.next_deadline()? <= clock {
+            self.events.pop()
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use z80::instruction::{Immediate, Instruction, Opcode};
+
+use crate::bus::Bus;
+
+const BANK_SIZE: usize = 0x4000;
+const BIOS_SIZE: u16 = 0x0400;
+const RAM_START: u16 = 0xc000;
+const RAM_MASK: u16 = 0x1fff; // 0xe000 - 0xffff mirrors the work RAM
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SymbolFormat {
+    WlaDx,   // .sym
+    SdccNoi, // .noi
+    SdccMap, // .map
+    Mesen,   // .mlb
+}
+
+impl SymbolFormat {
+    pub fn from_path(path: &str) -> Option<SymbolFormat> {
+        let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "sym" => Some(SymbolFormat::WlaDx),
+            "noi" => Some(SymbolFormat::SdccNoi),
+            "map" => Some(SymbolFormat::SdccMap),
+            "mlb" => Some(SymbolFormat::Mesen),
+            _ => None,
+        }
+    }
+}
+
+// Where a label lives, ROM labels are keyed by real offset so they follow their bank through every slot
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+enum SymbolKey {
+    Bios(u16),
+    Rom(usize),
+    Ram(u16),
+}
+
+impl SymbolKey {
+    // The region a label+offset may span: the BIOS, a single ROM bank or the work RAM
+    fn region(&self) -> (u8, usize) {
+        match self {
+            SymbolKey::Bios(_) => (0, 0),
+            SymbolKey::Rom(offset) => (1, offset / BANK_SIZE),
+            SymbolKey::Ram(_) => (2, 0),
+        }
+    }
+
+    fn distance(&self, other: &SymbolKey) -> usize {
+        match (self, other) {
+            (SymbolKey::Bios(a), SymbolKey::Bios(b)) | (SymbolKey::Ram(a), SymbolKey::Ram(b)) => (*a - *b) as usize,
+            (SymbolKey::Rom(a), SymbolKey::Rom(b)) => a - b,
+            _ => unreachable!("Labels are only looked up within their region"),
+        }
+    }
+}
+
+/// Labels loaded from WLA-DX, SDCC or Mesen symbol files
+#[derive(Default)]
+pub struct SymbolTable {
+    labels: BTreeMap<SymbolKey, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Adds the labels of a symbol file, returns how many were found
+    pub fn load(&mut self, format: SymbolFormat, text: &str) -> usize {
+        let before = self.labels.len();
+        let mut in_labels_section = true; // Old WLA-DX versions write the labels without a section header
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if format == SymbolFormat::WlaDx && line.starts_with('[') {
+                in_labels_section = line == "[labels]";
+                continue;
+            }
+            if !in_labels_section {
+                continue;
+            }
+
+            let symbol = match format {
+                SymbolFormat::WlaDx => parse_wla_dx(line),
+                SymbolFormat::SdccNoi => parse_sdcc_noi(line),
+                SymbolFormat::SdccMap => parse_sdcc_map(line),
+                SymbolFormat::Mesen => parse_mesen(line),
+            };
+
+            if let Some((key, name)) = symbol {
+                self.labels.entry(key).or_insert(name);
+            }
+        }
+
+        self.labels.len() - before
+    }
+
+    /// Label at exactly this CPU address, with the banks currently paged in
+    pub fn label(&self, bus: &Bus, address: u16) -> Option<&str> {
+        self.labels.get(&key_for(bus, address)?).map(String::as_str)
+    }
+
+    /// `label` or `label+#offset` for the closest label at or below a CPU address in the same bank
+    pub fn symbolize(&self, bus: &Bus, address: u16) -> Option<String> {
+        let key = key_for(bus, address)?;
+        let (found, name) = self.labels.range((Bound::Unbounded, Bound::Included(key))).next_back()?;
+        if found.region() != key.region() {
+            return None;
+        }
+
+        match key.distance(found) {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+#{:02x}", name, offset)),
+        }
+    }
+
+    /// The instruction's text with 16-bit operands and branch targets replaced by labels
+    pub fn format_instruction(&self, bus: &Bus, address: u16, instruction: &Instruction) -> String {
+        let text = instruction.opcode.to_string();
+
+        let displacement = match instruction.opcode {
+            Opcode::JumpRelative(_, Immediate::S8(displacement), _) => Some(displacement),
+            Opcode::DecrementAndJumpRelative(Immediate::S8(displacement), _) => Some(displacement),
+            _ => None,
+        };
+        if let Some(displacement) = displacement {
+            let target = address
+                .wrapping_add(instruction.length as u16)
+                .wrapping_add(displacement as u16);
+            return match (self.symbolize(bus, target), text.rfind(' ')) {
+                (Some(label), Some(idx)) => format!("{}{}", &text[..=idx], label),
+                _ => text,
+            };
+        }
+
+        // Only 16-bit immediates print with four hex digits
+        let mut result = String::new();
+        let mut rest = text.as_str();
+        while let Some(idx) = rest.find('#') {
+            result.push_str(&rest[..idx]);
+            let digits = rest[idx + 1..].chars().take_while(|c| c.is_ascii_hexdigit()).count();
+            let value = (digits == 4).then(|| u16::from_str_radix(&rest[idx + 1..idx + 5], 16).unwrap());
+
+            match value.and_then(|value| self.symbolize(bus, value)) {
+                Some(label) => result.push_str(&label),
+                None => result.push_str(&rest[idx..idx + 1 + digits]),
+            }
+            rest = &rest[idx + 1 + digits..];
+        }
+        result.push_str(rest);
+
+        result
+    }
+}
+
+// None for slots without a ROM bank, like open bus or cartridge RAM
+fn key_for(bus: &Bus, address: u16) -> Option<SymbolKey> {
+    if bus.bios_enabled() && address < BIOS_SIZE {
+        Some(SymbolKey::Bios(address))
+    } else if address >= RAM_START {
+        Some(SymbolKey::Ram(RAM_START | (address & RAM_MASK)))
+    } else {
+        bus.translate_address_to_real(address).ok().map(SymbolKey::Rom)
+    }
+}
+
+// Labels given as a bank and the CPU address that bank is accessed through
+fn banked_key(bank: usize, address: u16) -> SymbolKey {
+    if address >= RAM_START {
+        SymbolKey::Ram(RAM_START | (address & RAM_MASK))
+    } else {
+        SymbolKey::Rom(bank * BANK_SIZE + address as usize % BANK_SIZE)
+    }
+}
+
+fn parse_hex(value: &str) -> Option<usize> {
+    let value = value.trim_start_matches("0x").trim_start_matches('$');
+    usize::from_str_radix(value, 16).ok()
+}
+
+// SDCC reports the start and length of every area as symbols, those aren't labels
+fn is_sdcc_area_symbol(name: &str) -> bool {
+    name.starts_with("l__") || name.starts_with("s__")
+}
+
+// SDCC puts the bank of banked code into the bits above the 16-bit address, unbanked code sees the power-on mapping
+fn sdcc_key(value: usize) -> SymbolKey {
+    let address = value as u16;
+    match value >> 16 {
+        0 => banked_key(address as usize / BANK_SIZE, address),
+        bank => banked_key(bank, address),
+    }
+}
+
+// `bb:aaaa name` in the [labels] section
+fn parse_wla_dx(line: &str) -> Option<(SymbolKey, String)> {
+    let (location, name) = line.split_once(char::is_whitespace)?;
+    let (bank, address) = location.split_once(':')?;
+    let key = banked_key(parse_hex(bank)?, parse_hex(address)? as u16);
+    Some((key, name.trim().to_string()))
+}
+
+// `DEF name 0xaddress`
+fn parse_sdcc_noi(line: &str) -> Option<(SymbolKey, String)> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next()? != "DEF" {
+        return None;
+    }
+
+    let name = tokens.next()?;
+    let value = parse_hex(tokens.next()?)?;
+    (!is_sdcc_area_symbol(name)).then(|| (sdcc_key(value), name.to_string()))
+}
+
+// Symbol lines of the area listing: `[C:]   00000200  _main   module`
+fn parse_sdcc_map(line: &str) -> Option<(SymbolKey, String)> {
+    let mut tokens = line.split_whitespace().peekable();
+    if tokens.peek()?.ends_with(':') {
+        tokens.next();
+    }
+
+    let value = tokens.next()?;
+    let name = tokens.next()?;
+    let is_identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.');
+    if value.len() < 4 || !is_identifier || is_sdcc_area_symbol(name) {
+        return None;
+    }
+
+    Some((sdcc_key(parse_hex(value)?), name.to_string()))
+}
+
+// `type:offset[-end]:name[:comment]`, offsets are relative to the memory type
+fn parse_mesen(line: &str) -> Option<(SymbolKey, String)> {
+    let mut fields = line.splitn(4, ':');
+    let memory_type = fields.next()?;
+    let offset = parse_hex(fields.next()?.split('-').next()?)?;
+    let name = fields.next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let key = match memory_type {
+        "SmsPrgRom" | "P" => SymbolKey::Rom(offset),
+        "SmsWorkRam" | "R" => SymbolKey::Ram(RAM_START | (offset as u16 & RAM_MASK)),
+        "SmsBootRom" => SymbolKey::Bios(offset as u16),
+        _ => return None,
+    };
+    Some((key, name.to_string()))
+}
@@ -1,15 +1,20 @@
 use std::rc::Rc;
 
-use log::error;
+use log::{error, info, warn};
+use z80::cpu::Cpu;
+use z80::disassembler::DecodeError;
 use z80::instruction::Instruction;
 
-use crate::bus::{Bus, Passthrough};
-use crate::cpu::Cpu;
+use crate::bus::{Bus, Media, Passthrough};
 use crate::error::GgError;
+use crate::game_db::{GameDatabase, GameEntry};
 
 use crate::lua_engine::{HookType, LuaEngine};
-use crate::mapper::SegaMapper;
+use crate::machine::Machine;
+use crate::mapper::{self, SegaMapper, SEGA_SRAM_CAPACITY};
 use crate::psg::Psg;
+use crate::rom_info::RomInfo;
+use crate::scheduler::{Event, Scheduler, CPU_CLOCK_DIVIDER, PSG_SAMPLE_PERIOD};
 use crate::vdp::{Color, Mode, Vdp};
 
 pub struct SystemState {
@@ -22,31 +27,42 @@ pub struct System {
     pub bus: Bus,
     pub vdp: Vdp,
     pub psg: Psg,
+    pub game_database: GameDatabase, // Consulted when loading a cartridge, add override files before that
     lua: Rc<LuaEngine>,
     abort_invalid_io_op: bool,
-    clocks: usize,
-    master_clock: usize,
+    scheduler: Scheduler,
+    emulate_sms: bool,
+    rom_info: RomInfo,
+    game: Option<GameEntry>,
 }
 
 impl System {
     pub fn new(lua_script: Option<String>, emulate_sms: bool) -> System {
-        // todo: figure out mapper
-        let mapper = SegaMapper::new(0);
+        // Replaced by the detected mapper once a cartridge is loaded
+        let mapper = SegaMapper::new(0, Some(SEGA_SRAM_CAPACITY));
         let mut bus = Bus::new(mapper);
         let mode = if emulate_sms { Mode::SegaMasterSystem } else { Mode::GameGear };
         let lua = Rc::new(LuaEngine::new(lua_script));
 
         bus.powerup_reset_banks().unwrap();
 
+        let vdp = Vdp::new(mode, Rc::clone(&lua));
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(vdp.next_line_clock(), Event::LineIrq);
+        scheduler.schedule(PSG_SAMPLE_PERIOD, Event::PsgSample);
+
         System {
             cpu: Cpu::new(),
             bus,
-            vdp: Vdp::new(mode, Rc::clone(&lua)),
+            vdp,
             psg: Psg::new(),
+            game_database: GameDatabase::new(),
             lua,
             abort_invalid_io_op: true,
-            clocks: 0,
-            master_clock: 0,
+            scheduler,
+            emulate_sms,
+            rom_info: RomInfo::parse(&[]),
+            game: None,
         }
     }
 
@@ -58,31 +74,87 @@ impl System {
     pub fn load_bios(&mut self, data: &[u8]) {
         let previous_value = self.enable_bios();
         self.load_rom(Passthrough::Bios, data);
-        self.bus.bios_enabled = previous_value;
+        self.bus.set_bios_enabled(previous_value);
     }
 
     pub fn load_cartridge(&mut self, data: &[u8]) {
-        self.bus.rom.resize(data.len());
+        self.rom_info = RomInfo::parse(data);
+        match &self.rom_info.header {
+            Some(header) if !header.checksum_valid => warn!("Cartridge checksum {:04x} doesn't match", header.checksum),
+            Some(_) => {}
+            None => info!("Cartridge has no header"),
+        }
+
+        // Known games override the header and the heuristics, their entries exist because those get it wrong
+        self.game = self.game_database.find(self.rom_info.crc32).cloned();
+        let mapper = match &self.game {
+            Some(game) => {
+                info!("Found {} in the game database", game.title);
+                if !game.peripherals.is_empty() {
+                    warn!("Game needs peripherals that aren't emulated: {:?}", game.peripherals);
+                }
+                self.emulate_sms = game.system.mode() == Mode::SegaMasterSystem;
+                self.vdp.set_mode(game.system.mode());
+                game.mapper_type()
+            }
+            None => mapper::detect(data),
+        };
+        info!("Detected mapper: {:?}", mapper);
+        self.bus.set_mapper(mapper.create(data.len()));
 
         let previous_value = self.disable_bios();
         self.load_rom(Passthrough::Rom, data);
-        self.bus.bios_enabled = previous_value;
+        self.bus.set_bios_enabled(previous_value);
+    }
+
+    /// Inserts a Sega Card or expansion port image, the BIOS or the game enable its slot through port $3E
+    pub fn load_media(&mut self, media: Media, data: &[u8]) {
+        info!("Inserted {:?} ({} KB)", media, data.len() / 1024);
+        self.bus.insert_media(media, data);
+    }
+
+    /// Restores the battery backed RAM from a .sav file, the cartridge has to be loaded first
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        match self.bus.rom.save_ram_mut() {
+            Some(ram) => ram.load(data),
+            None => warn!("Cartridge has no battery backed RAM, ignoring save file"),
+        }
+    }
+
+    /// The battery backed RAM if it changed since the last call, for writing it back to the .sav file
+    pub fn take_dirty_save_ram(&mut self) -> Option<Vec<u8>> {
+        let ram = self.bus.rom.save_ram_mut()?;
+        if !ram.is_dirty() {
+            return None;
+        }
+
+        ram.mark_clean();
+        Some(ram.contents().to_vec())
+    }
+
+    pub fn rom_info(&self) -> &RomInfo {
+        &self.rom_info
+    }
+
+    /// The game database entry of the loaded cartridge
+    pub fn game(&self) -> Option<&GameEntry> {
+        self.game.as_ref()
     }
 
     pub fn disable_bios(&mut self) -> bool {
-        let previous_value = self.bus.bios_enabled;
-        self.bus.bios_enabled = false;
+        let previous_value = self.bus.bios_enabled();
+        self.bus.set_bios_enabled(false);
         previous_value
     }
 
     pub fn enable_bios(&mut self) -> bool {
-        let previous_value = self.bus.bios_enabled;
-        self.bus.bios_enabled = true;
+        let previous_value = self.bus.bios_enabled();
+        self.bus.set_bios_enabled(true);
         previous_value
     }
 
-    pub fn decode_instr_at_pc(&mut self) -> Result<Instruction, String> {
-        self.cpu.decode_at_pc(&mut self.bus)
+    pub fn decode_instr_at_pc(&mut self) -> Result<Instruction, DecodeError> {
+        self.bus.decode(self.cpu.registers.pc)
     }
 
     pub fn tick(&mut self) -> Result<SystemState, GgError> {
@@ -94,56 +166,61 @@ impl System {
             self.lua.execute_hook(current_pc_before_tick, HookType::CpuExec);
         }
 
-        if self.vdp.is_hblank() {
-            self.clocks = 0;
+        // The Pause button of the Master System is connected to the NMI line
+        if self.emulate_sms {
+            self.cpu.set_nmi_line(self.bus.joysticks[0].pause);
         }
 
         // Process tick for all components
         let mut repeat_not_fulfilled = false;
 
-        if self.master_clock % 3 == 0 {
-            let result = self.cpu.tick(&mut self.bus, &mut self.vdp, &mut self.psg);
-            match result {
-                Err(GgError::IoRequestNotFulfilled) => (),
-                Err(GgError::JumpNotTaken) => (),
-                Err(GgError::CpuHalted) => (),
-                Err(GgError::RepeatNotFulfilled) => repeat_not_fulfilled = true,
-                Err(GgError::IoControllerInvalidPort) | Err(GgError::VdpInvalidIoMode) => {
-                    if self.abort_invalid_io_op {
-                        error!("Identified I/O error at address: {:04x}", self.cpu.registers.pc);
-                        if self.cpu.registers.pc < 0xc000 {
-                            error!(
-                                "Real address in ROM: {:08x}",
-                                self.bus.translate_address_to_real(self.cpu.registers.pc).unwrap()
-                            );
-                        }
-                        return Err(result.err().unwrap());
+        let mut machine = Machine::new(&mut self.bus, &mut self.vdp, &mut self.psg);
+        let result = self.cpu.tick(&mut machine).map_err(GgError::from);
+        let h_counter_latch = machine.h_counter_latch();
+        match result {
+            Err(GgError::IoRequestNotFulfilled) => (),
+            Err(GgError::JumpNotTaken) => (),
+            Err(GgError::RepeatNotFulfilled) => repeat_not_fulfilled = true,
+            Err(GgError::IoControllerInvalidPort) | Err(GgError::VdpInvalidIoMode) => {
+                if self.abort_invalid_io_op {
+                    error!("Identified I/O error at address: {:04x}", self.cpu.registers.pc);
+                    if let Ok(real_address) = self.bus.translate_address_to_real(self.cpu.registers.pc) {
+                        error!("Real address in ROM: {:08x}", real_address);
                     }
+                    return Err(result.err().unwrap());
                 }
-                Err(e) => {
-                    error!("Identified error at address: {:04x}", self.cpu.registers.pc);
-                    if self.cpu.registers.pc < 0xc000 {
-                        error!(
-                            "Real address in ROM: {:08x}",
-                            self.bus.translate_address_to_real(self.cpu.registers.pc).unwrap()
-                        );
-                    }
-                    return Err(e);
+            }
+            Err(e) => {
+                error!("Identified error at address: {:04x}", self.cpu.registers.pc);
+                if let Ok(real_address) = self.bus.translate_address_to_real(self.cpu.registers.pc) {
+                    error!("Real address in ROM: {:08x}", real_address);
                 }
-                _ => (),
-            };
-        }
-        let mut frame_generated = false;
-        if self.master_clock % 2 == 0 {
-            frame_generated = self.vdp.tick();
+                return Err(e);
+            }
+            _ => (),
+        };
+
+        // The CPU runs ahead of the VDP and PSG, they are caught up once an event is due or the CPU accesses them
+        let master_clock = self.cpu.cycles * CPU_CLOCK_DIVIDER;
+        if let Some(deadline) = h_counter_latch {
+            self.scheduler.schedule(deadline, Event::HCounterLatch);
         }
-        self.psg.tick();
 
-        self.master_clock += 1;
+        while let Some((deadline, event)) = self.scheduler.pop_due(master_clock) {
+            self.vdp.catch_up(deadline);
+            self.psg.catch_up(deadline);
+
+            match event {
+                Event::LineIrq => self.scheduler.schedule(self.vdp.next_line_clock(), Event::LineIrq),
+                Event::HCounterLatch => self.vdp.latch_h_counter(),
+                // No audio output yet, sample boundaries only keep the PSG in sync
+                Event::PsgSample => self.scheduler.schedule(deadline + PSG_SAMPLE_PERIOD, Event::PsgSample),
+            }
+        }
 
         // Let the caller know if we reached VBlank to cause a redraw
         Ok(SystemState {
-            frame_ready: frame_generated,
+            frame_ready: self.vdp.frame_ready(),
             repeat_not_fulfilled,
         })
     }
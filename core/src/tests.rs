@@ -1,9 +1,24 @@
 #[cfg(test)]
 mod tests {
-    use crate::bus::{Passthrough, RomWriteProtection};
-    use crate::cpu::Flags;
+    use crate::bus::{self, BankSelect, Media, Passthrough, RamFill, RomWriteProtection};
+    use crate::error::GgError;
+    use crate::game_db::{GameDatabase, GameRegion, GameSystem, Peripheral, SaveType};
+    use crate::io::{Controller, PortDevice};
+    use crate::machine::Machine;
+    use crate::mapper::{self, CodemastersMapper, MapperType, Page};
+    use crate::rom_disassembler::RomDisassembler;
+    use crate::rom_info::{Region, RomInfo};
+    use crate::symbols::{SymbolFormat, SymbolTable};
     use crate::system::System;
+    use crate::vdp;
+    use crate::zex;
     use serde_json::Value;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use z80::assembler::Assembler;
+    use z80::cpu::{Flags, InterruptMode};
+    use z80::disassembler::Disassembler;
     use z80::instruction::Reg16;
 
     fn is_ignore(_path: &std::path::Path) -> bool {
@@ -18,216 +33,1128 @@ mod tests {
         let json: Value = serde_json::from_str(&input).unwrap();
         let tests = json.as_array().unwrap();
         for test in tests {
-            let test = test.as_object().unwrap();
-            let name = test.get("name").unwrap().as_str().unwrap();
-            // println!("Running test: {}", name);
-
-            let initial = test.get("initial").unwrap().as_object().unwrap();
-            let final_ = test.get("final").unwrap().as_object().unwrap();
-
-            let mut system = System::new(None, false);
-            system.disable_bios();
-            system.set_abort_on_io_operation_behavior(false);
-            system.bus.rom.resize(0xffff);
-            system.bus.set_rom_write_protection(RomWriteProtection::Allow);
-            system.bus.disable_bank_behavior(true);
-
-            system.cpu.registers.a = initial.get("a").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.b = initial.get("b").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.c = initial.get("c").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.d = initial.get("d").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.e = initial.get("e").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.h = initial.get("h").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.l = initial.get("l").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.i = initial.get("i").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.r = initial.get("r").unwrap().as_u64().unwrap() as u8;
-            system.cpu.registers.f = Flags::from_bits(initial.get("f").unwrap().as_u64().unwrap() as u8).unwrap();
-            system
-                .cpu
-                .set_register_u16(Reg16::AFShadow, initial.get("af_").unwrap().as_u64().unwrap() as u16);
-            system
-                .cpu
-                .set_register_u16(Reg16::BCShadow, initial.get("bc_").unwrap().as_u64().unwrap() as u16);
-            system
-                .cpu
-                .set_register_u16(Reg16::DEShadow, initial.get("de_").unwrap().as_u64().unwrap() as u16);
-            system
-                .cpu
-                .set_register_u16(Reg16::HLShadow, initial.get("hl_").unwrap().as_u64().unwrap() as u16);
-            system
-                .cpu
-                .set_register_u16(Reg16::IX(None), initial.get("ix").unwrap().as_u64().unwrap() as u16);
-            system
-                .cpu
-                .set_register_u16(Reg16::IY(None), initial.get("iy").unwrap().as_u64().unwrap() as u16);
-
-            system.cpu.registers.pc = initial.get("pc").unwrap().as_u64().unwrap() as u16;
-            system.cpu.registers.sp = initial.get("sp").unwrap().as_u64().unwrap() as u16;
-
-            system.cpu.registers.iff1 = initial.get("iff1").unwrap().as_u64().unwrap() != 0;
-            system.cpu.registers.iff2 = initial.get("iff2").unwrap().as_u64().unwrap() != 0;
-
-            let ram = initial.get("ram").unwrap().as_array().unwrap();
-            for value in ram {
-                let addr = value.as_array().unwrap()[0].as_u64().unwrap() as usize;
-                let value = value.as_array().unwrap()[1].as_u64().unwrap() as u8;
-
-                if addr < 0xc000 {
-                    system.bus.write_passthrough(&Passthrough::Rom, addr, value);
-                } else {
-                    system.bus.write_passthrough(&Passthrough::Ram, addr - 0xc000, value);
-                }
-            }
+            run_test(test.as_object().unwrap());
+        }
+    }
 
-            let decoded = system.decode_instr_at_pc().unwrap().opcode;
+    #[test]
+    fn test_zex_parse_line() {
+        // Console output of zexall.sms, a failing group prints its CRCs on the line after its name
+        let output = "Z80 Instruction Exerciser 0.18\nUndocumented flags version\nOutputs:\n* SDSC Debug Console\n\
+                      * TMS9918 Text Mode\n* SRAM\n\nld hl, (nnnn) OK\nld (<bc|de>), a OK\n<scf|ccf> \n\
+                      CRC 0aa774b1 expected c6ea3f85\nld (<ix|iy>+1), a OK\r\nTests complete\n";
 
-            match system.tick() {
-                Ok(_) => (),
-                Err(e) => panic!("{}", e),
-            }
+        let mut report = zex::ZexReport::default();
+        output.split(['\r', '\n']).for_each(|line| report.parse_line(line));
 
-            assert_eq!(
-                system.cpu.registers.a,
-                final_.get("a").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.b,
-                final_.get("b").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.c,
-                final_.get("c").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.d,
-                final_.get("d").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.e,
-                final_.get("e").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.h,
-                final_.get("h").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.l,
-                final_.get("l").unwrap().as_u64().unwrap() as u8,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::AFShadow),
-                final_.get("af_").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::BCShadow),
-                final_.get("bc_").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::DEShadow),
-                final_.get("de_").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::HLShadow),
-                final_.get("hl_").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::IX(None)),
-                final_.get("ix").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.get_register_u16(Reg16::IY(None)),
-                final_.get("iy").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
+        let groups: Vec<_> = report.groups.iter().map(|group| (group.name.as_str(), group.passed)).collect();
+        assert_eq!(
+            groups,
+            [
+                ("ld hl, (nnnn)", true),
+                ("ld (<bc|de>), a", true),
+                ("<scf|ccf>", false),
+                ("ld (<ix|iy>+1), a", true)
+            ]
+        );
+        assert!(report.complete);
+        assert!(!report.passed());
+    }
 
-            let mut final_f = Flags::from_bits(final_.get("f").unwrap().as_u64().unwrap() as u8).unwrap();
-            reset_undocumented_flags(&mut system.cpu.registers.f, &mut final_f);
+    // Each exerciser runs for several minutes, use `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_zexdoc() {
+        run_zex("../external/test_roms/zexdoc.sms");
+    }
 
-            assert_eq!(system.cpu.registers.f, final_f, "Testcase {} ({})", name, decoded);
+    #[test]
+    #[ignore]
+    fn test_zexall() {
+        run_zex("../external/test_roms/zexall.sms");
+    }
 
-            assert_eq!(
-                system.cpu.registers.pc,
-                final_.get("pc").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
-            );
-            assert_eq!(
-                system.cpu.registers.sp,
-                final_.get("sp").unwrap().as_u64().unwrap() as u16,
-                "Testcase {} ({})",
-                name,
-                decoded
+    // ZEXDOC completes after about 16.6 and ZEXALL after about 21.2 billion T-states, a CPU that got stuck has to fail
+    // the test instead of running forever
+    const ZEX_CYCLE_BUDGET: usize = 30_000_000_000;
+
+    fn run_zex(path: &str) {
+        let rom = std::fs::read(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        let report = zex::run(&rom, Some(ZEX_CYCLE_BUDGET)).unwrap();
+
+        assert!(
+            report.complete,
+            "{} did not complete within {} T-states, {} groups finished",
+            path,
+            ZEX_CYCLE_BUDGET,
+            report.groups.len()
+        );
+        let failed: Vec<_> = report.failed_groups().map(|group| group.name.as_str()).collect();
+        assert!(failed.is_empty(), "Failed groups: {:?}", failed);
+    }
+
+    // Block I/O and RETN vectors in the jsmoo format. They don't list their port traffic, so they run against
+    // the real devices and read from ports with a known power-on value: 0xdc (joystick, 0xff) and 0x7e (V counter, 0x00).
+    #[test]
+    fn test_block_io_and_retn() {
+        let json: Value = serde_json::from_str(BLOCK_IO_AND_RETN_TESTS).unwrap();
+        for test in json.as_array().unwrap() {
+            run_test(test.as_object().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_port_traffic_and_cycles() {
+        let json: Value = serde_json::from_str(PORT_TRAFFIC_TESTS).unwrap();
+        for test in json.as_array().unwrap() {
+            run_test(test.as_object().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let mut system = flat_system(true);
+
+        system.cpu.registers.sp = 0xdff0;
+        system.cpu.registers.iff1 = true;
+
+        // NMI handler at 0x0066 consists of NOPs like the rest of the ROM
+        system.bus.joysticks[0].set_pause(true);
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.pc, 0x0067);
+        assert!(!system.cpu.registers.iff1);
+        assert!(system.cpu.registers.iff2);
+        assert_eq!(system.bus.read_word(0xdfee).unwrap(), 0x0000);
+
+        // Holding the button doesn't request another NMI
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.pc, 0x0068);
+
+        system.bus.joysticks[0].set_pause(false);
+        system.tick().unwrap();
+        system.bus.joysticks[0].set_pause(true);
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.pc, 0x0067);
+    }
+
+    #[test]
+    fn test_halt_until_nmi() {
+        let mut system = flat_system(true);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x0010, 0x76);
+
+        system.cpu.registers.pc = 0x0010;
+        system.cpu.registers.sp = 0xdff0;
+
+        system.tick().unwrap();
+        assert!(system.cpu.halted);
+        assert_eq!(system.cpu.registers.pc, 0x0010);
+
+        // Every internal NOP takes 4 T-states and refreshes R
+        let (cycles, r) = (system.cpu.cycles, system.cpu.registers.r);
+        system.tick().unwrap();
+        system.tick().unwrap();
+        assert!(system.cpu.halted);
+        assert_eq!(system.cpu.registers.pc, 0x0010);
+        assert_eq!(system.cpu.cycles, cycles + 8);
+        assert_eq!(system.cpu.registers.r, r + 2);
+
+        system.bus.joysticks[0].set_pause(true);
+        system.tick().unwrap();
+        assert!(!system.cpu.halted);
+        assert_eq!(system.cpu.registers.pc, 0x0067);
+        assert_eq!(system.bus.read_word(0xdfee).unwrap(), 0x0011);
+    }
+
+    #[test]
+    fn test_io_errors_move_on() {
+        let mut system = flat_system(false);
+        system.set_abort_on_io_operation_behavior(false);
+
+        // in a,(0x01) without a Gear-to-Gear byte, then in a,(0x20) from an unassigned port
+        for (offset, byte) in [0xdb, 0x01, 0xdb, 0x20].into_iter().enumerate() {
+            system.bus.write_passthrough(&Passthrough::Rom, 0x0010 + offset, byte);
+        }
+        system.cpu.registers.pc = 0x0010;
+
+        let cycles = system.cpu.cycles;
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.pc, 0x0012);
+        assert_eq!(system.cpu.cycles, cycles + 11);
+
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.pc, 0x0014);
+        assert_eq!(system.cpu.cycles, cycles + 22);
+    }
+
+    #[test]
+    fn test_io_catches_up_vdp() {
+        let mut system = flat_system(false);
+
+        // in a,(0x7e) with the CPU 10 and a half lines ahead of the VDP
+        system.bus.write_passthrough(&Passthrough::Rom, 0x0010, 0xdb);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x0011, 0x7e);
+        system.cpu.registers.pc = 0x0010;
+        system.cpu.cycles = 10 * vdp::DOTS_PER_LINE * 2 / 3 + 100;
+
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 10);
+
+        // The port is read 7 T-states into the instruction, which is already on the next line
+        system.cpu.registers.pc = 0x0010;
+        system.cpu.cycles = 11 * vdp::DOTS_PER_LINE * 2 / 3 - 3;
+
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 11);
+    }
+
+    #[test]
+    fn test_page_table() {
+        let mut system = System::new(None, false);
+        system.bus.rom.resize(0x20000);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x0000, 0xa0);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x0400, 0xa1);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x1c400, 0xb7);
+        system.bus.write_passthrough(&Passthrough::Bios, 0x0000, 0xbb);
+
+        // The first KB is the BIOS until it's disabled, afterwards it's fixed to bank 0 no matter the mapping
+        assert_eq!(system.bus.read(0x0000).unwrap(), 0xbb);
+        assert!(system.bus.translate_address_to_real(0x0000).is_err());
+        system.disable_bios();
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_0, 7).unwrap();
+        assert_eq!(system.bus.read(0x0000).unwrap(), 0xa0);
+        assert_eq!(system.bus.read(0x0400).unwrap(), 0xb7);
+        assert_eq!(system.bus.translate_address_to_real(0x0400).unwrap(), 0x1c400);
+        assert_eq!(system.bus.fetch_bank(BankSelect::Bank0), 7);
+
+        // Bank registers wrap around the ROM size and still read back from work RAM
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 9).unwrap();
+        assert_eq!(system.bus.fetch_bank(BankSelect::Bank2), 1);
+        assert_eq!(system.bus.read(bus::MEMORY_REGISTER_CR_BANK_SELECT_2).unwrap(), 9);
+
+        // SRAM in slot 2, the second SRAM bank selected through bit 2
+        system.bus.write(0xfffc, 0b0000_1100).unwrap();
+        assert!(system.bus.is_sram_bank_active());
+        system.bus.write(0x8001, 0x55).unwrap();
+        assert_eq!(system.bus.peek_cartridge_ram(0x4001), 0x55);
+        // Only ROM banks have a ROM address
+        assert!(system.bus.translate_address_to_real(0x8001).is_err());
+        assert!(system.bus.translate_address_to_real(0xc000).is_err());
+        system.bus.write(0xfffc, 0).unwrap();
+        assert_eq!(system.bus.read(0x8001).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn test_memory_control() {
+        let mut system = System::new(None, false);
+        system.load_cartridge(&vec![0x11u8; 0x8000]);
+        system.load_media(Media::Card, &vec![0x22u8; 0x8000]);
+        system.load_media(Media::Expansion, &vec![0x33u8; 0x8000]);
+        system.bus.write_passthrough(&Passthrough::Bios, 0x0000, 0xbb);
+        system.bus.write(0xc000, 0x44).unwrap();
+
+        // What the BIOS writes before jumping into a cartridge, card or expansion game
+        let read = |system: &mut System, control: u8, address: u16| {
+            system.bus.write_io(bus::MEMORY_CONTROL_PORT, control).unwrap();
+            system.bus.read(address).unwrap()
+        };
+        assert_eq!(read(&mut system, 0xe3, 0x0000), 0xbb);
+        assert_eq!(read(&mut system, 0xe3, 0x4000), 0xff);
+        assert_eq!(read(&mut system, 0xab, 0x0000), 0x11);
+        assert_eq!(read(&mut system, 0xcb, 0x0000), 0x22);
+        assert_eq!(read(&mut system, 0x6b, 0x4000), 0x33);
+        assert!(!system.bus.bios_enabled());
+
+        // Disabled work RAM floats and ignores writes, bank registers still reach the mapper
+        assert_eq!(read(&mut system, 0xfb, 0xc000), 0xff);
+        system.bus.write(0xc000, 0x55).unwrap();
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 1).unwrap();
+        assert_eq!(read(&mut system, 0xab, 0xc000), 0x44);
+        assert_eq!(system.bus.fetch_bank(BankSelect::Bank2), 1);
+    }
+
+    #[test]
+    fn test_work_ram() {
+        let mut system = System::new(None, false);
+        system.load_cartridge(&vec![0u8; 0x20000]);
+        assert_eq!(system.bus.ram.buffer.len(), 0x2000);
+
+        // 8 KB mirrored at 0xe000, bank register writes land in RAM as well
+        system.bus.write(0xc123, 0x42).unwrap();
+        assert_eq!(system.bus.read(0xe123).unwrap(), 0x42);
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 5).unwrap();
+        assert_eq!(system.bus.read(0xdfff).unwrap(), 5);
+        assert_eq!(system.bus.fetch_bank(BankSelect::Bank2), 5);
+
+        // Code written through the mirror is decoded again
+        system.bus.write(0xc000, 0x00).unwrap();
+        assert_eq!(system.bus.decode(0xc000).unwrap().opcode.to_string(), "nop");
+        system.bus.write(0xe000, 0xc9).unwrap();
+        assert_eq!(system.bus.decode(0xc000).unwrap().opcode.to_string(), "ret");
+
+        system.bus.fill_ram(RamFill::Ones);
+        assert!(system.bus.ram.buffer.iter().all(|&byte| byte == 0xff));
+        assert_eq!(system.bus.decode(0xc000).unwrap().opcode.to_string(), "rst #38");
+        system.bus.fill_ram(RamFill::Seed(1234));
+        let seeded = system.bus.ram.buffer.clone();
+        system.bus.fill_ram(RamFill::Seed(1234));
+        assert_eq!(system.bus.ram.buffer, seeded);
+        assert!(seeded.iter().any(|&byte| byte != seeded[0]));
+        assert_eq!("random".parse(), Ok(RamFill::Random));
+        assert_eq!("42".parse(), Ok(RamFill::Seed(42)));
+        assert!("nope".parse::<RamFill>().is_err());
+    }
+
+    #[test]
+    fn test_peek_poke() {
+        let mut system = System::new(None, false);
+        system.bus.set_mapper(MapperType::SegaEeprom.create(0x20000));
+        system.bus.set_rom_write_protection(RomWriteProtection::Abort);
+        system.disable_bios();
+
+        // Pokes go around the write protection and the mapper registers but still reach the decode cache
+        system.bus.poke(0x0100, 0xc9);
+        assert_eq!(system.bus.decode(0x0100).unwrap().opcode.to_string(), "ret");
+        system.bus.poke(0x0100, 0x00);
+        assert_eq!(system.bus.decode(0x0100).unwrap().opcode.to_string(), "nop");
+        system.bus.poke(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 7);
+        assert_eq!(system.bus.peek(bus::MEMORY_REGISTER_CR_BANK_SELECT_2), 7);
+        assert_eq!(system.bus.fetch_bank(BankSelect::Bank2), 2);
+
+        // Looking at the EEPROM's serial port doesn't clock it
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        system.bus.poke(0x8000, 0b110);
+        assert_eq!(system.bus.peek(0x8000), 0b011);
+        assert_eq!(system.bus.peek_cartridge_ram(0), 0xff);
+
+        // Peeking the data port neither advances the address nor refills the buffer
+        system.vdp.poke_vram(0x0000, 0x12);
+        system.vdp.poke_vram(0x4001, 0x34);
+        system.vdp.write_io(0xbf, 0x00).unwrap();
+        system.vdp.write_io(0xbf, 0x00).unwrap();
+        assert_eq!(system.vdp.peek_io(0xbe).unwrap(), 0x12);
+        assert_eq!(system.vdp.peek_io(0xbe).unwrap(), 0x12);
+        assert_eq!(system.vdp.registers.address, 0x0001);
+        assert_eq!(system.vdp.read_io(0xbe).unwrap(), 0x12);
+        assert_eq!(system.vdp.peek_io(0xbe).unwrap(), 0x34);
+        system.vdp.poke_cram(0x41, 0x0f);
+        assert_eq!(system.vdp.peek_cram(0x01), 0x0f);
+
+        system.psg.poke_tone(1, 0x1fe);
+        system.psg.poke_volume(1, 0x0f);
+        assert_eq!((system.psg.peek_tone(1), system.psg.peek_volume(1)), (0x1fe, 0x0f));
+    }
+
+    #[test]
+    fn test_sprite_terminator() {
+        // The exercisers tell Mode 4 apart by stacking 5 sprites, which collide but don't overflow
+        let mut system = System::new(None, true);
+        system.vdp.registers.r5 = 0xff;
+        for idx in 0..5 {
+            system.vdp.poke_vram(0x3f00 + idx, 0x00);
+            system.vdp.poke_vram(0x3f80 + idx * 2, 0xd0);
+        }
+        system.vdp.poke_vram(0x3f05, 0xd0);
+
+        system.render();
+        assert_eq!(system.vdp.peek_io(0xbf).unwrap() & 0b0110_0000, 0b0010_0000);
+    }
+
+    #[test]
+    fn test_save_ram() {
+        let mut system = System::new(None, false);
+        system.load_cartridge(&vec![0u8; 0x20000]);
+        assert_eq!(system.take_dirty_save_ram(), None);
+
+        // Only the used part is saved, rounded up to 8/16/32 KB
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        system.bus.write(0x8010, 0x42).unwrap();
+        let save = system.take_dirty_save_ram().unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x10], 0x42);
+        assert_eq!(system.take_dirty_save_ram(), None);
+
+        // Writing the same value again isn't a change
+        system.bus.write(0x8010, 0x42).unwrap();
+        assert_eq!(system.take_dirty_save_ram(), None);
+
+        // Neither does it grow the save, zeros written to an untouched bank don't make it 32 KB
+        system.bus.write(0xfffc, 0b0000_1100).unwrap();
+        system.bus.write(0x8000, 0x00).unwrap();
+        assert_eq!(system.take_dirty_save_ram(), None);
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        system.bus.write(0x8010, 0x43).unwrap();
+        assert_eq!(system.take_dirty_save_ram().unwrap().len(), 0x2000);
+        system.bus.write(0xfffc, 0b0000_1100).unwrap();
+        system.bus.write(0x8000, 0x01).unwrap();
+        assert_eq!(system.take_dirty_save_ram().unwrap().len(), 0x8000);
+
+        // A reloaded cartridge starts from the .sav file
+        system.load_cartridge(&vec![0u8; 0x20000]);
+        system.load_save_ram(&save);
+        assert_eq!(system.take_dirty_save_ram(), None);
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        assert_eq!(system.bus.read(0x8010).unwrap(), 0x42);
+
+        // The game database knows which carts have SRAM and how much
+        let mut rom = vec![0u8; 0x20000];
+        rom[0x8010] = 0x99;
+        let crc = crc32fast::hash(&rom);
+        system.game_database.load(&format!("{:08x} gg usa sega - - No Save\n", crc));
+        system.load_cartridge(&rom);
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        assert!(!system.bus.is_sram_bank_active());
+        system.bus.write(0x8010, 0x42).unwrap();
+        assert_eq!(system.bus.read(0x8010).unwrap(), 0x99);
+        assert_eq!(system.take_dirty_save_ram(), None);
+
+        system
+            .game_database
+            .load(&format!("{:08x} gg usa sega sram - 8 KB Save\n", crc));
+        system.load_cartridge(&rom);
+        system.bus.write(0xfffc, 0b0000_1100).unwrap();
+        system.bus.write(0xbfff, 0x42).unwrap();
+        assert_eq!(system.take_dirty_save_ram().unwrap().len(), 0x2000);
+    }
+
+    #[test]
+    fn test_rom_info() {
+        assert_eq!(RomInfo::parse(&[0; 0x8000]).header, None);
+
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0000] = 0x12;
+        rom[0x8000] = 0x34;
+        rom[0xffff] = 0x01;
+        rom[0x7ff0..0x7ff8].copy_from_slice(b"TMR SEGA");
+        rom[0x7ff8] = 0xff; // Inside the header, not part of the checksum
+        rom[0x7ffa..0x7ffc].copy_from_slice(&0x0047u16.to_le_bytes());
+        rom[0x7ffc..0x7fff].copy_from_slice(&[0x02, 0x25, 0x21]);
+        rom[0x7fff] = 0x7e;
+
+        let info = RomInfo::parse(&rom);
+        assert_eq!(info.size, 0x10000);
+        assert_eq!(info.crc32, crc32fast::hash(&rom));
+        assert_eq!(info.is_game_gear(), Some(true));
+        let header = info.header.unwrap();
+        assert_eq!(header.offset, 0x7ff0);
+        assert_eq!(header.product_code, 22502);
+        assert_eq!(header.version, 1);
+        assert_eq!(header.region, Region::GgInternational);
+        assert_eq!(header.declared_size, Some(0x10000));
+        assert!(header.checksum_valid);
+
+        // Small SMS ROMs have their header at the end of the ROM, a size beyond the file can't be checked
+        let mut rom = vec![0u8; 0x2000];
+        rom[0x1ff0..0x1ff8].copy_from_slice(b"TMR SEGA");
+        rom[0x1fff] = 0x4c;
+        let header = RomInfo::parse(&rom).header.unwrap();
+        assert_eq!((header.offset, header.region), (0x1ff0, Region::SmsExport));
+        assert!(!header.checksum_valid);
+    }
+
+    #[test]
+    fn test_game_db() {
+        let mut database = GameDatabase::new();
+        assert_eq!(database.load(include_str!("game_db.txt")), 46);
+        let golf = database.find(0x5e53c7f7).unwrap();
+        assert_eq!(golf.title, "Ernie Els Golf (Europe) (En,Fr,De,Es,It)");
+        assert_eq!(
+            (golf.system, golf.region, golf.save),
+            (GameSystem::GameGear, GameRegion::Europe, SaveType::Sram(0x2000))
+        );
+        assert_eq!(golf.mapper_type(), MapperType::Codemasters { ram: true });
+        assert_eq!(database.find(0x578a8a38).unwrap().mapper_type(), MapperType::SegaEeprom);
+
+        // Overrides replace embedded entries and add new ones, broken lines are skipped
+        let rom = vec![0u8; 0x20000];
+        let crc = crc32fast::hash(&rom);
+        let mut system = System::new(None, false);
+        let text = format!(
+            "# comment\n{:08x} gg-sms world sega eeprom light-phaser,paddle Test Cart #1 (World)\n\
+             5e53c7f7 gg europe codemasters - -  Ernie Els Golf\nzzzzzzzz gg usa sega - - Broken\n",
+            crc
+        );
+        assert_eq!(system.game_database.load(&text), 2);
+        assert_eq!(
+            system.game_database.find(0x5e53c7f7).unwrap().mapper_type(),
+            MapperType::Codemasters { ram: false }
+        );
+
+        system.load_cartridge(&rom);
+        let game = system.game().unwrap();
+        assert_eq!(game.title, "Test Cart #1 (World)");
+        assert_eq!(game.peripherals, vec![Peripheral::LightPhaser, Peripheral::Paddle]);
+        assert_eq!(system.bus.rom.name(), "Sega Mapper (93C46 EEPROM)");
+    }
+
+    // Clocks `count` bits of `value` (MSB first) into the EEPROM behind 0x8000 and returns what DO read back
+    fn eeprom_shift(system: &mut System, value: u16, count: u8) -> u16 {
+        let mut out = 0;
+        for bit in (0..count).rev() {
+            let data = ((value >> bit) & 1) as u8;
+            system.bus.write(0x8000, 0b100 | data).unwrap();
+            system.bus.write(0x8000, 0b110 | data).unwrap();
+            out = out << 1 | (system.bus.read(0x8000).unwrap() & 1) as u16;
+        }
+        out
+    }
+
+    #[test]
+    fn test_eeprom() {
+        let mut system = System::new(None, false);
+        system.disable_bios();
+        system.bus.set_mapper(MapperType::SegaEeprom.create(0x20000));
+        // Start bit and opcode, followed by the 6 bit word address
+        let (write, read, ewen) = (0b101 << 6, 0b110 << 6, 0b1_0011 << 4);
+
+        // Writes are ignored until EWEN
+        system.bus.write(0xfffc, 0b1000_1000).unwrap();
+        eeprom_shift(&mut system, write | 3, 9);
+        eeprom_shift(&mut system, 0x1234, 16);
+        system.bus.write(0x8000, 0).unwrap();
+        assert_eq!(system.take_dirty_save_ram(), None);
+
+        // Like every other command EWEN only ends when CS drops, a write clocked in before that is ignored
+        eeprom_shift(&mut system, ewen, 9);
+        eeprom_shift(&mut system, write | 4, 9);
+        eeprom_shift(&mut system, 0x5678, 16);
+        system.bus.write(0x8000, 0).unwrap();
+        eeprom_shift(&mut system, write | 3, 9);
+        eeprom_shift(&mut system, 0x1234, 16);
+        system.bus.write(0x8000, 0).unwrap();
+        assert_eq!(system.bus.read(0x8000).unwrap() & 1, 1);
+
+        // READ shifts out a dummy zero while the address goes in, then the word
+        assert_eq!(eeprom_shift(&mut system, read | 3, 9) & 1, 0);
+        assert_eq!(eeprom_shift(&mut system, 0, 16), 0x1234);
+        assert_eq!(eeprom_shift(&mut system, 0, 16), 0xffff); // Word 4 was never written
+        system.bus.write(0x8000, 0).unwrap();
+
+        let save = system.take_dirty_save_ram().unwrap();
+        assert_eq!(save.len(), 128);
+        assert_eq!(&save[6..8], &[0x34, 0x12]);
+
+        // With the EEPROM unmapped slot 2 is ROM again
+        system.bus.write(0xfffc, 0).unwrap();
+        assert!(!system.bus.is_sram_bank_active());
+        assert_eq!(system.bus.read(0x8000).unwrap(), 0x00);
+    }
+
+    #[test]
+    fn test_decode_cache() {
+        let mut system = System::new(None, false);
+        system.disable_bios();
+        system.bus.rom.resize(0x20000);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x4000, 0x00); // bank 1: nop
+        system.bus.write_passthrough(&Passthrough::Rom, 0x8000, 0x3c); // bank 2: inc a
+        system.cpu.registers.sp = 0xdff0;
+
+        // The same CPU address must decode differently after a bank switch
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_1, 1).unwrap();
+        system.cpu.registers.pc = 0x4000;
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 0);
+
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_1, 2).unwrap();
+        system.cpu.registers.pc = 0x4000;
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 1);
+
+        // A cached instruction reports the address it's decoded at, not the one it was cached from
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 2).unwrap();
+        assert_eq!(system.bus.decode(0x4000).unwrap().offset, 0x4000);
+        assert_eq!(system.bus.decode(0x8000).unwrap().offset, 0x8000);
+
+        // Self-modifying code in RAM
+        system.cpu.registers.pc = 0xc000;
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 1);
+
+        system.bus.write(0xc000, 0x3c).unwrap();
+        system.cpu.registers.pc = 0xc000;
+        system.tick().unwrap();
+        assert_eq!(system.cpu.registers.a, 2);
+    }
+
+    // 128 KB ROM with every 8 KB page starting with its own number, the code is a few stores to mapper registers
+    fn mapper_test_rom(store: [u8; 2]) -> Vec<u8> {
+        let mut rom = vec![0; 0x20000];
+        for page in 0..rom.len() / 0x2000 {
+            rom[page * 0x2000] = page as u8;
+        }
+        for idx in 0..4 {
+            rom[0x100 + idx * 3..0x100 + idx * 3 + 3].copy_from_slice(&[0x32, store[0], store[1]]);
+        }
+        rom
+    }
+
+    #[test]
+    fn test_mappers() {
+        // Unknown Sega mapper carts may have up to 32 KB of SRAM
+        let sega = MapperType::Sega { ram: Some(0x8000) };
+        assert_eq!(mapper::detect(&[0; 0x8000]), sega);
+        assert_eq!(mapper::detect(&mapper_test_rom([0xff, 0xff])), sega);
+
+        // Codemasters: found through the header checksum, no fixed first KB and slot 2 starts out on bank 0
+        let mut rom = mapper_test_rom([0x00, 0x80]);
+        rom[0x7fe6..0x7fea].copy_from_slice(&[0x34, 0x12, 0xcc, 0xed]);
+        assert_eq!(mapper::detect(&rom), MapperType::Codemasters { ram: false });
+
+        let mut system = System::new(None, false);
+        system.load_cartridge(&rom);
+        system.disable_bios();
+        assert_eq!(system.bus.read(0x8000).unwrap(), 0);
+        system.bus.write(0x8000, 5).unwrap();
+        system.bus.write(0x0000, 3).unwrap();
+        assert_eq!(system.bus.read(0x8000).unwrap(), 10);
+        assert_eq!(system.bus.read(0x0000).unwrap(), 6);
+        assert_eq!(system.bus.translate_address_to_real(0x8001).unwrap(), 5 * 0x4000 + 1);
+
+        // Bit 7 of the slot 1 register pages the on-cart RAM into 0xa000 - 0xbfff
+        system.bus.set_mapper(Box::new(CodemastersMapper::new(0x20000, true)));
+        system.bus.write(0x4000, 0x81).unwrap();
+        system.bus.write(0xa000, 0x42).unwrap();
+        assert_eq!(system.bus.read(0xa000).unwrap(), 0x42);
+        assert_eq!(system.bus.translate_address_to_real(0x4000).unwrap(), 0x4000);
+        system.bus.write(0x4000, 0x01).unwrap();
+        assert_eq!(system.bus.read(0xa000).unwrap(), 0x00);
+        assert!(!system.bus.is_sram_bank_active());
+
+        // Korean: a single register at 0xa000 for slot 2
+        let rom = mapper_test_rom([0x00, 0xa0]);
+        assert_eq!(mapper::detect(&rom), MapperType::Korean);
+        system.load_cartridge(&rom);
+        assert_eq!(system.bus.read(0x8000).unwrap(), 4);
+        system.bus.write(0xa000, 6).unwrap();
+        assert_eq!(system.bus.read(0x8000).unwrap(), 12);
+        assert_eq!(system.bus.read(0x4000).unwrap(), 2);
+
+        // MSX: 8 KB pages, the registers at 0x0000 - 0x0003 select 0x8000, 0xa000, 0x4000 and 0x6000
+        let rom = mapper_test_rom([0x00, 0x00]);
+        assert_eq!(mapper::detect(&rom), MapperType::Msx);
+        system.load_cartridge(&rom);
+        system.bus.write(0x0000, 4).unwrap();
+        system.bus.write(0x0002, 9).unwrap();
+        assert_eq!(system.bus.read(0x8000).unwrap(), 4);
+        assert_eq!(system.bus.read(0x4000).unwrap(), 9);
+        assert_eq!(system.bus.read(0x2000).unwrap(), 1);
+        assert_eq!(MapperType::MsxNemesis.create(0x20000).page(0), Page::Rom(0x1e000));
+    }
+
+    #[test]
+    fn test_rom_disassembler() {
+        let fixed = Assembler::new(
+            r#"
+                    org 0
+                    di
+                    jp main
+                    org #38
+                    reti
+                    org #66
+                    retn
+                    org #100
+            main:   ld a, 3
+                    ld [#ffff], a
+                    call #8000
+                    call #4000
+            idle:   halt
+                    jr idle
+                    db "DATA"
+                    org #4000
+                    db #ed, #63, #00, #c0 ; long form of ld [#c000], hl
+                    ret
+            "#,
+        )
+        .assemble()
+        .unwrap();
+        let paged = Assembler::new(
+            "org #8000
+ld b, 2
+wait: djnz wait
+ret",
+        )
+        .assemble()
+        .unwrap();
+
+        let mut rom = vec![0xff; 0x10000];
+        rom[..fixed.bytes.len()].copy_from_slice(&fixed.bytes);
+        rom[0xc000..0xc000 + paged.bytes.len()].copy_from_slice(&paged.bytes);
+
+        let listing = RomDisassembler::new(&rom).disassemble();
+
+        // Banks come in the order the trace reached them, the one it never reached is still listed
+        let order: Vec<_> = listing.banks.iter().map(|bank| (bank.bank, bank.reached)).collect();
+        assert_eq!(order, [(0, true), (3, true), (1, true), (2, false)]);
+
+        // Bank 3 was only ever paged into slot 2
+        let bank = &listing.banks[1];
+        assert_eq!(bank.origin, 0x8000);
+        assert_eq!(bank.code_bytes, 5);
+        assert!(bank.source.contains("l03_8000:"));
+        assert!(listing.banks[0].source.contains("reset:"));
+        assert!(listing.banks[0].source.contains("call l03_8000"));
+        assert!(listing.banks[0].source.contains("jr l00_010b"));
+        assert!(listing.banks[0].source.contains("db #44, #41, #54, #41"));
+        assert!(listing.banks[2].source.contains("db #ed, #63, #00, #c0 ; ld [#c000], hl"));
+        assert!(listing.banks[3].source.starts_with("; bank 02, never reached"));
+        assert_eq!(listing.banks[3].code_bytes, 0);
+
+        for bank in &listing.banks {
+            let program = Assembler::new(&bank.source).assemble().unwrap();
+            assert_eq!(program.origin, bank.origin);
+            assert!(
+                program.bytes == rom[bank.bank * 0x4000..(bank.bank + 1) * 0x4000],
+                "Bank {}",
+                bank.bank
             );
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut system = System::new(None, true);
+        system.disable_bios();
+        system.bus.rom.resize(0x20000);
+
+        let mut symbols = SymbolTable::new();
+        let wla = "; WLA-DX symbolic information\n[labels]\n00:0000 reset\n03:8000 music_init\n00:c010 frame_counter\n[definitions]\n00000010 _sizeof_x";
+        assert_eq!(symbols.load(SymbolFormat::WlaDx, wla), 3);
+        assert_eq!(
+            symbols.load(SymbolFormat::SdccNoi, "DEF _main 0x200\nDEF s__CODE 0x200\nDEF _banked 0x54000"),
+            2
+        );
+        assert_eq!(
+            symbols.load(SymbolFormat::SdccMap, "     C:   00000300  _update    main\n Area  Addr"),
+            1
+        );
+        assert_eq!(
+            symbols.load(
+                SymbolFormat::Mesen,
+                "SmsPrgRom:C010:data_table:comment\nSmsWorkRam:0020-0021:player_x\nSmsPrgRom:0400::"
+            ),
+            2
+        );
+        system.bus.symbols = symbols;
+        let bus = &system.bus;
+
+        assert_eq!(bus.symbols.label(bus, 0x0200), Some("_main"));
+        assert_eq!(bus.symbols.symbolize(bus, 0x0205).as_deref(), Some("_main+#05"));
+        assert_eq!(bus.symbols.symbolize(bus, 0x0304).as_deref(), Some("_update+#04"));
+        assert_eq!(bus.symbols.symbolize(bus, 0xe010).as_deref(), Some("frame_counter"));
+        assert_eq!(bus.symbols.symbolize(bus, 0xc021).as_deref(), Some("player_x+#01"));
+
+        // Banked labels only show up while their bank is paged in, and never leak into the next bank
+        assert_eq!(bus.symbols.symbolize(bus, 0x8000), None);
+        assert_eq!(bus.symbols.symbolize(bus, 0x4000), None);
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_2, 3).unwrap();
+        system.bus.write(bus::MEMORY_REGISTER_CR_BANK_SELECT_1, 5).unwrap();
+        let bus = &system.bus;
+        assert_eq!(bus.symbols.label(bus, 0x8000), Some("music_init"));
+        assert_eq!(bus.symbols.label(bus, 0x4000), Some("_banked"));
+        assert_eq!(bus.symbols.symbolize(bus, 0x8010).as_deref(), Some("data_table"));
+
+        let decode = |bytes: &[u8]| Disassembler::new(bytes).decode(0).unwrap();
+        assert_eq!(
+            bus.symbols.format_instruction(bus, 0x0100, &decode(&[0xcd, 0x00, 0x80])),
+            "call music_init"
+        );
+        assert_eq!(
+            bus.symbols.format_instruction(bus, 0x0100, &decode(&[0x3a, 0x21, 0xc0])),
+            "ld a, [player_x+#01]"
+        );
+        assert_eq!(bus.symbols.format_instruction(bus, 0x0100, &decode(&[0x3e, 0x02])), "ld a, #02");
+        assert_eq!(bus.symbols.format_instruction(bus, 0x0201, &decode(&[0x18, 0xfd])), "jr _main");
+
+        // Cartridge RAM paged into slot 2 doesn't pick up the labels of ROM bank 0
+        system.bus.write(0xfffc, 0b0000_1000).unwrap();
+        let bus = &system.bus;
+        assert_eq!(bus.symbols.symbolize(bus, 0x8010), None);
+    }
+
+    #[test]
+    fn test_interrupt_modes() {
+        let mut system = flat_system(false);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x12ff, 0x34);
+        system.bus.write_passthrough(&Passthrough::Rom, 0x1300, 0x56);
+
+        let mut irq = |mode: InterruptMode, data_bus: u8| {
+            system.cpu.registers.pc = 0x0100;
+            system.cpu.registers.sp = 0xdff0;
+            system.cpu.registers.i = 0x12;
+            system.cpu.interrupt_mode = mode;
+            system.bus.data_bus = data_bus;
+
+            let cycles_before_irq = system.cpu.cycles;
+            let instruction = system.decode_instr_at_pc().unwrap();
+            let mut machine = Machine::new(&mut system.bus, &mut system.vdp, &mut system.psg);
+            system.cpu.trigger_irq(&mut machine, &instruction).unwrap();
+            let pushed = (system.cpu.registers.sp == 0xdfee).then(|| system.bus.read_word(0xdfee).unwrap());
+            (system.cpu.registers.pc, pushed, system.cpu.cycles - cycles_before_irq)
+        };
+
+        assert_eq!(irq(InterruptMode::IM0, 0xff), (0x0038, Some(0x0100), 13));
+        assert_eq!(irq(InterruptMode::IM0, 0xd7), (0x0010, Some(0x0100), 13));
+        // call #cdcd, the address bytes come from the following acknowledge cycles
+        assert_eq!(irq(InterruptMode::IM0, 0xcd), (0xcdcd, Some(0x0100), 19));
+        // nop, execution continues with the interrupted instruction
+        assert_eq!(irq(InterruptMode::IM0, 0x00), (0x0100, None, 6));
+        assert_eq!(irq(InterruptMode::IM1, 0x00), (0x0038, Some(0x0100), 13));
+        assert_eq!(irq(InterruptMode::IM2, 0xff), (0x5634, Some(0x0100), 19));
+    }
+
+    // Serves the `in` values of a jsmoo vector in order and records all port traffic
+    struct MockPorts {
+        reads: VecDeque<u8>,
+        log: Rc<RefCell<Vec<(u16, u8, String)>>>,
+    }
+
+    impl PortDevice for MockPorts {
+        fn read_io(&mut self, port: u16) -> Result<u8, GgError> {
+            let value = self.reads.pop_front().unwrap_or(0xff);
+            self.log.borrow_mut().push((port, value, "r".to_string()));
+            Ok(value)
+        }
+
+        fn write_io(&mut self, port: u16, value: u8) -> Result<(), GgError> {
+            self.log.borrow_mut().push((port, value, "w".to_string()));
+            Ok(())
+        }
+    }
+
+    const PORT_TRAFFIC_TESTS: &str = r#"[
+        {
+            "name": "db 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 18, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 219], [1, 52]]},
+            "final": {"pc": 2, "sp": 49408, "a": 171, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 219], [1, 52]]},
+            "cycles": [[0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"]],
+            "ports": [[4660, 171, "r"]]
+        },
+        {
+            "name": "ed 79 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 85, "b": 18, "c": 190, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 237], [1, 121]]},
+            "final": {"pc": 2, "sp": 49408, "a": 85, "b": 18, "c": 190, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 237], [1, 121]]},
+            "cycles": [[0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"], [0, null, "----"]],
+            "ports": [[4798, 85, "w"]]
+        }
+    ]"#;
+
+    const BLOCK_IO_AND_RETN_TESTS: &str = r#"[
+        {
+            "name": "ed b2 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 0, "b": 2, "c": 220, "d": 0, "e": 0, "f": 0, "h": 192, "l": 0, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 237], [1, 178], [49152, 0]]},
+            "final": {"pc": 0, "sp": 49408, "a": 0, "b": 1, "c": 220, "d": 0, "e": 0, "f": 7, "h": 192, "l": 1,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 237], [1, 178], [49152, 255]]}
+        },
+        {
+            "name": "ed b2 0001",
+            "initial": {"pc": 0, "sp": 49408, "a": 0, "b": 1, "c": 126, "d": 0, "e": 0, "f": 1, "h": 192, "l": 16, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 237], [1, 178], [49168, 170]]},
+            "final": {"pc": 2, "sp": 49408, "a": 0, "b": 0, "c": 126, "d": 0, "e": 0, "f": 64, "h": 192, "l": 17,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 237], [1, 178], [49168, 0]]}
+        },
+        {
+            "name": "ed aa 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 0, "b": 16, "c": 220, "d": 0, "e": 0, "f": 0, "h": 192, "l": 32, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 237], [1, 170], [49184, 0]]},
+            "final": {"pc": 2, "sp": 49408, "a": 0, "b": 15, "c": 220, "d": 0, "e": 0, "f": 27, "h": 192, "l": 31,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 237], [1, 170], [49184, 255]]}
+        },
+        {
+            "name": "ed ba 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 0, "b": 25, "c": 126, "d": 0, "e": 0, "f": 0, "h": 192, "l": 48, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                        "ram": [[0, 237], [1, 186], [49200, 170]]},
+            "final": {"pc": 0, "sp": 49408, "a": 0, "b": 24, "c": 126, "d": 0, "e": 0, "f": 4, "h": 192, "l": 47,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 0,
+                      "ram": [[0, 237], [1, 186], [49200, 0]]}
+        },
+        {
+            "name": "ed 45 0000",
+            "initial": {"pc": 0, "sp": 49408, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "i": 0, "r": 0,
+                        "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 0, "iff2": 1,
+                        "ram": [[0, 237], [1, 69], [49408, 52], [49409, 18]]},
+            "final": {"pc": 4660, "sp": 49410, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0,
+                      "af_": 0, "bc_": 0, "de_": 0, "hl_": 0, "ix": 0, "iy": 0, "iff1": 1, "iff2": 1,
+                      "ram": [[0, 237], [1, 69], [49408, 52], [49409, 18]]}
+        }
+    ]"#;
+
+    // 64 KB of writable ROM without the BIOS or mapper in the way, for tests that place their own code and data
+    fn flat_system(emulate_sms: bool) -> System {
+        let mut system = System::new(None, emulate_sms);
+        system.disable_bios();
+        system.bus.rom.resize(0xffff);
+        system.bus.set_rom_write_protection(RomWriteProtection::Allow);
+        system.bus.disable_bank_behavior(true);
+        system
+    }
 
+    fn run_test(test: &serde_json::Map<String, Value>) {
+        let name = test.get("name").unwrap().as_str().unwrap();
+        // println!("Running test: {}", name);
+
+        let initial = test.get("initial").unwrap().as_object().unwrap();
+        let final_ = test.get("final").unwrap().as_object().unwrap();
+
+        let mut system = flat_system(false);
+        system.set_abort_on_io_operation_behavior(false);
+
+        system.cpu.registers.a = initial.get("a").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.b = initial.get("b").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.c = initial.get("c").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.d = initial.get("d").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.e = initial.get("e").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.h = initial.get("h").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.l = initial.get("l").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.i = initial.get("i").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.r = initial.get("r").unwrap().as_u64().unwrap() as u8;
+        system.cpu.registers.f = Flags::from_bits(initial.get("f").unwrap().as_u64().unwrap() as u8).unwrap();
+        system
+            .cpu
+            .set_register_u16(Reg16::AFShadow, initial.get("af_").unwrap().as_u64().unwrap() as u16);
+        system
+            .cpu
+            .set_register_u16(Reg16::BCShadow, initial.get("bc_").unwrap().as_u64().unwrap() as u16);
+        system
+            .cpu
+            .set_register_u16(Reg16::DEShadow, initial.get("de_").unwrap().as_u64().unwrap() as u16);
+        system
+            .cpu
+            .set_register_u16(Reg16::HLShadow, initial.get("hl_").unwrap().as_u64().unwrap() as u16);
+        system
+            .cpu
+            .set_register_u16(Reg16::IX(None), initial.get("ix").unwrap().as_u64().unwrap() as u16);
+        system
+            .cpu
+            .set_register_u16(Reg16::IY(None), initial.get("iy").unwrap().as_u64().unwrap() as u16);
+
+        system.cpu.registers.pc = initial.get("pc").unwrap().as_u64().unwrap() as u16;
+        system.cpu.registers.sp = initial.get("sp").unwrap().as_u64().unwrap() as u16;
+        if let Some(wz) = initial.get("wz") {
+            system.cpu.registers.wz = wz.as_u64().unwrap() as u16;
+        }
+
+        system.cpu.registers.iff1 = initial.get("iff1").unwrap().as_u64().unwrap() != 0;
+        system.cpu.registers.iff2 = initial.get("iff2").unwrap().as_u64().unwrap() != 0;
+
+        let ram = initial.get("ram").unwrap().as_array().unwrap();
+        for value in ram {
+            let addr = value.as_array().unwrap()[0].as_u64().unwrap() as usize;
+            let value = value.as_array().unwrap()[1].as_u64().unwrap() as u8;
+
+            if addr < 0xc000 {
+                system.bus.write_passthrough(&Passthrough::Rom, addr, value);
+            } else {
+                system.bus.write_passthrough(&Passthrough::Ram, addr - 0xc000, value);
+            }
+        }
+
+        // Only the low byte of the port address is decoded by the SMS/GG, so that's all we compare
+        let expected_ports = test.get("ports").map(|ports| {
+            ports
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|port| {
+                    let port = port.as_array().unwrap();
+                    (
+                        port[0].as_u64().unwrap() as u16,
+                        port[1].as_u64().unwrap() as u8,
+                        port[2].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let port_log = Rc::new(RefCell::new(Vec::new()));
+        if let Some(expected_ports) = &expected_ports {
+            system.bus.port_device = Some(Box::new(MockPorts {
+                reads: expected_ports
+                    .iter()
+                    .filter(|(_, _, direction)| direction == "r")
+                    .map(|(_, value, _)| *value)
+                    .collect(),
+                log: Rc::clone(&port_log),
+            }));
+        }
+
+        let decoded = system.decode_instr_at_pc().unwrap().opcode;
+        let cycles_before_tick = system.cpu.cycles;
+
+        match system.tick() {
+            Ok(_) => (),
+            Err(e) => panic!("{}", e),
+        }
+
+        if let Some(cycles) = test.get("cycles") {
             assert_eq!(
-                system.cpu.registers.iff1,
-                final_.get("iff1").unwrap().as_u64().unwrap() != 0,
-                "Testcase {} ({})",
+                system.cpu.cycles - cycles_before_tick,
+                cycles.as_array().unwrap().len(),
+                "Testcase {} ({}): cycles",
                 name,
                 decoded
             );
+        }
+
+        if let Some(expected_ports) = expected_ports {
+            assert_eq!(*port_log.borrow(), expected_ports, "Testcase {} ({}): ports", name, decoded);
+        }
+
+        assert_eq!(
+            system.cpu.registers.a,
+            final_.get("a").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.b,
+            final_.get("b").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.c,
+            final_.get("c").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.d,
+            final_.get("d").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.e,
+            final_.get("e").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.h,
+            final_.get("h").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.l,
+            final_.get("l").unwrap().as_u64().unwrap() as u8,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::AFShadow),
+            final_.get("af_").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::BCShadow),
+            final_.get("bc_").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::DEShadow),
+            final_.get("de_").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::HLShadow),
+            final_.get("hl_").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::IX(None)),
+            final_.get("ix").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.get_register_u16(Reg16::IY(None)),
+            final_.get("iy").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+
+        let final_f = Flags::from_bits(final_.get("f").unwrap().as_u64().unwrap() as u8).unwrap();
+        assert_eq!(system.cpu.registers.f, final_f, "Testcase {} ({})", name, decoded);
 
+        if let Some(wz) = final_.get("wz") {
             assert_eq!(
-                system.cpu.registers.iff2,
-                final_.get("iff2").unwrap().as_u64().unwrap() != 0,
+                system.cpu.registers.wz,
+                wz.as_u64().unwrap() as u16,
                 "Testcase {} ({})",
                 name,
                 decoded
             );
-
-            let ram = final_.get("ram").unwrap().as_array().unwrap();
-            for value in ram {
-                let addr = value.as_array().unwrap()[0].as_u64().unwrap() as u16;
-                let value = value.as_array().unwrap()[1].as_u64().unwrap() as u8;
-                assert_eq!(system.bus.read(addr).unwrap(), value, "Testcase {} ({})", name, decoded);
-            }
         }
-    }
 
-    // We don't care about F3 and F5 for now
-    fn reset_undocumented_flags(lhs: &mut Flags, rhs: &mut Flags) {
-        lhs.set(Flags::F3, false);
-        lhs.set(Flags::F5, false);
-        rhs.set(Flags::F3, false);
-        rhs.set(Flags::F5, false);
+        assert_eq!(
+            system.cpu.registers.pc,
+            final_.get("pc").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+        assert_eq!(
+            system.cpu.registers.sp,
+            final_.get("sp").unwrap().as_u64().unwrap() as u16,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+
+        assert_eq!(
+            system.cpu.registers.iff1,
+            final_.get("iff1").unwrap().as_u64().unwrap() != 0,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+
+        assert_eq!(
+            system.cpu.registers.iff2,
+            final_.get("iff2").unwrap().as_u64().unwrap() != 0,
+            "Testcase {} ({})",
+            name,
+            decoded
+        );
+
+        let ram = final_.get("ram").unwrap().as_array().unwrap();
+        for value in ram {
+            let addr = value.as_array().unwrap()[0].as_u64().unwrap() as u16;
+            let value = value.as_array().unwrap()[1].as_u64().unwrap() as u8;
+            assert_eq!(system.bus.read(addr).unwrap(), value, "Testcase {} ({})", name, decoded);
+        }
     }
 }
@@ -8,6 +8,7 @@ use crate::error::GgError;
 use crate::io::Controller;
 use crate::lua_engine::{HookType, LuaEngine};
 use crate::memory::Memory;
+use crate::scheduler::VDP_CLOCK_DIVIDER;
 use crate::vdp::pattern::Pattern;
 use log::{debug, error, trace};
 
@@ -25,6 +26,9 @@ pub const VISIBLE_HEIGHT: usize = 144;
 pub const OFFSET_X: usize = 48;
 pub const OFFSET_Y: usize = 24;
 
+// 342 pixels plus the extra dot of the H counter jump
+pub(crate) const DOTS_PER_LINE: usize = 343;
+
 pub type Color = (u8, u8, u8, u8);
 
 enum IoMode {
@@ -50,7 +54,7 @@ pub struct Registers {
     pub address: u16,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Mode {
     SegaMasterSystem,
     GameGear,
@@ -60,6 +64,8 @@ pub struct Vdp {
     pub v: u8,
     pub h: u8,
     pub registers: Registers,
+    clock: usize, // Master clock cycle the VDP has been caught up to
+    h_latch: u8,
     pub vram: Memory<u16>,
     pub cram: Memory<u16>,
     pub(crate) data_buffer: u8,
@@ -83,6 +89,8 @@ impl Vdp {
         Vdp {
             v: 0,
             h: 0,
+            clock: 0,
+            h_latch: 0,
             v_2nd_loop: false,
             h_2nd_loop: false,
             control_data: VecDeque::new(),
@@ -103,7 +111,49 @@ impl Vdp {
         }
     }
 
-    pub(crate) fn tick(&mut self) -> bool {
+    /// What a read from a VDP port would return, without advancing the address or clearing the status flags
+    pub fn peek_io(&self, port: u8) -> Result<u8, GgError> {
+        match port {
+            0x40..=0x7f if port.is_multiple_of(2) => Ok(self.v),
+            0x40..=0x7f => Ok(self.h_latch),
+            IO_DATA_CONTROL_START..=IO_DATA_CONTROL_END if port.is_multiple_of(2) => Ok(self.data_buffer),
+            IO_DATA_CONTROL_START..=IO_DATA_CONTROL_END => Ok(self.status),
+            _ => Err(GgError::IoControllerInvalidPort),
+        }
+    }
+
+    pub fn peek_vram(&self, address: u16) -> u8 {
+        self.vram.read(address % 0x4000)
+    }
+
+    /// Changes VRAM without touching the address register or running the Lua hooks
+    pub fn poke_vram(&mut self, address: u16, value: u8) {
+        self.vram.write(address % 0x4000, value);
+        self.vram_dirty = true;
+    }
+
+    pub fn peek_cram(&self, address: u16) -> u8 {
+        self.cram.read(address % self.cram.buffer.len() as u16)
+    }
+
+    /// Changes CRAM directly, the Game Gear's latch for the low byte isn't involved
+    pub fn poke_cram(&mut self, address: u16, value: u8) {
+        let address = address % self.cram.buffer.len() as u16;
+        self.cram.write(address, value);
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn catch_up(&mut self, clock: usize) {
+        while self.clock < clock {
+            self.tick();
+            self.clock += VDP_CLOCK_DIVIDER;
+        }
+    }
+
+    pub(crate) fn tick(&mut self) {
         self.handle_counters();
 
         // Line IRQ
@@ -119,10 +169,29 @@ impl Vdp {
         if self.is_vblank() && self.is_hblank() {
             self.status |= 0b1000_0000;
         }
+    }
 
+    pub(crate) fn frame_ready(&self) -> bool {
         self.v_2nd_loop && self.v > INTERNAL_HEIGHT as u8 && self.vram_dirty
     }
 
+    pub(crate) fn latch_h_counter(&mut self) {
+        self.h_latch = self.h;
+    }
+
+    // Master clock cycle at which the next line starts (h = 0)
+    pub(crate) fn next_line_clock(&self) -> usize {
+        self.clock + (DOTS_PER_LINE - self.dot_index()) * VDP_CLOCK_DIVIDER
+    }
+
+    fn dot_index(&self) -> usize {
+        if self.h_2nd_loop {
+            0xea + (self.h - 0x93) as usize
+        } else {
+            self.h as usize
+        }
+    }
+
     pub fn vblank_irq_pending(&self) -> bool {
         if self.registers.r1 & 0b0010_0000 > 0 {
             return self.status & 0b1000_0000 > 0;
@@ -206,13 +275,15 @@ impl Vdp {
 
         for idx in 0..64 {
             let sprite_attr_base_addr = self.get_sprite_attribute_table_addr();
-            let y = self.vram.read(sprite_attr_base_addr + idx) + 1;
+            let y = self.vram.read(sprite_attr_base_addr + idx);
             let x = self.vram.read(sprite_attr_base_addr + 0x80 + 2 * idx);
             let n = self.vram.read(sprite_attr_base_addr + 0x80 + 2 * idx + 1);
 
+            // The terminator is the raw Y value, before the sprite is moved down by one line
             if y == 0xd0 {
                 break;
             }
+            let y = y.wrapping_add(1);
 
             if y == 0xe0 {
                 continue;
@@ -631,10 +702,11 @@ impl Controller for Vdp {
     fn read_io(&mut self, port: u8) -> Result<u8, GgError> {
         match port {
             0x40..=0x7f => {
+                // The H counter only holds a meaningful value after it was latched by a TH pin transition
                 if port % 2 == 0 {
                     Ok(self.v)
                 } else {
-                    Ok(self.h)
+                    Ok(self.h_latch)
                 }
             }
             IO_DATA_CONTROL_START..=IO_DATA_CONTROL_END => {
@@ -0,0 +1,108 @@
+use crate::error::GgError;
+use crate::system::System;
+
+// Printed by the SMS port of ZEXDOC/ZEXALL once every group has run
+const COMPLETE_MARKER: &str = "Tests complete";
+const PASSED_MARKER: &str = "OK";
+const CRC_MARKER: &str = "CRC";
+const EXPECTED_MARKER: &str = "expected";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZexGroup {
+    pub name: String,
+    pub passed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ZexReport {
+    pub groups: Vec<ZexGroup>,
+    pub complete: bool,
+    pending: Option<String>, // The last line that wasn't a result, a failing group prints its CRCs below its name
+}
+
+impl ZexReport {
+    // A passing group prints "<name> OK", a failing one prints "<name>" and then "CRC xxxxxxxx expected xxxxxxxx" on
+    // the next line
+    pub(crate) fn parse_line(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+
+        if line.contains(COMPLETE_MARKER) {
+            self.complete = true;
+            self.pending = None;
+        } else if let Some(name) = line.strip_suffix(PASSED_MARKER) {
+            self.push_group(name, true);
+        } else if let Some(idx) = line.find(CRC_MARKER).filter(|_| line.contains(EXPECTED_MARKER)) {
+            // Without a line break the name is still in front of the CRCs
+            let name = match line[..idx].trim() {
+                "" => self.pending.take().unwrap_or_default(),
+                name => name.to_string(),
+            };
+            self.push_group(&name, false);
+        } else {
+            self.pending = Some(line.to_string());
+        }
+    }
+
+    fn push_group(&mut self, name: &str, passed: bool) {
+        self.pending = None;
+        self.groups.push(ZexGroup {
+            name: name.trim().to_string(),
+            passed,
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.complete && self.groups.iter().all(|group| group.passed)
+    }
+
+    pub fn failed_groups(&self) -> impl Iterator<Item = &ZexGroup> {
+        self.groups.iter().filter(|group| !group.passed)
+    }
+}
+
+// Runs ZEXDOC/ZEXALL without a window and collects the per group results from the SDSC debug console.
+// The run stops once the ROM reports completion or, if given, after `max_cycles` CPU T-states.
+pub fn run(rom: &[u8], max_cycles: Option<usize>) -> Result<ZexReport, GgError> {
+    let mut system = System::new(None, true);
+    system.set_abort_on_io_operation_behavior(false);
+    system.load_cartridge(rom);
+    system.disable_bios();
+
+    let mut report = ZexReport::default();
+    let mut line = String::new();
+    let mut consumed = 0;
+
+    while !report.complete {
+        if max_cycles.is_some_and(|max_cycles| system.cpu.cycles >= max_cycles) {
+            break;
+        }
+
+        // Sprite collisions are only detected while rendering, the exercisers rely on them to pick Mode 4 over the
+        // TMS9918 text mode, whose scroll buffer overlaps ZEXDOC's test harness in RAM
+        if system.tick()?.frame_ready {
+            system.render();
+        }
+
+        // The console buffer can be cleared by the ROM, so only look at what was appended since the last tick
+        let buffer = &system.bus.sdsc_console.buffer;
+        if buffer.len() < consumed {
+            consumed = 0;
+        }
+
+        for c in buffer[consumed..].chars() {
+            if c != '\r' && c != '\n' {
+                line.push(c);
+                continue;
+            }
+
+            report.parse_line(&line);
+            line.clear();
+        }
+        consumed = buffer.len();
+    }
+
+    Ok(report)
+}
@@ -1,7 +1,8 @@
 use std::collections::VecDeque;
 
 use core::bus::{
-    BankSelect, RomWriteProtection, MEMORY_REGISTER_CR_BANK_SELECT_0, MEMORY_REGISTER_CR_BANK_SELECT_1, MEMORY_REGISTER_CR_BANK_SELECT_2,
+    BankSelect, Media, RomWriteProtection, MEMORY_REGISTER_CR_BANK_SELECT_0, MEMORY_REGISTER_CR_BANK_SELECT_1,
+    MEMORY_REGISTER_CR_BANK_SELECT_2,
 };
 use core::system::{System, SystemState};
 use core::vdp::{Color, INTERNAL_HEIGHT, INTERNAL_WIDTH, OFFSET_X, OFFSET_Y, VISIBLE_HEIGHT, VISIBLE_WIDTH};
@@ -11,14 +12,17 @@ use eframe::egui::{
     Window,
 };
 use eframe::CreationContext;
-use log::error;
+use log::{error, info};
+use std::fs;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use z80::disassembler::Disassembler;
-use z80::instruction::{Instruction, Opcode};
+use z80::instruction::Instruction;
 
 use crate::EmulatorSettings;
 
 pub(crate) const SCALE: usize = 8;
+const SAVE_RAM_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(PartialEq, Debug)]
 enum MemoryView {
@@ -33,7 +37,7 @@ pub(crate) struct Emulator {
     system: System,
     background_color: Color,
     dissasembly_cache: Vec<Instruction>,
-    trace: VecDeque<(u16, Opcode)>,
+    trace: VecDeque<(u16, Instruction)>,
     paused: bool,
     stepping: bool,
     debugger_enabled: bool,
@@ -44,6 +48,8 @@ pub(crate) struct Emulator {
     memory_view: MemoryView,
     frame_time_cap: Duration,
     frame_time: Instant,
+    save_path: Option<PathBuf>,
+    save_time: Instant,
 }
 
 impl eframe::App for Emulator {
@@ -75,8 +81,17 @@ impl eframe::App for Emulator {
 
         self.handle_input(ctx);
 
+        if self.save_time.elapsed() > SAVE_RAM_FLUSH_INTERVAL {
+            self.flush_save_ram();
+            self.save_time = Instant::now();
+        }
+
         ctx.request_repaint();
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.flush_save_ram();
+    }
 }
 
 impl Emulator {
@@ -84,6 +99,12 @@ impl Emulator {
         let mut system = System::new(emulator_settings.lua, emulator_settings.emulate_sms);
         system.set_abort_on_io_operation_behavior(false); // Let's only log invalid ports
         system.bus.set_rom_write_protection(RomWriteProtection::Warn);
+        system.bus.fill_ram(emulator_settings.ram_fill);
+
+        if let Some(text) = &emulator_settings.game_db {
+            let count = system.game_database.load(text);
+            info!("Loaded {} game database overrides", count);
+        }
 
         if emulator_settings.cpu_test {
             system.load_cartridge(emulator_settings.cartridge.as_ref());
@@ -93,6 +114,25 @@ impl Emulator {
             system.load_cartridge(emulator_settings.cartridge.as_ref());
         }
 
+        if let Some(path) = &emulator_settings.save_path {
+            if let Ok(save) = fs::read(path) {
+                info!("Loaded save RAM from {}", path.display());
+                system.load_save_ram(&save);
+            }
+        }
+
+        if let Some(card) = &emulator_settings.card {
+            system.load_media(Media::Card, card);
+        }
+        if let Some(expansion) = &emulator_settings.expansion {
+            system.load_media(Media::Expansion, expansion);
+        }
+
+        for (format, text) in &emulator_settings.symbols {
+            let count = system.bus.symbols.load(*format, text);
+            info!("Loaded {} labels", count);
+        }
+
         let internal_texture = cc.egui_ctx.load_texture(
             "internal_frame",
             ColorImage::new([INTERNAL_WIDTH, INTERNAL_HEIGHT], Color32::BLACK),
@@ -119,6 +159,19 @@ impl Emulator {
             memory_view: MemoryView::Rom,
             frame_time_cap: Duration::from_micros(200),
             frame_time: Instant::now(),
+            save_path: emulator_settings.save_path,
+            save_time: Instant::now(),
+        }
+    }
+
+    fn flush_save_ram(&mut self) {
+        let (Some(path), Some(save)) = (&self.save_path, self.system.take_dirty_save_ram()) else {
+            return;
+        };
+
+        match fs::write(path, save) {
+            Ok(()) => info!("Saved save RAM to {}", path.display()),
+            Err(e) => error!("Failed to write {}: {}", path.display(), e),
         }
     }
 
@@ -133,10 +186,13 @@ impl Emulator {
         }
 
         ctx.input(|i| {
+            // The Master System has no START button, its Pause button takes the same key
             if i.key_down(Key::Enter) {
                 self.system.bus.joysticks[0].set_start(true);
+                self.system.bus.joysticks[0].set_pause(true);
             } else {
                 self.system.bus.joysticks[0].set_start(false);
+                self.system.bus.joysticks[0].set_pause(false);
             }
 
             if i.key_down(Key::A) {
@@ -216,7 +272,7 @@ impl Emulator {
             ui.horizontal(|ui| {
                 ui.label(format!(
                     "ROM: {}",
-                    if self.system.bus.bios_enabled {
+                    if self.system.bus.bios_enabled() {
                         String::from("BIOS")
                     } else {
                         format!("Cartridge ({})", self.system.bus.rom.name())
@@ -266,6 +322,9 @@ impl Emulator {
                 ui.label(format!("IY: {:04x}", self.system.cpu.registers.iy));
                 ui.label("Flags: SZ-H-PNC");
                 ui.label(format!("       {:08b}", self.system.cpu.registers.f.bits()));
+                if self.system.cpu.halted {
+                    ui.label("HALTED");
+                }
             });
 
             ui.separator();
@@ -310,12 +369,13 @@ impl Emulator {
         });
 
         Window::new("CPU Mappings").resizable(false).show(ctx, |ui| {
-            let rom0_bank = self.system.bus.read(MEMORY_REGISTER_CR_BANK_SELECT_0);
-            let rom1_bank = self.system.bus.read(MEMORY_REGISTER_CR_BANK_SELECT_1);
-            let rom2_bank = self.system.bus.read(MEMORY_REGISTER_CR_BANK_SELECT_2);
+            let rom0_bank = self.system.bus.peek(MEMORY_REGISTER_CR_BANK_SELECT_0);
+            let rom1_bank = self.system.bus.peek(MEMORY_REGISTER_CR_BANK_SELECT_1);
+            let rom2_bank = self.system.bus.peek(MEMORY_REGISTER_CR_BANK_SELECT_2);
             let sram_active = self.system.bus.is_sram_bank_active();
             let sram_bank = self.system.bus.fetch_bank(BankSelect::Bank2);
 
+            ui.label(format!("Memory Control: {:08b}", self.system.bus.memory_control()));
             ui.label(format!(
                 "SRAM Bank #{:02x}: {:08x} [{}]",
                 sram_bank,
@@ -324,21 +384,52 @@ impl Emulator {
             ));
             ui.label(format!(
                 "ROM Bank #{:02x}: {:08x}",
-                rom0_bank.unwrap_or(0),
+                rom0_bank,
                 self.system.bus.translate_address_to_real(0x0000).unwrap_or(0x69)
             ));
             ui.label(format!(
                 "ROM Bank #{:02x}: {:08x}",
-                rom1_bank.unwrap_or(0),
+                rom1_bank,
                 self.system.bus.translate_address_to_real(0x4000).unwrap_or(0x69)
             ));
             ui.label(format!(
                 "ROM Bank #{:02x}: {:08x}",
-                rom2_bank.unwrap_or(0),
+                rom2_bank,
                 self.system.bus.translate_address_to_real(0x8000).unwrap_or(0x69)
             ));
         });
 
+        Window::new("Cartridge").resizable(false).show(ctx, |ui| {
+            let rom_info = self.system.rom_info();
+            ui.label(format!("Size: {} KB  CRC32: {:08x}", rom_info.size / 1024, rom_info.crc32));
+            if let Some(game) = self.system.game() {
+                ui.label(format!("Title: {}", game.title));
+                ui.label(format!("Database: {:?} {:?} {:?}", game.system, game.region, game.save));
+            }
+
+            match &rom_info.header {
+                Some(header) => {
+                    ui.label(format!("Header: {:04x}", header.offset));
+                    ui.label(format!("Product: {:05}  Version: {}", header.product_code, header.version));
+                    ui.label(format!("Region: {}", header.region));
+                    ui.label(format!(
+                        "Declared Size: {}",
+                        header
+                            .declared_size
+                            .map_or(String::from("Invalid"), |size| format!("{} KB", size / 1024))
+                    ));
+                    ui.label(format!(
+                        "Checksum: {:04x} [{}]",
+                        header.checksum,
+                        if header.checksum_valid { "Valid" } else { "Invalid" }
+                    ));
+                }
+                None => {
+                    ui.label("Header: None");
+                }
+            }
+        });
+
         Window::new("Memory").resizable(false).min_width(500.0).show(ctx, |ui| {
             ComboBox::from_label("Source")
                 .selected_text(format!("{:?}", self.memory_view))
@@ -370,10 +461,10 @@ impl Emulator {
                             let addr = base_addr + offset;
 
                             let value = match self.memory_view {
-                                MemoryView::Rom | MemoryView::Ram => self.system.bus.read(addr as u16).unwrap_or(0x69),
-                                MemoryView::Sram => self.system.bus.sram.read(addr as u16),
-                                MemoryView::Vram => self.system.vdp.vram.read(addr as u16),
-                                MemoryView::Cram => self.system.vdp.cram.read(addr as u16),
+                                MemoryView::Rom | MemoryView::Ram => self.system.bus.peek(addr as u16),
+                                MemoryView::Sram => self.system.bus.peek_cartridge_ram(addr),
+                                MemoryView::Vram => self.system.vdp.peek_vram(addr as u16),
+                                MemoryView::Cram => self.system.vdp.peek_cram(addr as u16),
                             };
 
                             line += &format!(" {:02x}", value);
@@ -397,8 +488,12 @@ impl Emulator {
         SidePanel::right("Right Panel").show(ctx, |ui| {
             ui.heading("Disassembly");
             let mut addr = self.system.cpu.registers.pc;
+            let bus = &self.system.bus;
             for instr in &self.dissasembly_cache {
-                ui.label(format!("{:04x}: {}", addr, instr.opcode));
+                if let Some(label) = bus.symbols.label(bus, addr) {
+                    ui.label(format!("{}:", label));
+                }
+                ui.label(format!("{:04x}: {}", addr, bus.symbols.format_instruction(bus, addr, instr)));
                 addr += instr.length as u16;
             }
 
@@ -409,8 +504,10 @@ impl Emulator {
                 .stick_to_bottom(true)
                 .scroll_bar_visibility(ScrollBarVisibility::AlwaysHidden)
                 .show(ui, |ui| {
-                    for instr in &self.trace {
-                        ui.label(format!("{:04x}: {}", instr.0, instr.1));
+                    // Labels resolve through the current banks, older entries may have run in another bank
+                    let bus = &self.system.bus;
+                    for (addr, instr) in &self.trace {
+                        ui.label(format!("{:04x}: {}", addr, bus.symbols.format_instruction(bus, *addr, instr)));
                     }
                 });
         });
@@ -430,12 +527,14 @@ impl Emulator {
             }
 
             match self.system.decode_instr_at_pc() {
+                // Don't flood the trace with the same HALT while waiting for an interrupt
+                Ok(_) if self.system.cpu.halted => (),
                 Ok(instr) => {
                     if self.trace.len() == 1024 {
                         self.trace.pop_front();
                     }
 
-                    self.trace.push_back((self.system.cpu.registers.pc, instr.opcode))
+                    self.trace.push_back((self.system.cpu.registers.pc, instr))
                 }
                 Err(e) => error!("{}", e),
             }
@@ -455,14 +554,9 @@ impl Emulator {
         }
 
         // Update disasaembly cache
-        let mut data: Vec<u8> = Vec::new();
-        for offset in 0..100 {
-            data.push(self.system.bus.read(self.system.cpu.registers.pc + offset).unwrap())
-        }
-
         self.dissasembly_cache.clear();
-        let disasm = Disassembler::new(&data);
-        let mut current_offset = 0;
+        let disasm = Disassembler::from_source(&self.system.bus);
+        let mut current_offset = self.system.cpu.registers.pc as usize;
         for _ in 0..10 {
             match disasm.decode(current_offset) {
                 Ok(instr) => {
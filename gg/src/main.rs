@@ -1,6 +1,9 @@
 mod emulator;
 
 use clap::Parser;
+use core::bus::RamFill;
+use core::rom_info::RomInfo;
+use core::symbols::SymbolFormat;
 use core::vdp::{VISIBLE_HEIGHT, VISIBLE_WIDTH};
 use eframe::egui::{FontFamily, FontId, Style, TextStyle, ViewportBuilder, Visuals};
 use eframe::NativeOptions;
@@ -10,6 +13,7 @@ use log::{info, Level};
 use std::fs::File;
 use std::io;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use zip::ZipArchive;
 
 #[derive(Parser, Debug)]
@@ -20,12 +24,27 @@ struct Args {
     #[arg(long)]
     rom: String,
 
+    #[arg(long)]
+    card: Option<String>,
+
+    #[arg(long)]
+    expansion: Option<String>,
+
     #[arg(long)]
     lua: Option<String>,
 
+    #[arg(long)]
+    symbols: Vec<String>,
+
+    #[arg(long)]
+    game_db: Option<String>,
+
     #[arg(long, default_value_t = false)]
     cpu_test: bool,
 
+    #[arg(long, default_value_t = String::from("zero"))]
+    ram_fill: String,
+
     #[arg(long, default_value_t = String::from("info"))]
     log_level: String,
 
@@ -37,9 +56,15 @@ struct EmulatorSettings {
     bios: Vec<u8>,
     cartridge: Vec<u8>,
     cartridge_name: String,
+    card: Option<Vec<u8>>,
+    expansion: Option<Vec<u8>>,
+    save_path: Option<PathBuf>,
     lua: Option<String>,
+    symbols: Vec<(SymbolFormat, String)>,
+    game_db: Option<String>,
     emulate_sms: bool,
     cpu_test: bool,
+    ram_fill: RamFill,
 }
 
 fn main() {
@@ -101,7 +126,11 @@ fn get_emulator_settings(args: &Args) -> EmulatorSettings {
 
         let mut buffer: Vec<u8> = Vec::new();
         let _ = file.read_to_end(&mut buffer).unwrap();
-        let emulate_sms = path.ends_with(".sms") || args.rom.contains("[S]");
+        // Japanese SMS games may come without a header, the extension is all we have for those
+        let emulate_sms = match RomInfo::parse(&buffer).is_game_gear() {
+            Some(is_game_gear) => !is_game_gear,
+            None => path.ends_with(".sms"),
+        };
         (emulate_sms, buffer, path)
     };
 
@@ -113,17 +142,52 @@ fn get_emulator_settings(args: &Args) -> EmulatorSettings {
     } else {
         None
     };
+    let symbols = args
+        .symbols
+        .iter()
+        .map(|path| {
+            let format = SymbolFormat::from_path(path).unwrap_or_else(|| panic!("Unknown symbol file format: {}", path));
+            let mut file = File::open(path).unwrap();
+            let mut text = String::new();
+            let _ = file.read_to_string(&mut text).unwrap();
+            (format, text)
+        })
+        .collect();
+
+    // Battery backed RAM lives next to the ROM (or the zip it came in), the CPU test has nothing to save
+    let save_path = (!args.cpu_test).then(|| Path::new(&args.rom).with_extension("sav"));
+
+    let game_db = args.game_db.as_ref().map(|path| {
+        let mut file = File::open(path).unwrap();
+        let mut text = String::new();
+        let _ = file.read_to_string(&mut text).unwrap();
+        text
+    });
+
     let mut file = File::open(&args.bios).unwrap();
     let mut bios: Vec<u8> = Vec::new();
     let _ = file.read_to_end(&mut bios).unwrap();
 
+    let read_media = |path: &String| {
+        let mut file = File::open(path).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        let _ = file.read_to_end(&mut buffer).unwrap();
+        buffer
+    };
+
     EmulatorSettings {
         bios,
         cartridge,
         lua,
+        symbols,
+        game_db,
         emulate_sms: is_sms,
         cpu_test: args.cpu_test,
+        ram_fill: args.ram_fill.parse().unwrap_or_else(|e| panic!("Invalid RAM fill: {}", e)),
         cartridge_name: filename,
+        card: args.card.as_ref().map(read_media),
+        expansion: args.expansion.as_ref().map(read_media),
+        save_path,
     }
 }
 
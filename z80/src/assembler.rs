@@ -0,0 +1,706 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use crate::disassembler::Disassembler;
+use crate::metadata;
+
+// The encoding table is derived from the disassembler, so both always agree on the syntax. Every opcode is
+// decoded twice with different operand bytes, the immediates that change between both runs are operand
+// fields and are traced back to the bytes they were decoded from.
+const FILLER_A: [u8; 4] = [0x00, 0x5a, 0x3c, 0x71];
+const FILLER_B: [u8; 4] = [0x00, 0xa5, 0xc3, 0x17];
+
+// Register and condition names as printed by the `Display` impls, they can't be used as labels
+const NAMES: &[&str] = &[
+    "a", "b", "c", "d", "e", "h", "l", "f", "i", "r", "af", "bc", "de", "hl", "sp", "pc", "ix", "iy", "ixh", "ixl", "iyh", "iyl", "af'",
+    "nz", "z", "nc", "po", "pe", "p", "m",
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Slot {
+    Fixed(i64),      // Part of the opcode, e.g. the bit number of `bit` or the vector of `rst`
+    Byte(usize),     // 8-bit immediate or (ix+d)/(iy+d) displacement
+    Word(usize),     // 16-bit immediate, little endian
+    Relative(usize), // Displacement of `jr` and `djnz`
+}
+
+#[derive(Debug)]
+struct Template {
+    bytes: Vec<u8>,
+    slots: Vec<Slot>,
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Name(String),            // Register or condition, `[..]` included for indirect registers
+    Expr(String, bool),      // Expression and whether it is dereferenced
+    Indexed(String, String), // `[ix+d]`/`[iy+d]` with the signed displacement expression
+}
+
+impl Operand {
+    fn key(&self) -> String {
+        match self {
+            Operand::Name(name) => name.clone(),
+            Operand::Expr(_, false) => "{}".to_string(),
+            Operand::Expr(_, true) => "[{}]".to_string(),
+            Operand::Indexed(base, _) => format!("[{}+{{}}]", base),
+        }
+    }
+
+    fn expr(&self) -> Option<&str> {
+        match self {
+            Operand::Name(_) => None,
+            Operand::Expr(expr, _) => Some(expr),
+            Operand::Indexed(_, expr) => Some(expr),
+        }
+    }
+}
+
+/// Machine code produced by the assembler. `bytes` covers everything from the lowest to the highest
+/// address written, gaps between `org` blocks are filled with zeros.
+pub struct Program {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, i64>,
+}
+
+/// Assembles the syntax printed by `Opcode`'s `Display` impl: `ld a, [ix+#05]`, `jp nz, #1234`, ...
+///
+/// Numbers are decimal, `#ff`/`0xff` hex or `0b1010` binary, `$` is the address of the current line.
+/// Expressions support `+ - * / % & | ^ << >> ~` and parentheses, `[..]` dereferences like in the disassembly.
+/// The operand of `jr`/`djnz` is the target address like for `jp`, not the displacement the disassembler prints.
+///
+/// Directives: `label:`, `name equ expr`, `org expr`, `db expr, "string", ...` and `dw expr, ...`.
+pub struct Assembler<'a> {
+    pub source: &'a str,
+}
+
+struct Pass {
+    final_pass: bool,
+    pc: i64,
+    line_pc: i64, // Value of `$`, the address the current line starts at
+    symbols: HashMap<String, i64>,
+    defined: HashSet<String>, // Labels seen so far in this pass
+    output: Vec<(u16, u8)>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(source: &'a str) -> Assembler<'a> {
+        Assembler { source }
+    }
+
+    pub fn assemble(&self) -> Result<Program, String> {
+        let mut pass = Pass {
+            final_pass: false,
+            pc: 0,
+            line_pc: 0,
+            symbols: HashMap::new(),
+            defined: HashSet::new(),
+            output: Vec::new(),
+        };
+        self.run_pass(&mut pass)?;
+
+        pass.final_pass = true;
+        pass.pc = 0;
+        pass.defined.clear();
+        self.run_pass(&mut pass)?;
+
+        let origin = pass.output.iter().map(|(address, _)| *address).min().unwrap_or(0);
+        let end = pass.output.iter().map(|(address, _)| *address as usize + 1).max().unwrap_or(0);
+        let mut bytes = vec![0; end.saturating_sub(origin as usize)];
+        for (address, value) in pass.output {
+            bytes[(address - origin) as usize] = value;
+        }
+
+        Ok(Program {
+            origin,
+            bytes,
+            symbols: pass.symbols,
+        })
+    }
+
+    /// Assembles a single instruction as if it was located at `address`
+    pub fn assemble_instruction(text: &str, address: u16) -> Result<Vec<u8>, String> {
+        let source = format!("org {}\n{}", address, text);
+        Ok(Assembler::new(&source).assemble()?.bytes)
+    }
+
+    fn run_pass(&self, pass: &mut Pass) -> Result<(), String> {
+        for (idx, line) in self.source.lines().enumerate() {
+            self.assemble_line(pass, line)
+                .map_err(|msg| format!("line {}: {}", idx + 1, msg))?;
+        }
+
+        Ok(())
+    }
+
+    fn assemble_line(&self, pass: &mut Pass, line: &str) -> Result<(), String> {
+        let mut line = strip_comment(line).trim();
+        pass.line_pc = pass.pc;
+
+        if let Some((label, rest)) = split_label(line) {
+            self.define(pass, label, pass.pc)?;
+            line = rest.trim();
+        }
+
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, rest) = split_mnemonic(line);
+        let (directive, _) = split_mnemonic(rest);
+        if directive.eq_ignore_ascii_case("equ") {
+            let value = self.evaluate_resolved(pass, rest.trim()[3..].trim())?;
+            return self.define(pass, mnemonic, value);
+        }
+
+        match mnemonic.to_ascii_lowercase().as_str() {
+            "equ" => Err("equ needs a name".to_string()),
+            "org" => {
+                pass.pc = self.evaluate_resolved(pass, rest)?;
+                if !(0..=0xffff).contains(&pass.pc) {
+                    return Err(format!("Origin out of range: {:x}", pass.pc));
+                }
+                Ok(())
+            }
+            "db" => {
+                for item in split_operands(rest)? {
+                    if let Some(string) = item.strip_prefix('"').and_then(|item| item.strip_suffix('"')) {
+                        for c in string.chars() {
+                            self.emit(pass, c as u8)?;
+                        }
+                    } else {
+                        let value = self.evaluate(pass, &item)?.0;
+                        self.emit(pass, byte(value)?)?;
+                    }
+                }
+                Ok(())
+            }
+            "dw" => {
+                for item in split_operands(rest)? {
+                    let value = word(self.evaluate(pass, &item)?.0)?;
+                    self.emit(pass, value as u8)?;
+                    self.emit(pass, (value >> 8) as u8)?;
+                }
+                Ok(())
+            }
+            _ => self.assemble_instruction_line(pass, &mnemonic.to_ascii_lowercase(), rest),
+        }
+    }
+
+    fn assemble_instruction_line(&self, pass: &mut Pass, mnemonic: &str, rest: &str) -> Result<(), String> {
+        let operands = split_operands(rest)?
+            .iter()
+            .map(|operand| parse_operand(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (operands, candidates) = match lookup(mnemonic, &operands) {
+            Some(candidates) => (operands, candidates),
+            None => {
+                // `[ix]` is accepted as a shorthand for `[ix+0]`
+                let operands = operands
+                    .into_iter()
+                    .map(|operand| match operand {
+                        Operand::Name(name) if name == "[ix]" || name == "[iy]" => {
+                            Operand::Indexed(name[1..3].to_string(), "0".to_string())
+                        }
+                        operand => operand,
+                    })
+                    .collect::<Vec<_>>();
+                match lookup(mnemonic, &operands) {
+                    Some(candidates) => (operands, candidates),
+                    None => return Err(format!("Unknown instruction: {} {}", mnemonic, rest.trim())),
+                }
+            }
+        };
+
+        let values = operands
+            .iter()
+            .filter_map(Operand::expr)
+            .map(|expr| self.evaluate(pass, expr))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Unresolved forward references in the first pass can only be operand fields, not opcode bits
+        let template = candidates
+            .iter()
+            .find(|template| {
+                template.slots.iter().zip(&values).all(|(slot, (value, resolved))| match slot {
+                    Slot::Fixed(fixed) => !resolved || fixed == value,
+                    _ => true,
+                })
+            })
+            .ok_or_else(|| format!("Invalid operand for: {} {}", mnemonic, rest.trim()))?;
+
+        let mut bytes = template.bytes.clone();
+        let length = bytes.len() as i64;
+        for (slot, (value, _)) in template.slots.iter().zip(&values) {
+            match *slot {
+                Slot::Fixed(_) => (),
+                Slot::Byte(position) => bytes[position] = byte(*value)?,
+                Slot::Word(position) => {
+                    let value = word(*value)?;
+                    bytes[position] = value as u8;
+                    bytes[position + 1] = (value >> 8) as u8;
+                }
+                Slot::Relative(position) => {
+                    let displacement = *value - (pass.pc + length);
+                    if pass.final_pass && !(-128..=127).contains(&displacement) {
+                        return Err(format!("Relative jump out of range: {}", displacement));
+                    }
+                    bytes[position] = displacement as u8;
+                }
+            }
+        }
+
+        for value in bytes {
+            self.emit(pass, value)?;
+        }
+
+        Ok(())
+    }
+
+    fn define(&self, pass: &mut Pass, name: &str, value: i64) -> Result<(), String> {
+        if NAMES.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(format!("Reserved name can't be used as a label: {}", name));
+        }
+
+        if !pass.defined.insert(name.to_string()) {
+            return Err(format!("Duplicate label: {}", name));
+        }
+        pass.symbols.insert(name.to_string(), value);
+
+        Ok(())
+    }
+
+    fn emit(&self, pass: &mut Pass, value: u8) -> Result<(), String> {
+        if pass.pc > 0xffff {
+            return Err("Program exceeds the address space".to_string());
+        }
+
+        if pass.final_pass {
+            pass.output.push((pass.pc as u16, value));
+        }
+        pass.pc += 1;
+
+        Ok(())
+    }
+
+    // Returns the value and whether it could be resolved
+    fn evaluate(&self, pass: &Pass, expr: &str) -> Result<(i64, bool), String> {
+        let mut parser = ExprParser::new(expr, &pass.symbols, pass.line_pc);
+        let value = parser.parse()?;
+        if pass.final_pass && parser.unresolved {
+            return Err(format!("Undefined symbol in: {}", expr));
+        }
+
+        Ok((value, !parser.unresolved))
+    }
+
+    fn evaluate_resolved(&self, pass: &Pass, expr: &str) -> Result<i64, String> {
+        match self.evaluate(pass, expr)? {
+            (value, true) => Ok(value),
+            _ => Err(format!("Expression must not contain forward references: {}", expr)),
+        }
+    }
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if !(-128..=255).contains(&value) {
+        return Err(format!("Value doesn't fit into a byte: {}", value));
+    }
+    Ok(value as u8)
+}
+
+fn word(value: i64) -> Result<u16, String> {
+    if !(-32768..=65535).contains(&value) {
+        return Err(format!("Value doesn't fit into a word: {}", value));
+    }
+    Ok(value as u16)
+}
+
+fn lookup(mnemonic: &str, operands: &[Operand]) -> Option<&'static Vec<Template>> {
+    let key = instruction_key(mnemonic, operands);
+    templates().get(&key)
+}
+
+fn instruction_key(mnemonic: &str, operands: &[Operand]) -> String {
+    let operands = operands.iter().map(Operand::key).collect::<Vec<_>>();
+    format!("{} {}", mnemonic, operands.join(", ")).trim_end().to_string()
+}
+
+fn templates() -> &'static HashMap<String, Vec<Template>> {
+    static TEMPLATES: OnceLock<HashMap<String, Vec<Template>>> = OnceLock::new();
+    TEMPLATES.get_or_init(|| {
+        let mut templates: HashMap<String, Vec<Template>> = HashMap::new();
+        for info in metadata::table() {
+            if let Some((key, template)) = build_template(&info.encoding.pattern()) {
+                templates.entry(key).or_default().push(template);
+            }
+        }
+
+        // Prefer the shortest of several encodings, e.g. `ld hl, [nn]` over its ED prefixed twin
+        for candidates in templates.values_mut() {
+            candidates.sort_by_key(|template| template.bytes.len());
+        }
+
+        templates
+    })
+}
+
+fn build_template(pattern: &[Option<u8>; 4]) -> Option<(String, Template)> {
+    let fill = |filler: &[u8; 4]| {
+        let mut data = [0; 8];
+        for idx in 0..4 {
+            data[idx] = pattern[idx].unwrap_or(filler[idx]);
+        }
+        data
+    };
+    let data_a = fill(&FILLER_A);
+    let data_b = fill(&FILLER_B);
+
+    let instruction_a = Disassembler::new(&data_a).decode(0).ok()?;
+    let instruction_b = Disassembler::new(&data_b).decode(0).ok()?;
+    let length = instruction_a.length;
+
+    let (mnemonic, operands_a) = parse_instruction(&instruction_a.opcode.to_string()).ok()?;
+    let (_, operands_b) = parse_instruction(&instruction_b.opcode.to_string()).ok()?;
+    let key = instruction_key(&mnemonic, &operands_a);
+    if key != instruction_key(&mnemonic, &operands_b) {
+        return None;
+    }
+
+    let is_free = |position: usize| position < length && pattern[position].is_none();
+    let filler_word = |filler: &[u8; 4], position: usize| filler[position] as i64 | (filler[position + 1] as i64) << 8;
+
+    let mut slots = Vec::new();
+    let mut covered = [false; 4];
+    for (operand_a, operand_b) in operands_a.iter().zip(&operands_b) {
+        let (Some(expr_a), Some(expr_b)) = (operand_a.expr(), operand_b.expr()) else {
+            continue;
+        };
+        let value_a = ExprParser::new(expr_a, &HashMap::new(), 0).parse().ok()?;
+        let value_b = ExprParser::new(expr_b, &HashMap::new(), 0).parse().ok()?;
+
+        if value_a == value_b {
+            slots.push(Slot::Fixed(value_a));
+            continue;
+        }
+
+        let word_position = (1..3).find(|&position| {
+            is_free(position)
+                && is_free(position + 1)
+                && value_a == filler_word(&FILLER_A, position)
+                && value_b == filler_word(&FILLER_B, position)
+        });
+        if let Some(position) = word_position {
+            covered[position] = true;
+            covered[position + 1] = true;
+            slots.push(Slot::Word(position));
+            continue;
+        }
+
+        let position = (1..4).find(|&position| {
+            is_free(position) && value_a & 0xff == FILLER_A[position] as i64 && value_b & 0xff == FILLER_B[position] as i64
+        })?;
+        covered[position] = true;
+        slots.push(if mnemonic == "jr" || mnemonic == "djnz" {
+            Slot::Relative(position)
+        } else {
+            Slot::Byte(position)
+        });
+    }
+
+    // Every operand byte has to be accounted for, otherwise the filler would end up in the output
+    if (0..length.min(4)).any(|position| is_free(position) && !covered[position]) {
+        return None;
+    }
+
+    Some((
+        key,
+        Template {
+            bytes: data_a[..length].to_vec(),
+            slots,
+        },
+    ))
+}
+
+fn parse_instruction(text: &str) -> Result<(String, Vec<Operand>), String> {
+    let (mnemonic, rest) = split_mnemonic(text.trim());
+    let operands = split_operands(rest)?
+        .iter()
+        .map(|operand| parse_operand(operand))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((mnemonic.to_ascii_lowercase(), operands))
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    let (inner, indirect) = match text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+        Some(inner) => (inner.trim(), true),
+        None => (text, false),
+    };
+    if inner.is_empty() {
+        return Err(format!("Missing operand: {}", text));
+    }
+
+    let lower = inner.to_ascii_lowercase();
+    if NAMES.contains(&lower.as_str()) {
+        return Ok(Operand::Name(if indirect { format!("[{}]", lower) } else { lower }));
+    }
+
+    if indirect && (lower.starts_with("ix") || lower.starts_with("iy")) {
+        let displacement = inner[2..].trim_start();
+        if displacement.starts_with('+') || displacement.starts_with('-') {
+            return Ok(Operand::Indexed(lower[..2].to_string(), displacement.to_string()));
+        }
+    }
+
+    Ok(Operand::Expr(inner.to_string(), indirect))
+}
+
+fn split_mnemonic(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(idx) => (&line[..idx], line[idx..].trim()),
+        None => (line, ""),
+    }
+}
+
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let end = line.find(|c: char| !is_identifier_char(c))?;
+    if end > 0 && line[end..].starts_with(':') && is_identifier_start(line.chars().next()?) {
+        Some((&line[..end], &line[end + 1..]))
+    } else {
+        None
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (idx, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..idx],
+            // A quote after a register name is the shadow register, e.g. af'
+            (None, '\'') if previous.is_ascii_alphanumeric() => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if open == c => quote = None,
+            _ => (),
+        }
+        previous = c;
+    }
+    line
+}
+
+fn split_operands(text: &str) -> Result<Vec<String>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut previous = ' ';
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '\'') if previous.is_ascii_alphanumeric() => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if open == c => quote = None,
+            (None, '(' | '[') => depth += 1,
+            (None, ')' | ']') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                previous = c;
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+        previous = c;
+    }
+
+    if quote.is_some() || depth != 0 {
+        return Err(format!("Unbalanced quotes or brackets: {}", text));
+    }
+    operands.push(current.trim().to_string());
+
+    if operands.iter().any(String::is_empty) {
+        return Err(format!("Empty operand: {}", text));
+    }
+
+    Ok(operands)
+}
+
+fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    symbols: &'a HashMap<String, i64>,
+    pc: i64,
+    unresolved: bool,
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(expr: &str, symbols: &'a HashMap<String, i64>, pc: i64) -> ExprParser<'a> {
+        ExprParser {
+            chars: expr.chars().collect(),
+            position: 0,
+            symbols,
+            pc,
+            unresolved: false,
+        }
+    }
+
+    fn parse(&mut self) -> Result<i64, String> {
+        let value = self.parse_binary(0)?;
+        self.skip_whitespace();
+        if self.position != self.chars.len() {
+            return Err(format!("Unexpected character in expression: {}", self.chars[self.position]));
+        }
+        Ok(value)
+    }
+
+    // Precedence climbing, from the loosest to the tightest binding operators
+    fn parse_binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        'outer: loop {
+            self.skip_whitespace();
+            for operator in LEVELS[level] {
+                if self.consume(operator) {
+                    let rhs = self.parse_binary(level + 1)?;
+                    lhs = match *operator {
+                        "|" => lhs | rhs,
+                        "^" => lhs ^ rhs,
+                        "&" => lhs & rhs,
+                        "<<" => lhs.wrapping_shl(rhs as u32),
+                        ">>" => lhs.wrapping_shr(rhs as u32),
+                        "+" => lhs.wrapping_add(rhs),
+                        "-" => lhs.wrapping_sub(rhs),
+                        "*" => lhs.wrapping_mul(rhs),
+                        _ if rhs == 0 && self.unresolved => 0,
+                        _ if rhs == 0 => return Err("Division by zero".to_string()),
+                        "/" => lhs / rhs,
+                        _ => lhs % rhs,
+                    };
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        if self.consume("-") {
+            return Ok(self.parse_unary()?.wrapping_neg());
+        }
+        if self.consume("+") {
+            return self.parse_unary();
+        }
+        if self.consume("~") {
+            return Ok(!self.parse_unary()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        let c = *self.chars.get(self.position).ok_or("Unexpected end of expression")?;
+
+        if self.consume("(") {
+            let value = self.parse_binary(0)?;
+            self.skip_whitespace();
+            if !self.consume(")") {
+                return Err("Missing closing parenthesis".to_string());
+            }
+            return Ok(value);
+        }
+
+        if self.consume("$") {
+            return Ok(self.pc);
+        }
+
+        if self.consume("#") {
+            return self.parse_number(16);
+        }
+
+        if self.consume("0x") || self.consume("0X") {
+            return self.parse_number(16);
+        }
+
+        if self.consume("0b") || self.consume("0B") {
+            return self.parse_number(2);
+        }
+
+        if c.is_ascii_digit() {
+            return self.parse_number(10);
+        }
+
+        if c == '\'' {
+            let value = *self.chars.get(self.position + 1).ok_or("Unterminated character literal")?;
+            if self.chars.get(self.position + 2) != Some(&'\'') {
+                return Err("Unterminated character literal".to_string());
+            }
+            self.position += 3;
+            return Ok(value as i64);
+        }
+
+        if is_identifier_start(c) {
+            let start = self.position;
+            while self.position < self.chars.len() && is_identifier_char(self.chars[self.position]) {
+                self.position += 1;
+            }
+            let name = self.chars[start..self.position].iter().collect::<String>();
+            return match self.symbols.get(&name) {
+                Some(value) => Ok(*value),
+                None => {
+                    self.unresolved = true;
+                    Ok(0)
+                }
+            };
+        }
+
+        Err(format!("Unexpected character in expression: {}", c))
+    }
+
+    fn parse_number(&mut self, radix: u32) -> Result<i64, String> {
+        let start = self.position;
+        while self.position < self.chars.len() && self.chars[self.position].is_digit(radix) {
+            self.position += 1;
+        }
+
+        let digits = self.chars[start..self.position].iter().collect::<String>();
+        i64::from_str_radix(&digits, radix).map_err(|_| format!("Invalid number: {}", digits))
+    }
+
+    fn consume(&mut self, token: &str) -> bool {
+        let matches = token
+            .chars()
+            .enumerate()
+            .all(|(idx, c)| self.chars.get(self.position + idx) == Some(&c));
+        if matches {
+            self.position += token.len();
+        }
+        matches
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+use crate::cpu::Cpu;
+use crate::disassembler::{ByteSource, DecodeError, Disassembler, MAX_INSTRUCTION_LENGTH};
+use crate::instruction::Instruction;
+
+/// Everything the CPU is wired to: memory, the I/O space and the interrupt lines.
+///
+/// Reads take `&mut self` because memory mapped registers are allowed to react to them. Errors of the machine are
+/// passed through unchanged as `CpuError::Bus`.
+pub trait Z80Bus {
+    type Error: fmt::Debug;
+
+    fn read(&mut self, address: u16) -> Result<u8, Self::Error>;
+    fn write(&mut self, address: u16, value: u8) -> Result<(), Self::Error>;
+
+    fn read_word(&mut self, address: u16) -> Result<u16, Self::Error> {
+        let low = self.read(address)? as u16;
+        let high = self.read(address.wrapping_add(1))? as u16;
+        Ok((high << 8) | low)
+    }
+
+    fn write_word(&mut self, address: u16, value: u16) -> Result<(), Self::Error> {
+        self.write(address, value as u8)?;
+        self.write(address.wrapping_add(1), (value >> 8) as u8)
+    }
+
+    /// `in`, the upper half of the port is driven by A or B like on the real address bus
+    fn read_io(&mut self, port: u16) -> Result<u8, Self::Error>;
+    /// `out`, the upper half of the port is driven by A or B like on the real address bus
+    fn write_io(&mut self, port: u16, value: u8) -> Result<(), Self::Error>;
+
+    /// Whether a bus error aborts the instruction before the PC and the cycle count move on. Anything else (an
+    /// unassigned port, a device with nothing to return) lets execution continue past the instruction.
+    fn is_fatal(&self, _error: &Self::Error) -> bool {
+        true
+    }
+
+    /// Level of the maskable interrupt line, polled before every instruction
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+
+    /// Interrupt acknowledge cycle, returns the byte the interrupting device puts on the data bus (an instruction byte in
+    /// IM 0, read once per byte, the low byte of the vector table address in IM 2). An undriven bus floats to 0xff.
+    fn acknowledge_irq(&mut self) -> u8 {
+        0xff
+    }
+
+    /// Decodes the instruction at a CPU address, machines with a decode cache override this
+    fn decode(&mut self, address: u16) -> Result<Instruction, DecodeError> {
+        let mut fetched = Fetched {
+            address: address as usize,
+            bytes: [None; MAX_INSTRUCTION_LENGTH],
+        };
+        for (idx, byte) in fetched.bytes.iter_mut().enumerate() {
+            // Reads wrap around at 0xffff like the PC does
+            *byte = self.read(address.wrapping_add(idx as u16)).ok();
+        }
+
+        Disassembler::from_source(&fetched).decode(address as usize)
+    }
+
+    /// Called right before an instruction executes with the CPU at its first cycle, meant for tracing and for timing
+    /// the instruction's bus accesses
+    fn before_execute(&mut self, _cpu: &Cpu, _instruction: &Instruction) {}
+}
+
+// The bytes of a single instruction, kept at their CPU address so decode errors report the right offset
+struct Fetched {
+    address: usize,
+    bytes: [Option<u8>; MAX_INSTRUCTION_LENGTH],
+}
+
+impl ByteSource for Fetched {
+    fn read_byte(&self, offset: usize) -> Option<u8> {
+        *self.bytes.get(offset.checked_sub(self.address)?)?
+    }
+}
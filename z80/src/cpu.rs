@@ -1,15 +1,10 @@
-use crate::bus::{self, BankSelect, Bus};
-use crate::error::GgError;
+use crate::bus::Z80Bus;
+use crate::disassembler::{DecodeError, Disassembler};
 use crate::handlers::Handlers;
-use crate::io::Controller as _;
-use crate::psg::Psg;
-use crate::vdp::{self, Vdp};
-use crate::{joystick, sdsc};
+use crate::instruction::{Instruction, Opcode, Reg16, Reg8, Register};
 use bitflags::bitflags;
 use log::{debug, error, trace};
 use std::fmt;
-use z80::disassembler::Disassembler;
-use z80::instruction::{Instruction, Opcode, Reg16, Reg8, Register};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Registers {
@@ -37,6 +32,7 @@ pub struct Registers {
     pub sp: u16,
     pub iff1: bool,
     pub iff2: bool,
+    pub wz: u16, // MEMPTR, only observable through F3/F5 of BIT n,(HL)
 }
 
 bitflags! {
@@ -45,9 +41,9 @@ bitflags! {
         const CARRY = 0b0000_0001;
         const SUBTRACT = 0b0000_0010;
         const PARITY_OR_OVERFLOW = 0b0000_0100;
-        const F3 = 0b0000_1000; // unused
+        const F3 = 0b0000_1000;
         const HALF_CARRY = 0b0001_0000;
-        const F5 = 0b0010_0000; // unused
+        const F5 = 0b0010_0000;
         const ZERO = 0b0100_0000;
         const SIGN = 0b1000_0000;
     }
@@ -60,12 +56,12 @@ pub enum InterruptMode {
 }
 
 impl InterruptMode {
-    pub(crate) fn from(value: u8) -> Result<InterruptMode, GgError> {
+    pub(crate) fn from<E>(value: u8) -> Result<InterruptMode, CpuError<E>> {
         match value {
             0 => Ok(InterruptMode::IM0),
             1 => Ok(InterruptMode::IM1),
             2 => Ok(InterruptMode::IM2),
-            _ => Err(GgError::InvalidInterruptMode { mode: value }),
+            _ => Err(CpuError::InvalidInterruptMode { mode: value }),
         }
     }
 
@@ -78,6 +74,42 @@ impl InterruptMode {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpuError<E> {
+    Bus(E),
+    Decoder(DecodeError),
+    OpcodeNotImplemented { opcode: Opcode },
+    InvalidOpcodeImplementation { instruction: Opcode },
+    InvalidInterruptMode { mode: u8 },
+    // Not failures, a conditional branch fell through or a repeat instruction finished (the PC moves on)
+    JumpNotTaken,
+    RepeatNotFulfilled,
+}
+
+impl<E> From<E> for CpuError<E> {
+    fn from(error: E) -> CpuError<E> {
+        CpuError::Bus(error)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for CpuError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Bus(error) => write!(f, "{}", error),
+            CpuError::Decoder(error) => write!(f, "Decoder error: {}", error),
+            CpuError::OpcodeNotImplemented { opcode } => write!(f, "Opcode not implemented {}", opcode),
+            CpuError::InvalidOpcodeImplementation { instruction } => {
+                write!(f, "Missing operand implementation for instruction: {}", instruction)
+            }
+            CpuError::InvalidInterruptMode { mode } => write!(f, "Invalid interrupt mode: {}", mode),
+            CpuError::JumpNotTaken => write!(f, "Jump not taken"),
+            CpuError::RepeatNotFulfilled => write!(f, "Repeat not fulfilled"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for CpuError<E> {}
+
 pub struct Cpu {
     pub registers: Registers,
     pub interrupt_mode: InterruptMode,
@@ -89,10 +121,19 @@ pub struct Cpu {
     // Directly after an EI or DI instruction, interrupts aren’t accepted. They’re accepted again after
     // the instruction after the EI (RET in the following example).
     pub ignore_next_irq: bool,
+    // Total T-states executed since power on
+    pub cycles: usize,
+    pub halted: bool,
+    // The NMI input is edge-triggered, only a transition from released to asserted requests an NMI
+    nmi_line: bool,
+    nmi_pending: bool,
+    // Flags computed by the previous instruction, or 0 if it didn't compute any. SCF and CCF take F3/F5 from
+    // (Q ^ F) | A, so they differ depending on whether the instruction before them touched the flags.
+    pub(crate) q: u8,
 }
 
 impl Cpu {
-    pub(crate) fn new() -> Cpu {
+    pub fn new() -> Cpu {
         Cpu {
             registers: Registers {
                 a: 0,
@@ -119,44 +160,43 @@ impl Cpu {
                 sp: 0,
                 iff1: false,
                 iff2: false,
+                wz: 0,
             },
             interrupt_mode: InterruptMode::IM0,
             ignore_next_irq: false,
+            cycles: 0,
+            halted: false,
+            nmi_line: false,
+            nmi_pending: false,
+            q: 0,
         }
     }
 
-    pub(crate) fn decode_at_pc(&self, bus: &mut Bus) -> Result<Instruction, String> {
-        let mut data = vec![];
-
-        // The next bytes *should* not be out of bounds, but we'll wrap around just in case
-        // I introduced this for certain cases in JSMoo's z80 unit tests
-        // Important: For the unit tests we must run this with disabled banking behavior as that can trip up
-        // the ROM address calculations
-
-        for idx in 0..4 {
-            let pc = self.registers.pc.wrapping_add(idx);
-            data.push(bus.read(pc).unwrap());
-        }
-
-        let disasm = Disassembler::new(&data);
-        disasm.decode(0)
+    pub fn decode_at_pc<B: Z80Bus>(&self, bus: &mut B) -> Result<Instruction, DecodeError> {
+        bus.decode(self.registers.pc)
     }
 
-    pub(crate) fn tick(&mut self, bus: &mut Bus, vdp: &mut Vdp, psg: &mut Psg) -> Result<Instruction, GgError> {
+    pub fn tick<B: Z80Bus>(&mut self, bus: &mut B) -> Result<Instruction, CpuError<B::Error>> {
         let mut instruction = match self.decode_at_pc(bus) {
             Ok(instruction) => instruction,
-            Err(msg) => return Err(GgError::DecoderError { msg }),
+            Err(error) => return Err(CpuError::Decoder(error)),
         };
 
-        if vdp.vblank_irq_pending() || vdp.scanline_irq_pending() {
-            if self.registers.iff1 && !self.ignore_next_irq {
-                self.trigger_irq(bus, &instruction)?;
-
-                instruction = match self.decode_at_pc(bus) {
-                    Ok(instruction) => instruction,
-                    Err(msg) => return Err(GgError::DecoderError { msg }),
-                };
-            }
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.trigger_nmi(bus, &instruction)?;
+
+            instruction = match self.decode_at_pc(bus) {
+                Ok(instruction) => instruction,
+                Err(error) => return Err(CpuError::Decoder(error)),
+            };
+        } else if bus.irq_pending() && self.registers.iff1 && !self.ignore_next_irq {
+            self.trigger_irq(bus, &instruction)?;
+
+            instruction = match self.decode_at_pc(bus) {
+                Ok(instruction) => instruction,
+                Err(error) => return Err(CpuError::Decoder(error)),
+            };
         }
 
         // Directly after an EI or DI instruction, interrupts aren’t accepted. They’re accepted again after
@@ -165,128 +205,139 @@ impl Cpu {
             self.ignore_next_irq = false;
         }
 
-        let prefix = if self.registers.pc < 0xc000 { "rom" } else { "ram" };
-        let real_pc_addr = match bus.translate_address_to_real(self.registers.pc) {
-            Ok(rom_addr) => rom_addr,
-            Err(_) => self.registers.pc as usize, // This can happen if we execute code in RAM (example: end of BIOS)
-        };
-        trace!(
-            "[{}:{:04x}->{:08x}] {:<20} [{:?}]",
-            prefix,
-            self.registers.pc,
-            real_pc_addr,
-            format!("{}", instruction.opcode),
-            self
-        );
-        trace!(
-            "Bank 1: {:02x}  Bank 2: {:02x}  Bank 3: {:02x}  SRAM: {}  V: {}  H: {}  VBlank: {}",
-            bus.fetch_bank(BankSelect::Bank0),
-            bus.fetch_bank(BankSelect::Bank1),
-            bus.fetch_bank(BankSelect::Bank2),
-            bus.is_sram_bank_active(),
-            vdp.v,
-            vdp.h,
-            vdp.vblank_irq_pending()
-        );
-
-        let mut handlers = Handlers::new(self, bus, vdp, psg);
+        // A halted CPU keeps executing internal NOPs until an interrupt arrives, PC stays on the HALT
+        if self.halted {
+            self.cycles += 4;
+            self.increment_r();
+            return Ok(instruction);
+        }
+
+        bus.before_execute(self, &instruction);
+
+        // Every (ix+d)/(iy+d) access leaves the effective address in MEMPTR
+        if let Some(address) = self.indexed_address(bus)? {
+            self.registers.wz = address;
+        }
+
+        self.execute(bus, &instruction).map(|_| instruction)
+    }
+
+    /// Runs the handler of an instruction and moves PC past it unless it jumped or repeats
+    fn execute<B: Z80Bus>(&mut self, bus: &mut B, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let mut handlers = Handlers::new(self, bus);
         let result = match instruction.opcode {
-            Opcode::Jump(_, _, _) => handlers.jump(&instruction),
-            Opcode::DisableInterrupts(_) => handlers.set_interrupt_state(false, &instruction),
-            Opcode::EnableInterrupts(_) => handlers.set_interrupt_state(true, &instruction),
-            Opcode::Load(_, _, _) => handlers.load(&instruction),
-            Opcode::LoadIncrementRepeat(_) => handlers.load_increment_repeat(&instruction),
-            Opcode::Out(_, _, _) => handlers.out(&instruction),
-            Opcode::In(_, _, _) => handlers.in_(&instruction),
-            Opcode::Compare(_, _) => handlers.compare(&instruction),
-            Opcode::JumpRelative(_, _, _) => handlers.jump_relative(&instruction),
-            Opcode::Call(_, _, _) => handlers.call(&instruction),
-            Opcode::Return(_, _) => handlers.return_(&instruction),
-            Opcode::ReturnFromIrq(_) => handlers.return_from_irq(&instruction),
-            Opcode::OutIncrementRepeat(_) => handlers.out_increment_repeat(&instruction),
-            Opcode::OutDecrementRepeat(_) => handlers.out_decrement_repeat(&instruction),
-            Opcode::Or(_, _) => handlers.or(&instruction),
-            Opcode::Push(_, _) => handlers.push(&instruction),
-            Opcode::Pop(_, _) => handlers.pop(&instruction),
-            Opcode::Increment(_, _) => handlers.increment(&instruction),
-            Opcode::Decrement(_, _) => handlers.decrement(&instruction),
-            Opcode::ResetBit(_, _, _) => handlers.reset_bit(&instruction),
-            Opcode::DecrementAndJumpRelative(_, _) => handlers.decrement_and_jump_relative(&instruction),
-            Opcode::Xor(_, _) => handlers.xor(&instruction),
-            Opcode::OutIncrement(_) => handlers.outi(&instruction),
-            Opcode::OutDecrement(_) => handlers.outd(&instruction),
-            Opcode::Restart(_, _) => handlers.restart(&instruction),
-            Opcode::SetInterruptMode(_, _) => handlers.set_interrupt_mode(&instruction),
-            Opcode::Subtract(_, _) => handlers.subtract(&instruction),
-            Opcode::Add(_, _, _) => handlers.add(&instruction),
-            Opcode::And(_, _) => handlers.and(&instruction),
-            Opcode::SubtractCarry(_, _, _) => handlers.subtract_carry(&instruction),
-            Opcode::RotateRightCarryAccumulator(_) => handlers.rotate_right_carry_accumulator(&instruction),
-            Opcode::RotateRightAccumulator(_) => handlers.rotate_right_accumulator(&instruction),
-            Opcode::RotateLeftCarryAccumulator(_) => handlers.rotate_left_carry_accumulator(&instruction),
-            Opcode::RotateLeftAccumulator(_) => handlers.rotate_left_accumulator(&instruction),
-            Opcode::RotateRightCarry(_, _) => handlers.rotate_right_carry(&instruction),
-            Opcode::RotateRight(_, _) => handlers.rotate_right(&instruction),
-            Opcode::RotateLeftCarry(_, _) => handlers.rotate_left_carry(&instruction),
-            Opcode::RotateLeft(_, _) => handlers.rotate_left(&instruction),
-            Opcode::RotateLeftCarryStore(_, _, _) => handlers.rotate_left_carry_store(&instruction),
-            Opcode::RotateRightCarryStore(_, _, _) => handlers.rotate_right_carry_store(&instruction),
-            Opcode::RotateLeftStore(_, _, _) => handlers.rotate_left_store(&instruction),
-            Opcode::RotateRightStore(_, _, _) => handlers.rotate_right_store(&instruction),
-            Opcode::ShiftRightArithmeticStore(_, _, _) => handlers.shift_right_arithmetic_store(&instruction),
-            Opcode::ShiftRightLogicalStore(_, _, _) => handlers.shift_right_logical_store(&instruction),
-            Opcode::ShiftLeftArithmeticStore(_, _, _) => handlers.shift_left_arithmetic_store(&instruction),
-            Opcode::ShiftLeftLogicalStore(_, _, _) => handlers.shift_left_logical_store(&instruction),
-            Opcode::SetBitStore(_, _, _, _) => handlers.set_bit_store(&instruction),
-            Opcode::ResetBitStore(_, _, _, _) => handlers.reset_bit_store(&instruction),
-            Opcode::Complement(_) => handlers.complement(&instruction),
-            Opcode::SetBit(_, _, _) => handlers.set_bit(&instruction),
-            Opcode::Halt(_) => return Err(GgError::CpuHalted),
-            Opcode::Exchange(_, _, _) => handlers.exchange(&instruction),
-            Opcode::ExchangeAll(_) => handlers.exchange_all(&instruction),
-            Opcode::TestBit(_, _, _) => handlers.test_bit(&instruction),
-            Opcode::LoadDecrementRepeat(_) => handlers.load_decrement_repeat(&instruction),
-            Opcode::LoadDecrement(_) => handlers.load_decrement(&instruction),
-            Opcode::InvertCarry(_) => handlers.invert_carry(&instruction),
-            Opcode::AddCarry(_, _, _) => handlers.add_carry(&instruction),
-            Opcode::SetCarryFlag(_) => handlers.set_carry_flag(&instruction),
-            Opcode::DecimalAdjustAccumulator(_) => handlers.decimal_adjust_accumulator(&instruction),
-            Opcode::ShiftRightArithmetic(_, _) => handlers.shift_right_arithmetic(&instruction),
-            Opcode::ShiftRightLogical(_, _) => handlers.shift_right_logical(&instruction),
-            Opcode::ShiftLeftArithmetic(_, _) => handlers.shift_left_arithmetic(&instruction),
-            Opcode::ShiftLeftLogical(_, _) => handlers.shift_left_logical(&instruction),
-            Opcode::Negate(_) => handlers.negate(&instruction),
-            Opcode::LoadIncrement(_) => handlers.load_increment(&instruction),
-            Opcode::CompareIncrementRepeat(_) => handlers.compare_increment_repeat(&instruction),
-            Opcode::CompareDecrementRepeat(_) => handlers.compare_decrement_repeat(&instruction),
-            Opcode::CompareIncrement(_) => handlers.compare_increment(&instruction),
-            Opcode::CompareDecrement(_) => handlers.compare_decrement(&instruction),
-            Opcode::InIncrement(_) => handlers.ini(&instruction),
-            Opcode::RotateLeftDecimal(_) => handlers.rotate_left_decimal(&instruction),
-            Opcode::RotateRightDecimal(_) => handlers.rotate_right_decimal(&instruction),
+            Opcode::Jump(_, _, _) => handlers.jump(instruction),
+            Opcode::DisableInterrupts(_) => handlers.set_interrupt_state(false, instruction),
+            Opcode::EnableInterrupts(_) => handlers.set_interrupt_state(true, instruction),
+            Opcode::Load(_, _, _) => handlers.load(instruction),
+            Opcode::LoadIncrementRepeat(_) => handlers.load_increment_repeat(instruction),
+            Opcode::Out(_, _, _) => handlers.out(instruction),
+            Opcode::In(_, _, _) => handlers.in_(instruction),
+            Opcode::Compare(_, _) => handlers.compare(instruction),
+            Opcode::JumpRelative(_, _, _) => handlers.jump_relative(instruction),
+            Opcode::Call(_, _, _) => handlers.call(instruction),
+            Opcode::Return(_, _) => handlers.return_(instruction),
+            Opcode::ReturnFromIrq(_) => handlers.return_from_irq(instruction),
+            Opcode::ReturnFromNmi(_) => handlers.return_from_nmi(instruction),
+            Opcode::OutIncrementRepeat(_) => handlers.out_increment_repeat(instruction),
+            Opcode::OutDecrementRepeat(_) => handlers.out_decrement_repeat(instruction),
+            Opcode::Or(_, _) => handlers.or(instruction),
+            Opcode::Push(_, _) => handlers.push(instruction),
+            Opcode::Pop(_, _) => handlers.pop(instruction),
+            Opcode::Increment(_, _) => handlers.increment(instruction),
+            Opcode::Decrement(_, _) => handlers.decrement(instruction),
+            Opcode::ResetBit(_, _, _) => handlers.reset_bit(instruction),
+            Opcode::DecrementAndJumpRelative(_, _) => handlers.decrement_and_jump_relative(instruction),
+            Opcode::Xor(_, _) => handlers.xor(instruction),
+            Opcode::OutIncrement(_) => handlers.outi(instruction),
+            Opcode::OutDecrement(_) => handlers.outd(instruction),
+            Opcode::Restart(_, _) => handlers.restart(instruction),
+            Opcode::SetInterruptMode(_, _) => handlers.set_interrupt_mode(instruction),
+            Opcode::Subtract(_, _) => handlers.subtract(instruction),
+            Opcode::Add(_, _, _) => handlers.add(instruction),
+            Opcode::And(_, _) => handlers.and(instruction),
+            Opcode::SubtractCarry(_, _, _) => handlers.subtract_carry(instruction),
+            Opcode::RotateRightCarryAccumulator(_) => handlers.rotate_right_carry_accumulator(instruction),
+            Opcode::RotateRightAccumulator(_) => handlers.rotate_right_accumulator(instruction),
+            Opcode::RotateLeftCarryAccumulator(_) => handlers.rotate_left_carry_accumulator(instruction),
+            Opcode::RotateLeftAccumulator(_) => handlers.rotate_left_accumulator(instruction),
+            Opcode::RotateRightCarry(_, _) => handlers.rotate_right_carry(instruction),
+            Opcode::RotateRight(_, _) => handlers.rotate_right(instruction),
+            Opcode::RotateLeftCarry(_, _) => handlers.rotate_left_carry(instruction),
+            Opcode::RotateLeft(_, _) => handlers.rotate_left(instruction),
+            Opcode::RotateLeftCarryStore(_, _, _) => handlers.rotate_left_carry_store(instruction),
+            Opcode::RotateRightCarryStore(_, _, _) => handlers.rotate_right_carry_store(instruction),
+            Opcode::RotateLeftStore(_, _, _) => handlers.rotate_left_store(instruction),
+            Opcode::RotateRightStore(_, _, _) => handlers.rotate_right_store(instruction),
+            Opcode::ShiftRightArithmeticStore(_, _, _) => handlers.shift_right_arithmetic_store(instruction),
+            Opcode::ShiftRightLogicalStore(_, _, _) => handlers.shift_right_logical_store(instruction),
+            Opcode::ShiftLeftArithmeticStore(_, _, _) => handlers.shift_left_arithmetic_store(instruction),
+            Opcode::ShiftLeftLogicalStore(_, _, _) => handlers.shift_left_logical_store(instruction),
+            Opcode::SetBitStore(_, _, _, _) => handlers.set_bit_store(instruction),
+            Opcode::ResetBitStore(_, _, _, _) => handlers.reset_bit_store(instruction),
+            Opcode::Complement(_) => handlers.complement(instruction),
+            Opcode::SetBit(_, _, _) => handlers.set_bit(instruction),
+            Opcode::Halt(_) => {
+                self.halted = true;
+                self.cycles += instruction.cycles().not_taken;
+                self.increment_r();
+                return Ok(());
+            }
+            Opcode::Exchange(_, _, _) => handlers.exchange(instruction),
+            Opcode::ExchangeAll(_) => handlers.exchange_all(instruction),
+            Opcode::TestBit(_, _, _) => handlers.test_bit(instruction),
+            Opcode::LoadDecrementRepeat(_) => handlers.load_decrement_repeat(instruction),
+            Opcode::LoadDecrement(_) => handlers.load_decrement(instruction),
+            Opcode::InvertCarry(_) => handlers.invert_carry(instruction),
+            Opcode::AddCarry(_, _, _) => handlers.add_carry(instruction),
+            Opcode::SetCarryFlag(_) => handlers.set_carry_flag(instruction),
+            Opcode::DecimalAdjustAccumulator(_) => handlers.decimal_adjust_accumulator(instruction),
+            Opcode::ShiftRightArithmetic(_, _) => handlers.shift_right_arithmetic(instruction),
+            Opcode::ShiftRightLogical(_, _) => handlers.shift_right_logical(instruction),
+            Opcode::ShiftLeftArithmetic(_, _) => handlers.shift_left_arithmetic(instruction),
+            Opcode::ShiftLeftLogical(_, _) => handlers.shift_left_logical(instruction),
+            Opcode::Negate(_) => handlers.negate(instruction),
+            Opcode::LoadIncrement(_) => handlers.load_increment(instruction),
+            Opcode::CompareIncrementRepeat(_) => handlers.compare_increment_repeat(instruction),
+            Opcode::CompareDecrementRepeat(_) => handlers.compare_decrement_repeat(instruction),
+            Opcode::CompareIncrement(_) => handlers.compare_increment(instruction),
+            Opcode::CompareDecrement(_) => handlers.compare_decrement(instruction),
+            Opcode::InIncrement(_) => handlers.ini(instruction),
+            Opcode::InDecrement(_) => handlers.ind(instruction),
+            Opcode::InIncrementRepeat(_) => handlers.in_increment_repeat(instruction),
+            Opcode::InDecrementRepeat(_) => handlers.in_decrement_repeat(instruction),
+            Opcode::RotateLeftDecimal(_) => handlers.rotate_left_decimal(instruction),
+            Opcode::RotateRightDecimal(_) => handlers.rotate_right_decimal(instruction),
             Opcode::NoOperation(_) => Ok(()),
             _ => {
                 error!("Handler missing for instruction: {}\n{}", instruction.opcode, self);
-                return Err(GgError::OpcodeNotImplemented {
+                return Err(CpuError::OpcodeNotImplemented {
                     opcode: instruction.opcode,
                 });
             }
         };
 
-        match result {
-            Err(GgError::BusRequestOutOfBounds { address }) => {
-                error!("Bus request out of bounds: {:04x}\n{}", address, self);
-                return Err(GgError::BusRequestOutOfBounds { address });
+        if let Err(CpuError::Bus(error)) = &result {
+            if bus.is_fatal(error) {
+                error!("Bus request failed: {:?}\n{}", error, self);
+                return result;
             }
-            _ => (),
         }
 
+        // Loading F through pop af or ex af, af' doesn't go through the ALU and clears Q like any other load
+        let computes_flags = instruction.info().is_some_and(|info| info.flags_written != 0);
+        self.q = match instruction.opcode {
+            Opcode::Pop(_, _) | Opcode::Exchange(_, _, _) => 0,
+            _ if computes_flags => self.registers.f.bits(),
+            _ => 0,
+        };
+
         let skip = match instruction.opcode {
             Opcode::Call(_, _, _) => result.is_ok(),
             Opcode::Jump(_, _, _) => result.is_ok(),
             Opcode::Return(_, _) => result.is_ok(),
             Opcode::ReturnFromIrq(_) => result.is_ok(),
-            Opcode::ReturnFromNmi(_) => result.is_ok(), // todo: sure?
+            Opcode::ReturnFromNmi(_) => result.is_ok(),
             Opcode::Restart(_, _) => result.is_ok(),
             // Do NOT increase PC if the repeat instruction's condition is not met
             Opcode::LoadIncrementRepeat(_) => result.is_err(),
@@ -300,82 +351,137 @@ impl Cpu {
             _ => false,
         };
 
+        // A skipped PC increment means the branch was taken or the repeat instruction loops
+        self.cycles += if skip {
+            instruction.cycles().taken
+        } else {
+            instruction.cycles().not_taken
+        };
+
         if !skip {
             self.increment_r();
             self.registers.pc = self.registers.pc.wrapping_add(instruction.length as u16);
         }
 
-        if result.is_ok() {
-            Ok(instruction)
-        } else {
-            Err(result.unwrap_err())
-        }
+        result
     }
 
     pub(crate) fn increment_r(&mut self) {
         self.registers.r = self.registers.r & 0b1000_0000 | (((self.registers.r & 0b0111_1111) + 1) & 0b0111_1111);
     }
 
-    pub(crate) fn trigger_irq(&mut self, bus: &mut Bus, current_instruction: &Instruction) -> Result<(), GgError> {
+    pub fn trigger_irq<B: Z80Bus>(&mut self, bus: &mut B, current_instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         debug!("IRQ triggered");
-
-        let vector = match self.interrupt_mode {
-            InterruptMode::IM0 => 0x0038,
-            InterruptMode::IM1 => 0x0038,
-            InterruptMode::IM2 => 0x0038,
-        };
+        let data_bus = bus.acknowledge_irq();
 
         self.registers.iff1 = false;
         self.registers.iff2 = false;
 
-        match current_instruction.opcode {
-            Opcode::Halt(length) => self.push_stack(bus, self.registers.pc + length as u16)?,
-            _ => self.push_stack(bus, self.registers.pc)?,
-        }
+        // The acknowledge cycle adds 2 wait states to the instruction, RST and the implied call of IM 1/2 take 11
+        let (vector, cycles) = match self.interrupt_mode {
+            InterruptMode::IM0 => {
+                // Every further byte of the instruction comes from another acknowledge cycle
+                let mut data = vec![data_bus];
+                let instruction = loop {
+                    match Disassembler::new(&data).decode(0) {
+                        Ok(instruction) => break instruction,
+                        Err(DecodeError::Truncated { .. }) => data.push(bus.acknowledge_irq()),
+                        Err(error) => return Err(CpuError::Decoder(error)),
+                    }
+                };
+
+                // PC doesn't move while fetching from the data bus, so the instruction behaves as if it sat right
+                // before the interrupted one: RST and CALL push the interrupted address, anything else falls through
+                let return_address = self.interrupt_return_address(current_instruction);
+                self.halted = false;
+                self.registers.pc = return_address.wrapping_sub(instruction.length as u16);
+                self.cycles += 2;
+                return self.execute(bus, &instruction);
+            }
+            InterruptMode::IM1 => (0x0038, 13),
+            InterruptMode::IM2 => {
+                let table_address = (self.registers.i as u16) << 8 | data_bus as u16;
+                (bus.read_word(table_address)?, 19)
+            }
+        };
 
+        self.push_interrupt_return_address(bus, current_instruction)?;
         self.registers.pc = vector;
+        self.registers.wz = vector;
+        self.cycles += cycles;
 
         Ok(())
     }
 
-    pub(crate) fn write_io(&mut self, port: u8, value: u8, vdp: &mut Vdp, bus: &mut Bus, psg: &mut Psg) -> Result<(), GgError> {
-        match port {
-            0x00..=0x06 => bus.write_io(port, value)?,
-            vdp::IO_DATA_CONTROL_START..=vdp::IO_DATA_CONTROL_END => vdp.write_io(port, value)?,
-            sdsc::CONTROL_PORT | sdsc::DATA_PORT => bus.write_io(port, value)?,
-            bus::MEMORY_CONTROL_PORT => bus.write_io(port, value)?,
-            0x40..=0x7f => psg.write_io(port, value)?,
-            _ => {
-                error!("Unassigned port (write): {:02x}", port);
-                return Err(GgError::IoControllerInvalidPort);
-            }
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
         }
 
+        self.nmi_line = asserted;
+    }
+
+    pub fn trigger_nmi<B: Z80Bus>(&mut self, bus: &mut B, current_instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        debug!("NMI triggered");
+
+        // IFF2 keeps the previous state so that RETN can restore it
+        self.registers.iff2 = self.registers.iff1;
+        self.registers.iff1 = false;
+
+        self.push_interrupt_return_address(bus, current_instruction)?;
+        self.registers.pc = 0x0066;
+        self.registers.wz = 0x0066;
+        self.cycles += 11;
+
         Ok(())
     }
 
-    pub(crate) fn read_io(&self, port: u8, vdp: &mut Vdp, bus: &mut Bus, _psg: &mut Psg) -> Result<u8, GgError> {
-        match port {
-            0x00..=0x06 => bus.read_io(port),
-            vdp::IO_DATA_CONTROL_START..=vdp::IO_DATA_CONTROL_END => vdp.read_io(port),
-            0x40..=0x7f => vdp.read_io(port),
-            joystick::JOYSTICK_AB_PORT | joystick::JOYSTICK_B_MISC_PORT => bus.read_io(port),
-            _ => {
-                error!("Unassigned port (read): {:02x}", port);
-                Err(GgError::IoControllerInvalidPort)
-            }
+    fn indexed_address<B: Z80Bus>(&self, bus: &mut B) -> Result<Option<u16>, CpuError<B::Error>> {
+        let base = match bus.read(self.registers.pc)? {
+            0xdd => self.registers.ix,
+            0xfd => self.registers.iy,
+            _ => return Ok(None),
+        };
+
+        let opcode = bus.read(self.registers.pc.wrapping_add(1))?;
+        let displaced = match opcode {
+            0xcb | 0x34 | 0x35 | 0x36 => true,
+            0x76 => false,
+            0x40..=0xbf => opcode & 0b0000_0111 == 6 || (0x70..0x78).contains(&opcode),
+            _ => false,
+        };
+        if !displaced {
+            return Ok(None);
+        }
+
+        let displacement = bus.read(self.registers.pc.wrapping_add(2))? as i8;
+        Ok(Some(base.wrapping_add_signed(displacement.into())))
+    }
+
+    fn push_interrupt_return_address<B: Z80Bus>(
+        &mut self, bus: &mut B, current_instruction: &Instruction,
+    ) -> Result<(), CpuError<B::Error>> {
+        let return_address = self.interrupt_return_address(current_instruction);
+        self.halted = false;
+        self.push_stack(bus, return_address)
+    }
+
+    // Leaving the halted state returns to the instruction after the HALT
+    fn interrupt_return_address(&self, current_instruction: &Instruction) -> u16 {
+        match current_instruction.opcode {
+            Opcode::Halt(length) if self.halted => self.registers.pc.wrapping_add(length as u16),
+            _ => self.registers.pc,
         }
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn set_reg(&mut self, register: Register, value: u16) {
+    pub fn set_reg(&mut self, register: Register, value: u16) {
         match register {
             Register::Reg8(reg) => self.set_register_u8(reg, value as u8),
             Register::Reg16(reg) => self.set_register_u16(reg, value),
         }
     }
 
-    pub(crate) fn get_register_u16(&self, register: Reg16) -> u16 {
+    pub fn get_register_u16(&self, register: Reg16) -> u16 {
         let get_offset = |offset: Option<i8>| match offset {
             Some(offset) => offset as i16,
             None => 0,
@@ -397,7 +503,7 @@ impl Cpu {
         }
     }
 
-    pub(crate) fn get_register_u8(&self, register: Reg8) -> u8 {
+    pub fn get_register_u8(&self, register: Reg8) -> u8 {
         let high = |value: u16| (value >> 8) as u8;
         let low = |value: u16| value as u8;
 
@@ -427,7 +533,7 @@ impl Cpu {
         }
     }
 
-    pub(crate) fn set_register_u16(&mut self, register: Reg16, value: u16) {
+    pub fn set_register_u16(&mut self, register: Reg16, value: u16) {
         match register {
             Reg16::AF => {
                 self.registers.a = (value >> 8) as u8;
@@ -468,7 +574,7 @@ impl Cpu {
         }
     }
 
-    pub(crate) fn set_register_u8(&mut self, register: Reg8, value: u8) {
+    pub fn set_register_u8(&mut self, register: Reg8, value: u8) {
         match register {
             Reg8::A => self.registers.a = value,
             Reg8::B => self.registers.b = value,
@@ -495,13 +601,13 @@ impl Cpu {
         }
     }
 
-    pub(crate) fn push_stack(&mut self, bus: &mut Bus, value: u16) -> Result<(), GgError> {
+    pub(crate) fn push_stack<B: Z80Bus>(&mut self, bus: &mut B, value: u16) -> Result<(), CpuError<B::Error>> {
         self.registers.sp -= 2;
         bus.write_word(self.registers.sp, value)?;
         Ok(())
     }
 
-    pub(crate) fn pop_stack(&mut self, bus: &mut Bus) -> Result<u16, GgError> {
+    pub(crate) fn pop_stack<B: Z80Bus>(&mut self, bus: &mut B) -> Result<u16, CpuError<B::Error>> {
         let value = bus.read_word(self.registers.sp)?;
         trace!("Popped {:04x} from stack at {:04x}", value, self.registers.sp);
         self.registers.sp += 2;
@@ -509,6 +615,12 @@ impl Cpu {
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
+
 impl fmt::Display for Flags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -526,9 +638,9 @@ impl fmt::Display for Flags {
 
 impl fmt::Display for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
+        writeln!(
             f,
-            "AF: {:04x}  BC: {:04x}  DE: {:04x}  HL: {:04x}  PC: {:04x}  SP: {:04x}\n",
+            "AF: {:04x}  BC: {:04x}  DE: {:04x}  HL: {:04x}  PC: {:04x}  SP: {:04x}",
             self.get_register_u16(Reg16::AF),
             self.get_register_u16(Reg16::BC),
             self.get_register_u16(Reg16::DE),
@@ -536,7 +648,7 @@ impl fmt::Display for Cpu {
             self.registers.pc,
             self.registers.sp
         )?;
-        write!(f, "{}\n", self.registers.f)
+        writeln!(f, "{}", self.registers.f)
     }
 }
 
@@ -544,7 +656,7 @@ impl fmt::Debug for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "AF: {:04x}  BC: {:04x}  DE: {:04x}  HL: {:04x}  AF': {:04x}  BC': {:04x}  DE': {:04x}  HL': {:04x}  IX: {:04x}  IY: {:04x}  PC: {:04x}  SP: {:04x}  R: {:02x}  I: {:02x}  Flags: {:08b} ({})",
+            "AF: {:04x}  BC: {:04x}  DE: {:04x}  HL: {:04x}  AF': {:04x}  BC': {:04x}  DE': {:04x}  HL': {:04x}  IX: {:04x}  IY: {:04x}  PC: {:04x}  SP: {:04x}  R: {:02x}  I: {:02x}  WZ: {:04x}  Flags: {:08b} ({})",
             self.get_register_u16(Reg16::AF),
             self.get_register_u16(Reg16::BC),
             self.get_register_u16(Reg16::DE),
@@ -559,6 +671,7 @@ impl fmt::Debug for Cpu {
             self.registers.sp,
             self.registers.r,
             self.registers.i,
+            self.registers.wz,
             self.registers.f.bits(),
             self.registers.f
         )
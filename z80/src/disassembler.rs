@@ -1,55 +1,113 @@
+use std::fmt;
+
 use crate::instruction::{Condition, Immediate, Instruction, Opcode, Operand, Reg16, Reg8, Register};
+use crate::metadata::Encoding;
+
+// Longest encoding the decoder looks at, prefixes and displacement included
+pub const MAX_INSTRUCTION_LENGTH: usize = 4;
+
+/// Anything instructions can be decoded from: a slice, a ROM image or the CPU's view of memory
+pub trait ByteSource {
+    /// Byte at `offset`, None past the end of the source
+    fn read_byte(&self, offset: usize) -> Option<u8>;
+}
+
+impl ByteSource for [u8] {
+    fn read_byte(&self, offset: usize) -> Option<u8> {
+        self.get(offset).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Truncated {
+        offset: usize,
+    },
+    UnknownOpcode {
+        offset: usize,
+        bytes: [u8; MAX_INSTRUCTION_LENGTH],
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { offset } => write!(f, "Truncated instruction at {:04x}", offset),
+            DecodeError::UnknownOpcode { offset, bytes } => write!(
+                f,
+                "Unknown instruction at {:04x}: {:02x} {:02x} {:02x} {:02x}",
+                offset, bytes[0], bytes[1], bytes[2], bytes[3]
+            ),
+        }
+    }
+}
 
-pub struct Disassembler<'a> {
-    pub data: &'a [u8],
+impl std::error::Error for DecodeError {}
+
+pub struct Disassembler<'a, S: ByteSource + ?Sized = [u8]> {
+    pub source: &'a S,
 }
 
 impl<'a> Disassembler<'a> {
-    pub fn new(data: &'a [u8]) -> Disassembler {
-        Disassembler { data }
+    pub fn new(data: &'a [u8]) -> Disassembler<'a> {
+        Disassembler { source: data }
+    }
+}
+
+impl<'a, S: ByteSource + ?Sized> Disassembler<'a, S> {
+    pub fn from_source(source: &'a S) -> Disassembler<'a, S> {
+        Disassembler { source }
     }
 
-    pub fn decode(&self, offset: usize) -> Result<Instruction, String> {
-        let sequence = (
-            Some(self.data[offset]),
-            Some(self.data[offset + 1]),
-            Some(self.data[offset + 2]),
-            Some(self.data[offset + 3]),
-        );
-        let opcode = self.decode_opcode(offset, sequence);
+    pub fn decode(&self, offset: usize) -> Result<Instruction, DecodeError> {
+        let sequence = [0, 1, 2, 3].map(|idx| self.source.read_byte(offset + idx));
+        let bytes = sequence.map(|value| value.unwrap_or(0));
+        let available = sequence.iter().take_while(|value| value.is_some()).count();
+
+        let opcode = self.decode_opcode(bytes, (sequence[0], sequence[1], sequence[2], sequence[3]));
+        if opcode == Opcode::Unknown(0) {
+            // A missing byte can keep the full encoding from matching
+            return Err(if available < MAX_INSTRUCTION_LENGTH {
+                DecodeError::Truncated { offset }
+            } else {
+                DecodeError::UnknownOpcode { offset, bytes }
+            });
+        }
 
-        if opcode != Opcode::Unknown(0) {
-            let length = self.calc_length(opcode);
-            Ok(Instruction { opcode, length, offset })
-        } else {
-            Err(format!("Unknown instruction {:x}", self.data[offset]))
+        let length = self.calc_length(opcode);
+        if length > available {
+            return Err(DecodeError::Truncated { offset });
         }
+
+        Ok(Instruction {
+            opcode,
+            length,
+            offset,
+            encoding: Encoding::from_bytes(bytes),
+        })
     }
 
-    pub fn disassemble(&self) -> Vec<Instruction> {
+    /// Decodes until the end of the source, stops at the first byte sequence that isn't an instruction
+    pub fn disassemble(&self) -> Result<Vec<Instruction>, DecodeError> {
         let mut instructions = Vec::new();
         let mut offset = 0;
 
-        while offset < self.data.len() {
-            match self.decode(offset) {
-                Ok(instruction) => {
-                    offset += instruction.length;
-                    instructions.push(instruction);
-                }
-                Err(msg) => panic!("{}", msg),
-            }
+        while self.source.read_byte(offset).is_some() {
+            let instruction = self.decode(offset)?;
+            offset += instruction.length;
+            instructions.push(instruction);
         }
 
-        instructions
+        Ok(instructions)
     }
 
-    fn decode_opcode(&self, offset: usize, sequence: (Option<u8>, Option<u8>, Option<u8>, Option<u8>)) -> Opcode {
+    fn decode_opcode(&self, bytes: [u8; MAX_INSTRUCTION_LENGTH], sequence: (Option<u8>, Option<u8>, Option<u8>, Option<u8>)) -> Opcode {
         match sequence {
             // NO PREFIX
             (Some(0x00), _, _, _) => Opcode::NoOperation(1),
             (Some(0x01), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::BC), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0x02), _, _, _) => Opcode::Load(
@@ -62,7 +120,7 @@ impl<'a> Disassembler<'a> {
             (Some(0x05), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::B), false), 1),
             (Some(0x06), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::B), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x07), _, _, _) => Opcode::RotateLeftCarryAccumulator(1),
@@ -86,14 +144,14 @@ impl<'a> Disassembler<'a> {
             (Some(0x0d), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::C), false), 1),
             (Some(0x0e), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::C), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x0f), _, _, _) => Opcode::RotateRightCarryAccumulator(1),
-            (Some(0x10), _, _, _) => Opcode::DecrementAndJumpRelative(Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x10), _, _, _) => Opcode::DecrementAndJumpRelative(Immediate::S8(bytes[1] as i8), 2),
             (Some(0x11), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::DE), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0x12), _, _, _) => Opcode::Load(
@@ -106,11 +164,11 @@ impl<'a> Disassembler<'a> {
             (Some(0x15), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::D), false), 1),
             (Some(0x16), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::D), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x17), _, _, _) => Opcode::RotateLeftAccumulator(1),
-            (Some(0x18), _, _, _) => Opcode::JumpRelative(Condition::None, Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x18), _, _, _) => Opcode::JumpRelative(Condition::None, Immediate::S8(bytes[1] as i8), 2),
             (Some(0x19), _, _, _) => Opcode::Add(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 Operand::Register(Register::Reg16(Reg16::DE), false),
@@ -126,18 +184,18 @@ impl<'a> Disassembler<'a> {
             (Some(0x1d), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::E), false), 1),
             (Some(0x1e), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::E), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x1f), _, _, _) => Opcode::RotateRightAccumulator(1),
-            (Some(0x20), _, _, _) => Opcode::JumpRelative(Condition::NotZero, Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x20), _, _, _) => Opcode::JumpRelative(Condition::NotZero, Immediate::S8(bytes[1] as i8), 2),
             (Some(0x21), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0x22), _, _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), true),
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 3,
             ),
@@ -146,11 +204,11 @@ impl<'a> Disassembler<'a> {
             (Some(0x25), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::H), false), 1),
             (Some(0x26), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::H), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x27), _, _, _) => Opcode::DecimalAdjustAccumulator(1),
-            (Some(0x28), _, _, _) => Opcode::JumpRelative(Condition::Zero, Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x28), _, _, _) => Opcode::JumpRelative(Condition::Zero, Immediate::S8(bytes[1] as i8), 2),
             (Some(0x29), _, _, _) => Opcode::Add(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 Operand::Register(Register::Reg16(Reg16::HL), false),
@@ -158,7 +216,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0x2a), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), true),
                 3,
             ),
             (Some(0x2b), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::HL), false), 1),
@@ -166,18 +224,18 @@ impl<'a> Disassembler<'a> {
             (Some(0x2d), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::L), false), 1),
             (Some(0x2e), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::L), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x2f), _, _, _) => Opcode::Complement(1),
-            (Some(0x30), _, _, _) => Opcode::JumpRelative(Condition::NotCarry, Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x30), _, _, _) => Opcode::JumpRelative(Condition::NotCarry, Immediate::S8(bytes[1] as i8), 2),
             (Some(0x31), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::SP), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0x32), _, _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), true),
                 Operand::Register(Register::Reg8(Reg8::A), false),
                 3,
             ),
@@ -186,11 +244,11 @@ impl<'a> Disassembler<'a> {
             (Some(0x35), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::HL), true), 1),
             (Some(0x36), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::HL), true),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x37), _, _, _) => Opcode::SetCarryFlag(1),
-            (Some(0x38), _, _, _) => Opcode::JumpRelative(Condition::Carry, Immediate::S8(self.data[offset + 1] as i8), 2),
+            (Some(0x38), _, _, _) => Opcode::JumpRelative(Condition::Carry, Immediate::S8(bytes[1] as i8), 2),
             (Some(0x39), _, _, _) => Opcode::Add(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 Operand::Register(Register::Reg16(Reg16::SP), false),
@@ -198,7 +256,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0x3a), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), true),
                 3,
             ),
             (Some(0x3b), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::SP), false), 1),
@@ -206,7 +264,7 @@ impl<'a> Disassembler<'a> {
             (Some(0x3d), _, _, _) => Opcode::Decrement(Operand::Register(Register::Reg8(Reg8::A), false), 1),
             (Some(0x3e), _, _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0x3f), _, _, _) => Opcode::InvertCarry(1),
@@ -688,48 +746,24 @@ impl<'a> Disassembler<'a> {
             (Some(0xbf), _, _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::A), false), 1),
             (Some(0xc0), _, _, _) => Opcode::Return(Condition::NotZero, 1),
             (Some(0xc1), _, _, _) => Opcode::Pop(Register::Reg16(Reg16::BC), 1),
-            (Some(0xc2), _, _, _) => Opcode::Jump(
-                Condition::NotZero,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
-            (Some(0xc3), _, _, _) => Opcode::Jump(
-                Condition::None,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
-            (Some(0xc4), _, _, _) => Opcode::Call(
-                Condition::NotZero,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xc2), _, _, _) => Opcode::Jump(Condition::NotZero, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
+            (Some(0xc3), _, _, _) => Opcode::Jump(Condition::None, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
+            (Some(0xc4), _, _, _) => Opcode::Call(Condition::NotZero, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xc5), _, _, _) => Opcode::Push(Register::Reg16(Reg16::BC), 1),
             (Some(0xc6), _, _, _) => Opcode::Add(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0xc7), _, _, _) => Opcode::Restart(Immediate::U8(0x00), 1),
             (Some(0xc8), _, _, _) => Opcode::Return(Condition::Zero, 1),
             (Some(0xc9), _, _, _) => Opcode::Return(Condition::None, 1),
-            (Some(0xca), _, _, _) => Opcode::Jump(
-                Condition::Zero,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
-            (Some(0xcc), _, _, _) => Opcode::Call(
-                Condition::Zero,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
-            (Some(0xcd), _, _, _) => Opcode::Call(
-                Condition::None,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xca), _, _, _) => Opcode::Jump(Condition::Zero, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
+            (Some(0xcc), _, _, _) => Opcode::Call(Condition::Zero, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
+            (Some(0xcd), _, _, _) => Opcode::Call(Condition::None, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xce), _, _, _) => Opcode::AddCarry(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0xcf), _, _, _) => Opcode::Restart(Immediate::U8(0x08), 1),
@@ -737,42 +771,34 @@ impl<'a> Disassembler<'a> {
             (Some(0xd1), _, _, _) => Opcode::Pop(Register::Reg16(Reg16::DE), 1),
             (Some(0xd2), _, _, _) => Opcode::Jump(
                 Condition::NotCarry,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0xd3), _, _, _) => Opcode::Out(
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), true), // todo: is true correct?
+                Operand::Immediate(Immediate::U8(bytes[1]), true), // todo: is true correct?
                 Operand::Register(Register::Reg8(Reg8::A), false),
                 2,
             ),
             (Some(0xd4), _, _, _) => Opcode::Call(
                 Condition::NotCarry,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0xd5), _, _, _) => Opcode::Push(Register::Reg16(Reg16::DE), 1),
-            (Some(0xd6), _, _, _) => Opcode::Subtract(Operand::Immediate(Immediate::U8(self.data[offset + 1]), false), 2),
+            (Some(0xd6), _, _, _) => Opcode::Subtract(Operand::Immediate(Immediate::U8(bytes[1]), false), 2),
             (Some(0xd7), _, _, _) => Opcode::Restart(Immediate::U8(0x10), 1),
             (Some(0xd8), _, _, _) => Opcode::Return(Condition::Carry, 1),
             (Some(0xd9), _, _, _) => Opcode::ExchangeAll(1),
-            (Some(0xda), _, _, _) => Opcode::Jump(
-                Condition::Carry,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xda), _, _, _) => Opcode::Jump(Condition::Carry, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xdb), _, _, _) => Opcode::In(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), true), // todo: is true correct?
+                Operand::Immediate(Immediate::U8(bytes[1]), true), // todo: is true correct?
                 2,
             ),
-            (Some(0xdc), _, _, _) => Opcode::Call(
-                Condition::Carry,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xdc), _, _, _) => Opcode::Call(Condition::Carry, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xde), _, _, _) => Opcode::SubtractCarry(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Immediate(Immediate::U8(self.data[offset + 1]), false),
+                Operand::Immediate(Immediate::U8(bytes[1]), false),
                 2,
             ),
             (Some(0xdf), _, _, _) => Opcode::Restart(Immediate::U8(0x18), 1),
@@ -780,7 +806,7 @@ impl<'a> Disassembler<'a> {
             (Some(0xe1), _, _, _) => Opcode::Pop(Register::Reg16(Reg16::HL), 1),
             (Some(0xe2), _, _, _) => Opcode::Jump(
                 Condition::NotParityOrOverflow,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0xe3), _, _, _) => Opcode::Exchange(
@@ -790,17 +816,17 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xe4), _, _, _) => Opcode::Call(
                 Condition::NotParityOrOverflow,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0xe5), _, _, _) => Opcode::Push(Register::Reg16(Reg16::HL), 1),
-            (Some(0xe6), _, _, _) => Opcode::And(Operand::Immediate(Immediate::U8(self.data[offset + 1]), false), 2),
+            (Some(0xe6), _, _, _) => Opcode::And(Operand::Immediate(Immediate::U8(bytes[1]), false), 2),
             (Some(0xe7), _, _, _) => Opcode::Restart(Immediate::U8(0x20), 1),
             (Some(0xe8), _, _, _) => Opcode::Return(Condition::ParityOrOverflow, 1),
             (Some(0xe9), _, _, _) => Opcode::Jump(Condition::None, Operand::Register(Register::Reg16(Reg16::HL), true), 1),
             (Some(0xea), _, _, _) => Opcode::Jump(
                 Condition::ParityOrOverflow,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
             (Some(0xeb), _, _, _) => Opcode::Exchange(
@@ -810,26 +836,18 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xec), _, _, _) => Opcode::Call(
                 Condition::ParityOrOverflow,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false),
                 3,
             ),
-            (Some(0xee), _, _, _) => Opcode::Xor(Operand::Immediate(Immediate::U8(self.data[offset + 1]), false), 2),
+            (Some(0xee), _, _, _) => Opcode::Xor(Operand::Immediate(Immediate::U8(bytes[1]), false), 2),
             (Some(0xef), _, _, _) => Opcode::Restart(Immediate::U8(0x28), 1),
             (Some(0xf0), _, _, _) => Opcode::Return(Condition::NotSign, 1),
             (Some(0xf1), _, _, _) => Opcode::Pop(Register::Reg16(Reg16::AF), 1),
-            (Some(0xf2), _, _, _) => Opcode::Jump(
-                Condition::NotSign,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xf2), _, _, _) => Opcode::Jump(Condition::NotSign, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xf3), _, _, _) => Opcode::DisableInterrupts(1),
-            (Some(0xf4), _, _, _) => Opcode::Call(
-                Condition::NotSign,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xf4), _, _, _) => Opcode::Call(Condition::NotSign, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xf5), _, _, _) => Opcode::Push(Register::Reg16(Reg16::AF), 1),
-            (Some(0xf6), _, _, _) => Opcode::Or(Operand::Immediate(Immediate::U8(self.data[offset + 1]), false), 2),
+            (Some(0xf6), _, _, _) => Opcode::Or(Operand::Immediate(Immediate::U8(bytes[1]), false), 2),
             (Some(0xf7), _, _, _) => Opcode::Restart(Immediate::U8(0x30), 1),
             (Some(0xf8), _, _, _) => Opcode::Return(Condition::Sign, 1),
             (Some(0xf9), _, _, _) => Opcode::Load(
@@ -837,18 +855,10 @@ impl<'a> Disassembler<'a> {
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 1,
             ),
-            (Some(0xfa), _, _, _) => Opcode::Jump(
-                Condition::Sign,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
+            (Some(0xfa), _, _, _) => Opcode::Jump(Condition::Sign, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
             (Some(0xfb), _, _, _) => Opcode::EnableInterrupts(1),
-            (Some(0xfc), _, _, _) => Opcode::Call(
-                Condition::Sign,
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 1)), false),
-                3,
-            ),
-            (Some(0xfe), _, _, _) => Opcode::Compare(Operand::Immediate(Immediate::U8(self.data[offset + 1]), false), 2),
+            (Some(0xfc), _, _, _) => Opcode::Call(Condition::Sign, Operand::Immediate(Immediate::U16(read_u16(bytes, 1)), false), 3),
+            (Some(0xfe), _, _, _) => Opcode::Compare(Operand::Immediate(Immediate::U8(bytes[1]), false), 2),
             (Some(0xff), _, _, _) => Opcode::Restart(Immediate::U8(0x38), 1),
 
             // 0xCB PREFIX
@@ -1126,7 +1136,7 @@ impl<'a> Disassembler<'a> {
                 2,
             ),
             (Some(0xed), Some(0x43), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::BC), false),
                 4,
             ),
@@ -1155,7 +1165,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xed), Some(0x4b), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::BC), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xed), Some(0x4d), _, _) => Opcode::ReturnFromIrq(2),
@@ -1180,7 +1190,7 @@ impl<'a> Disassembler<'a> {
                 2,
             ),
             (Some(0xed), Some(0x53), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::DE), false),
                 4,
             ),
@@ -1207,7 +1217,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xed), Some(0x5b), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::DE), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xed), Some(0x5e), _, _) => Opcode::SetInterruptMode(Immediate::U8(2), 2),
@@ -1232,7 +1242,7 @@ impl<'a> Disassembler<'a> {
                 2,
             ),
             (Some(0xed), Some(0x63), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::HL), false),
                 4,
             ),
@@ -1249,7 +1259,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xed), Some(0x6b), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::HL), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xed), Some(0x6f), _, _) => Opcode::RotateLeftDecimal(2),
@@ -1259,7 +1269,7 @@ impl<'a> Disassembler<'a> {
                 2,
             ),
             (Some(0xed), Some(0x73), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::SP), false),
                 4,
             ),
@@ -1280,7 +1290,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xed), Some(0x7b), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::SP), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xed), Some(0xa0), _, _) => Opcode::LoadIncrement(2),
@@ -1341,11 +1351,11 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x21), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::IX(None)), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), false),
                 4,
             ),
             (Some(0xdd), Some(0x22), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::IX(None)), false),
                 4,
             ),
@@ -1364,7 +1374,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x2a), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::IX(None)), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xdd), Some(0x2b), _, _) => Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::IX(None)), false), 2),
@@ -1382,8 +1392,8 @@ impl<'a> Disassembler<'a> {
                 Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::IX(Some(offset as i8))), true), 3)
             }
             (Some(0xdd), Some(0x36), _, _) => Opcode::Load(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                Operand::Immediate(Immediate::U8(self.data[offset + 3]), false),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
+                Operand::Immediate(Immediate::U8(bytes[3]), false),
                 4,
             ),
             (Some(0xdd), Some(0x39), _, _) => Opcode::Add(
@@ -1670,7 +1680,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x76), _, _) => Opcode::Halt(2),
             (Some(0xdd), Some(0x77), _, _) => Opcode::Load(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
                 Operand::Register(Register::Reg8(Reg8::A), false),
                 3,
             ),
@@ -1706,7 +1716,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x7e), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
                 3,
             ),
             (Some(0xdd), Some(0x7f), _, _) => Opcode::Load(
@@ -1746,7 +1756,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x86), _, _) => Opcode::Add(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
                 3,
             ),
             (Some(0xdd), Some(0x87), _, _) => Opcode::Add(
@@ -1786,7 +1796,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x8e), _, _) => Opcode::AddCarry(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
                 3,
             ),
             (Some(0xdd), Some(0x8f), _, _) => Opcode::AddCarry(
@@ -1800,10 +1810,9 @@ impl<'a> Disassembler<'a> {
             (Some(0xdd), Some(0x93), _, _) => Opcode::Subtract(Operand::Register(Register::Reg8(Reg8::E), false), 2),
             (Some(0xdd), Some(0x94), _, _) => Opcode::Subtract(Operand::Register(Register::Reg8(Reg8::IXH), false), 2),
             (Some(0xdd), Some(0x95), _, _) => Opcode::Subtract(Operand::Register(Register::Reg8(Reg8::IXL), false), 2),
-            (Some(0xdd), Some(0x96), _, _) => Opcode::Subtract(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                3,
-            ),
+            (Some(0xdd), Some(0x96), _, _) => {
+                Opcode::Subtract(Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true), 3)
+            }
             (Some(0xdd), Some(0x97), _, _) => Opcode::Subtract(Operand::Register(Register::Reg8(Reg8::A), false), 2),
             (Some(0xdd), Some(0x98), _, _) => Opcode::SubtractCarry(
                 Operand::Register(Register::Reg8(Reg8::A), false),
@@ -1837,7 +1846,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xdd), Some(0x9e), _, _) => Opcode::SubtractCarry(
                 Operand::Register(Register::Reg8(Reg8::A), false),
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
+                Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true),
                 3,
             ),
             (Some(0xdd), Some(0x9f), _, _) => Opcode::SubtractCarry(
@@ -1851,10 +1860,7 @@ impl<'a> Disassembler<'a> {
             (Some(0xdd), Some(0xa3), _, _) => Opcode::And(Operand::Register(Register::Reg8(Reg8::E), false), 2),
             (Some(0xdd), Some(0xa4), _, _) => Opcode::And(Operand::Register(Register::Reg8(Reg8::IXH), false), 2),
             (Some(0xdd), Some(0xa5), _, _) => Opcode::And(Operand::Register(Register::Reg8(Reg8::IXL), false), 2),
-            (Some(0xdd), Some(0xa6), _, _) => Opcode::And(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                3,
-            ),
+            (Some(0xdd), Some(0xa6), _, _) => Opcode::And(Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true), 3),
             (Some(0xdd), Some(0xa7), _, _) => Opcode::And(Operand::Register(Register::Reg8(Reg8::A), false), 2),
             (Some(0xdd), Some(0xa8), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::B), false), 2),
             (Some(0xdd), Some(0xa9), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::C), false), 2),
@@ -1862,10 +1868,7 @@ impl<'a> Disassembler<'a> {
             (Some(0xdd), Some(0xab), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::E), false), 2),
             (Some(0xdd), Some(0xac), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::IXH), false), 2),
             (Some(0xdd), Some(0xad), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::IXL), false), 2),
-            (Some(0xdd), Some(0xae), _, _) => Opcode::Xor(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                3,
-            ),
+            (Some(0xdd), Some(0xae), _, _) => Opcode::Xor(Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true), 3),
             (Some(0xdd), Some(0xaf), _, _) => Opcode::Xor(Operand::Register(Register::Reg8(Reg8::A), false), 2),
             (Some(0xdd), Some(0xb0), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::B), false), 2),
             (Some(0xdd), Some(0xb1), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::C), false), 2),
@@ -1873,10 +1876,7 @@ impl<'a> Disassembler<'a> {
             (Some(0xdd), Some(0xb3), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::E), false), 2),
             (Some(0xdd), Some(0xb4), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::IXH), false), 2),
             (Some(0xdd), Some(0xb5), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::IXL), false), 2),
-            (Some(0xdd), Some(0xb6), _, _) => Opcode::Or(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                3,
-            ),
+            (Some(0xdd), Some(0xb6), _, _) => Opcode::Or(Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true), 3),
             (Some(0xdd), Some(0xb7), _, _) => Opcode::Or(Operand::Register(Register::Reg8(Reg8::A), false), 2),
             (Some(0xdd), Some(0xb8), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::B), false), 2),
             (Some(0xdd), Some(0xb9), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::C), false), 2),
@@ -1884,10 +1884,7 @@ impl<'a> Disassembler<'a> {
             (Some(0xdd), Some(0xbb), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::E), false), 2),
             (Some(0xdd), Some(0xbc), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::IXH), false), 2),
             (Some(0xdd), Some(0xbd), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::IXL), false), 2),
-            (Some(0xdd), Some(0xbe), _, _) => Opcode::Compare(
-                Operand::Register(Register::Reg16(Reg16::IX(Some(self.data[offset + 2] as i8))), true),
-                3,
-            ),
+            (Some(0xdd), Some(0xbe), _, _) => Opcode::Compare(Operand::Register(Register::Reg16(Reg16::IX(Some(bytes[2] as i8))), true), 3),
             (Some(0xdd), Some(0xbf), _, _) => Opcode::Compare(Operand::Register(Register::Reg8(Reg8::A), false), 2),
             (Some(0xdd), Some(0xe1), _, _) => Opcode::Pop(Register::Reg16(Reg16::IX(None)), 2),
             (Some(0xdd), Some(0xe3), _, _) => Opcode::Exchange(
@@ -3322,11 +3319,11 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xfd), Some(0x21), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::IY(None)), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), false),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), false),
                 4,
             ),
             (Some(0xfd), Some(0x22), _, _) => Opcode::Load(
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 Operand::Register(Register::Reg16(Reg16::IY(None)), false),
                 4,
             ),
@@ -3345,7 +3342,7 @@ impl<'a> Disassembler<'a> {
             ),
             (Some(0xfd), Some(0x2a), _, _) => Opcode::Load(
                 Operand::Register(Register::Reg16(Reg16::IY(None)), false),
-                Operand::Immediate(Immediate::U16(self.read_u16(offset + 2)), true),
+                Operand::Immediate(Immediate::U16(read_u16(bytes, 2)), true),
                 4,
             ),
             (Some(0xfd), Some(0x2b), _, _) => Opcode::Decrement(Operand::Register(Register::Reg16(Reg16::IY(None)), false), 2),
@@ -5261,13 +5258,6 @@ impl<'a> Disassembler<'a> {
         }
     }
 
-    fn read_u16(&self, offset: usize) -> u16 {
-        let low = self.data[offset] as u16;
-        let high = self.data[offset + 1] as u16;
-
-        (high << 8) | low
-    }
-
     fn calc_length(&self, opcode: Opcode) -> usize {
         match opcode {
             Opcode::DisableInterrupts(length) => length,
@@ -5352,3 +5342,10 @@ impl<'a> Disassembler<'a> {
         }
     }
 }
+
+fn read_u16(bytes: [u8; MAX_INSTRUCTION_LENGTH], offset: usize) -> u16 {
+    let low = bytes[offset] as u16;
+    let high = bytes[offset + 1] as u16;
+
+    (high << 8) | low
+}
@@ -1,26 +1,19 @@
-use crate::bus::Bus;
-use crate::cpu::InterruptMode;
-use crate::error::GgError;
+use crate::bus::Z80Bus;
+use crate::cpu::{Cpu, CpuError, Flags, InterruptMode};
+use crate::instruction::{Condition, Immediate, Instruction, Opcode, Operand, Reg16, Reg8, Register};
 
-use crate::cpu::{Cpu, Flags};
-use crate::psg::Psg;
-use crate::vdp::Vdp;
-use z80::instruction::{Condition, Immediate, Instruction, Opcode, Operand, Reg16, Reg8, Register};
-
-pub(crate) struct Handlers<'a> {
+pub(crate) struct Handlers<'a, B: Z80Bus> {
     cpu: &'a mut Cpu,
-    bus: &'a mut Bus,
-    vdp: &'a mut Vdp,
-    psg: &'a mut Psg,
+    bus: &'a mut B,
 }
 
 #[allow(unused_variables)]
-impl<'a> Handlers<'a> {
-    pub(crate) fn new(cpu: &'a mut Cpu, bus: &'a mut Bus, vdp: &'a mut Vdp, psg: &'a mut Psg) -> Handlers<'a> {
-        Handlers { cpu, bus, vdp, psg }
+impl<'a, B: Z80Bus> Handlers<'a, B> {
+    pub(crate) fn new(cpu: &'a mut Cpu, bus: &'a mut B) -> Handlers<'a, B> {
+        Handlers { cpu, bus }
     }
 
-    pub(crate) fn load(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn load(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Load(
                 Operand::Register(Register::Reg16(dst_register), dst_deref),
@@ -30,6 +23,7 @@ impl<'a> Handlers<'a> {
                 let mut imm = src_imm;
                 if src_deref {
                     imm = self.bus.read_word(src_imm)?;
+                    self.cpu.registers.wz = src_imm.wrapping_add(1);
                 }
 
                 if dst_deref {
@@ -65,6 +59,11 @@ impl<'a> Handlers<'a> {
                 let dst = self.cpu.get_register_u16(dst_register);
                 let src = self.cpu.get_register_u8(src_register);
                 self.bus.write(dst, src)?;
+
+                if dst_register == Reg16::BC || dst_register == Reg16::DE {
+                    self.cpu.registers.wz = (src as u16) << 8 | (dst.wrapping_add(1) & 0x00ff);
+                }
+
                 Ok(())
             }
             Opcode::Load(Operand::Register(Register::Reg8(dst_register), false), Operand::Immediate(Immediate::U8(src_imm), false), _) => {
@@ -74,6 +73,7 @@ impl<'a> Handlers<'a> {
             Opcode::Load(Operand::Register(Register::Reg8(dst_register), false), Operand::Immediate(Immediate::U16(src_imm), true), _) => {
                 let src = self.bus.read(src_imm)?;
                 self.cpu.set_register_u8(dst_register, src);
+                self.cpu.registers.wz = src_imm.wrapping_add(1);
                 Ok(())
             }
             Opcode::Load(
@@ -81,17 +81,25 @@ impl<'a> Handlers<'a> {
                 Operand::Register(Register::Reg16(src_register), true),
                 _,
             ) => {
-                let src = self.cpu.get_register_u16(src_register);
-                let src = self.bus.read(src)?;
+                let address = self.cpu.get_register_u16(src_register);
+                let src = self.bus.read(address)?;
                 self.cpu.set_register_u8(dst_register, src);
+
+                if src_register == Reg16::BC || src_register == Reg16::DE {
+                    self.cpu.registers.wz = address.wrapping_add(1);
+                }
+
                 Ok(())
             }
             Opcode::Load(Operand::Immediate(Immediate::U16(dst_imm), true), Operand::Register(Register::Reg16(src_register), false), _) => {
                 self.bus.write_word(dst_imm, self.cpu.get_register_u16(src_register))?;
+                self.cpu.registers.wz = dst_imm.wrapping_add(1);
                 Ok(())
             }
             Opcode::Load(Operand::Immediate(Immediate::U16(dst_imm), true), Operand::Register(Register::Reg8(src_register), false), _) => {
-                self.bus.write(dst_imm, self.cpu.get_register_u8(src_register))?;
+                let src = self.cpu.get_register_u8(src_register);
+                self.bus.write(dst_imm, src)?;
+                self.cpu.registers.wz = (src as u16) << 8 | (dst_imm.wrapping_add(1) & 0x00ff);
                 Ok(())
             }
             Opcode::Load(Operand::Register(Register::Reg8(dst_reg), false), Operand::Register(Register::Reg8(src_reg), false), _) => {
@@ -104,6 +112,7 @@ impl<'a> Handlers<'a> {
                     self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.cpu.registers.iff2);
                     self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                     self.cpu.registers.f.set(Flags::SUBTRACT, false);
+                    self.set_undocumented_flags(src);
                 }
 
                 Ok(())
@@ -118,19 +127,21 @@ impl<'a> Handlers<'a> {
                 self.bus.write_word(self.cpu.get_register_u16(dst_reg), src)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn jump(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn jump(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let dst = match instruction.opcode {
             Opcode::Jump(condition, Operand::Immediate(Immediate::U16(imm), deref), _) => {
+                // MEMPTR is loaded with the target regardless of the condition
+                self.cpu.registers.wz = imm;
                 if self.check_cpu_flag(condition) {
                     Ok(if deref { self.bus.read_word(imm)? } else { imm })
                 } else {
-                    Err(GgError::JumpNotTaken)
+                    Err(CpuError::JumpNotTaken)
                 }
             }
             Opcode::Jump(condition, Operand::Register(Register::Reg16(reg), true), _) => {
@@ -138,11 +149,11 @@ impl<'a> Handlers<'a> {
                 if self.check_cpu_flag(condition) {
                     Ok(dst)
                 } else {
-                    Err(GgError::JumpNotTaken)
+                    Err(CpuError::JumpNotTaken)
                 }
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -152,7 +163,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn set_interrupt_state(&mut self, enabled: bool, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn set_interrupt_state(&mut self, enabled: bool, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         self.cpu.ignore_next_irq = enabled;
         self.cpu.registers.iff1 = enabled;
         self.cpu.registers.iff2 = enabled;
@@ -160,7 +171,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn load_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn load_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -180,15 +191,16 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, bc.wrapping_sub(1) > 0);
+        self.set_block_transfer_flags(src.wrapping_add(self.cpu.get_register_u8(Reg8::A)));
 
         if self.cpu.get_register_u16(Reg16::BC) == 0 {
             Ok(())
         } else {
-            Err(GgError::RepeatNotFulfilled)
+            self.repeat_block()
         }
     }
 
-    pub(crate) fn load_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn load_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -206,15 +218,16 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, bc.wrapping_sub(1) > 0);
+        self.set_block_transfer_flags(src.wrapping_add(self.cpu.get_register_u8(Reg8::A)));
 
         if self.cpu.get_register_u16(Reg16::BC) == 0 {
             Ok(())
         } else {
-            Err(GgError::RepeatNotFulfilled)
+            self.repeat_block()
         }
     }
 
-    pub(crate) fn load_decrement(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn load_decrement(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -232,11 +245,12 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, bc.wrapping_sub(1) > 0);
+        self.set_block_transfer_flags(src.wrapping_add(self.cpu.get_register_u8(Reg8::A)));
 
         Ok(())
     }
 
-    pub(crate) fn load_increment(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn load_increment(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -254,11 +268,12 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, bc.wrapping_sub(1) > 0);
+        self.set_block_transfer_flags(src.wrapping_add(self.cpu.get_register_u8(Reg8::A)));
 
         Ok(())
     }
 
-    pub(crate) fn negate(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn negate(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let a = self.cpu.get_register_u8(Reg8::A);
         let result = a.wrapping_neg();
         self.cpu.set_register_u8(Reg8::A, result);
@@ -266,6 +281,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, result > 0);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, result == 128);
         self.cpu.registers.f.set(Flags::SUBTRACT, true);
         self.cpu
@@ -276,64 +292,86 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn out(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn out(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (port, value) = match instruction.opcode {
             Opcode::Out(Operand::Immediate(Immediate::U8(dst_port), true), Operand::Register(Register::Reg8(src_reg), false), _) => {
-                (dst_port, self.cpu.get_register_u8(src_reg))
+                let src = self.cpu.get_register_u8(src_reg);
+                self.cpu.registers.wz = (src as u16) << 8 | dst_port.wrapping_add(1) as u16;
+                ((src as u16) << 8 | dst_port as u16, src)
             }
-            Opcode::Out(Operand::Register(Register::Reg8(dst_port), true), Operand::Register(Register::Reg8(src_reg), false), _) => {
-                let dst = self.cpu.get_register_u8(dst_port);
+            Opcode::Out(Operand::Register(Register::Reg8(_), true), Operand::Register(Register::Reg8(src_reg), false), _) => {
                 let src = self.cpu.get_register_u8(src_reg);
-                (dst, src)
+                let bc = self.cpu.get_register_u16(Reg16::BC);
+                self.cpu.registers.wz = bc.wrapping_add(1);
+                (bc, src)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
         };
 
-        self.cpu.write_io(port, value, self.vdp, self.bus, self.psg)?;
+        self.bus.write_io(port, value)?;
 
         Ok(())
     }
 
-    pub(crate) fn in_(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn in_(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::In(Operand::Register(Register::Reg8(dst_reg), false), Operand::Immediate(Immediate::U8(src_port), true), _) => {
-                let imm = self.cpu.read_io(src_port, self.vdp, self.bus, self.psg)?;
+                let a = self.cpu.get_register_u8(Reg8::A);
+                let port = (a as u16) << 8 | src_port as u16;
+                self.cpu.registers.wz = port.wrapping_add(1);
+
+                let imm = self.bus.read_io(port)?;
                 self.cpu.set_register_u8(dst_reg, imm);
                 Ok(())
             }
-            Opcode::In(Operand::Register(Register::Reg8(dst_reg), false), Operand::Register(Register::Reg8(src_reg), true), _) => {
-                let src = self.cpu.get_register_u8(src_reg);
-                let imm = self.cpu.read_io(src, self.vdp, self.bus, self.psg)?;
+            Opcode::In(Operand::Register(Register::Reg8(dst_reg), false), Operand::Register(Register::Reg8(_), true), _) => {
+                let bc = self.cpu.get_register_u16(Reg16::BC);
+                self.cpu.registers.wz = bc.wrapping_add(1);
+
+                let imm = self.bus.read_io(bc)?;
                 self.cpu.set_register_u8(dst_reg, imm);
+
+                // Unlike IN A,(n), IN r,(C) updates the flags
+                self.cpu.registers.f.set(Flags::ZERO, imm == 0);
+                self.cpu.registers.f.set(Flags::SIGN, imm & 0b1000_0000 != 0);
+                self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(imm));
+                self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+                self.cpu.registers.f.set(Flags::SUBTRACT, false);
+                self.set_undocumented_flags(imm);
+
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn ini(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let c = self.cpu.get_register_u8(Reg8::C);
-        let hl = self.cpu.get_register_u16(Reg16::HL);
-        let imm = self.cpu.read_io(c, self.vdp, self.bus, self.psg)?;
-        self.bus.write(hl, imm)?;
+    pub(crate) fn ini(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        self.block_in(true)?;
+        Ok(())
+    }
 
-        self.cpu.set_register_u16(Reg16::HL, hl.wrapping_add(1));
-        let b = self.cpu.get_register_u8(Reg8::B);
-        self.cpu.set_register_u8(Reg8::B, b.wrapping_sub(1));
+    pub(crate) fn ind(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        self.block_in(false)?;
+        Ok(())
+    }
 
-        self.cpu.registers.f.set(Flags::ZERO, b.wrapping_sub(1) == 0);
-        self.cpu.registers.f.set(Flags::SUBTRACT, true);
+    pub(crate) fn in_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let value = self.block_in(true)?;
+        self.repeat_block_io(value)
+    }
 
-        Ok(())
+    pub(crate) fn in_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let value = self.block_in(false)?;
+        self.repeat_block_io(value)
     }
 
-    pub(crate) fn compare(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn compare(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (lhs, rhs, result) = match instruction.opcode {
             Opcode::Compare(Operand::Immediate(Immediate::U8(imm), false), _) => {
                 let a = self.cpu.get_register_u8(Reg8::A);
@@ -371,113 +409,108 @@ impl<'a> Handlers<'a> {
             .registers
             .f
             .set(Flags::PARITY_OR_OVERFLOW, self.is_underflow(lhs, rhs, result));
+        // CP takes F3 and F5 from the operand instead of the result
+        self.set_undocumented_flags(rhs);
 
         Ok(())
     }
 
-    pub(crate) fn jump_relative(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn jump_relative(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::JumpRelative(condition, Immediate::S8(imm), _) => {
                 if self.check_cpu_flag(condition) {
                     let pc = self.cpu.get_register_u16(Reg16::PC);
                     let dst = pc.wrapping_add_signed(imm.into());
                     self.cpu.set_register_u16(Reg16::PC, dst);
+                    self.cpu.registers.wz = dst;
                     Ok(())
                 } else {
-                    Err(GgError::JumpNotTaken)
+                    Err(CpuError::JumpNotTaken)
                 }
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn call(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn call(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Call(condition, Operand::Immediate(Immediate::U16(imm), false), instruction_length) => {
+                self.cpu.registers.wz = imm;
                 if self.check_cpu_flag(condition) {
                     let next_instruction_addr = self.cpu.get_register_u16(Reg16::PC) + instruction_length as u16;
                     self.cpu.push_stack(self.bus, next_instruction_addr)?;
                     self.cpu.set_register_u16(Reg16::PC, imm);
                     Ok(())
                 } else {
-                    Err(GgError::JumpNotTaken)
+                    Err(CpuError::JumpNotTaken)
                 }
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn return_(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn return_(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Return(condition, _) => {
                 if self.check_cpu_flag(condition) {
                     let addr = self.cpu.pop_stack(self.bus)?;
                     self.cpu.set_register_u16(Reg16::PC, addr);
+                    self.cpu.registers.wz = addr;
                     return Ok(());
                 }
-                Err(GgError::JumpNotTaken)
+                Err(CpuError::JumpNotTaken)
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn return_from_irq(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn return_from_irq(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::ReturnFromIrq(_) => {
                 let addr = self.cpu.pop_stack(self.bus)?;
                 self.cpu.set_register_u16(Reg16::PC, addr);
+                self.cpu.registers.wz = addr;
                 self.cpu.registers.iff1 = self.cpu.registers.iff2;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn out_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let b = self.cpu.get_register_u8(Reg8::B);
-        let hl = self.cpu.get_register_u16(Reg16::HL);
-
-        let value = self.bus.read(hl)?;
-        let port = self.cpu.get_register_u8(Reg8::C);
-        self.cpu.write_io(port, value, self.vdp, self.bus, self.psg)?;
-
-        self.cpu.set_register_u16(Reg16::HL, hl + 1);
-        self.cpu.set_register_u8(Reg8::B, b.wrapping_sub(1));
-
-        if self.cpu.get_register_u8(Reg8::B) == 0 {
-            Ok(())
-        } else {
-            Err(GgError::RepeatNotFulfilled)
+    pub(crate) fn return_from_nmi(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        match instruction.opcode {
+            Opcode::ReturnFromNmi(_) => {
+                let addr = self.cpu.pop_stack(self.bus)?;
+                self.cpu.set_register_u16(Reg16::PC, addr);
+                self.cpu.registers.wz = addr;
+                self.cpu.registers.iff1 = self.cpu.registers.iff2;
+                Ok(())
+            }
+            _ => Err(CpuError::InvalidOpcodeImplementation {
+                instruction: instruction.opcode,
+            }),
         }
     }
 
-    pub(crate) fn out_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let b = self.cpu.get_register_u8(Reg8::B);
-        let hl = self.cpu.get_register_u16(Reg16::HL);
-
-        let value = self.bus.read(hl)?;
-        let port = self.cpu.get_register_u8(Reg8::C);
-        self.cpu.write_io(port, value, self.vdp, self.bus, self.psg)?;
-
-        self.cpu.set_register_u16(Reg16::HL, hl.wrapping_sub(1));
-        self.cpu.set_register_u8(Reg8::B, b.wrapping_sub(1));
+    pub(crate) fn out_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let value = self.block_out(true)?;
+        self.repeat_block_io(value)
+    }
 
-        if self.cpu.get_register_u8(Reg8::B) == 0 {
-            Ok(())
-        } else {
-            Err(GgError::RepeatNotFulfilled)
-        }
+    pub(crate) fn out_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let value = self.block_out(false)?;
+        self.repeat_block_io(value)
     }
 
-    pub(crate) fn compare_increment(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn compare_increment(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -502,11 +535,16 @@ impl<'a> Handlers<'a> {
             .registers
             .f
             .set(Flags::PARITY_OR_OVERFLOW, self.cpu.get_register_u16(Reg16::BC) != 0);
+        self.cpu.registers.wz = self.cpu.registers.wz.wrapping_add(1);
+
+        // F3 and F5 come from A - (HL) - H instead of the result
+        let half_carry = self.cpu.registers.f.contains(Flags::HALF_CARRY) as u8;
+        self.set_block_transfer_flags(result.wrapping_sub(half_carry));
 
         Ok(())
     }
 
-    pub(crate) fn compare_decrement(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn compare_decrement(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -531,11 +569,16 @@ impl<'a> Handlers<'a> {
             .registers
             .f
             .set(Flags::PARITY_OR_OVERFLOW, self.cpu.get_register_u16(Reg16::BC) != 0);
+        self.cpu.registers.wz = self.cpu.registers.wz.wrapping_sub(1);
+
+        // F3 and F5 come from A - (HL) - H instead of the result
+        let half_carry = self.cpu.registers.f.contains(Flags::HALF_CARRY) as u8;
+        self.set_block_transfer_flags(result.wrapping_sub(half_carry));
 
         Ok(())
     }
 
-    pub(crate) fn compare_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn compare_increment_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -560,15 +603,20 @@ impl<'a> Handlers<'a> {
             .registers
             .f
             .set(Flags::PARITY_OR_OVERFLOW, self.cpu.get_register_u16(Reg16::BC) != 0);
+        self.cpu.registers.wz = self.cpu.registers.wz.wrapping_add(1);
+
+        // F3 and F5 come from A - (HL) - H instead of the result
+        let half_carry = self.cpu.registers.f.contains(Flags::HALF_CARRY) as u8;
+        self.set_block_transfer_flags(result.wrapping_sub(half_carry));
 
         if self.cpu.get_register_u16(Reg16::BC) == 0 || self.cpu.registers.f.contains(Flags::ZERO) {
             Ok(())
         } else {
-            Err(GgError::RepeatNotFulfilled)
+            self.repeat_block()
         }
     }
 
-    pub(crate) fn compare_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn compare_decrement_repeat(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = {
             let hl = self.cpu.get_register_u16(Reg16::HL);
             self.bus.read(hl)?
@@ -593,21 +641,26 @@ impl<'a> Handlers<'a> {
             .registers
             .f
             .set(Flags::PARITY_OR_OVERFLOW, self.cpu.get_register_u16(Reg16::BC) != 0);
+        self.cpu.registers.wz = self.cpu.registers.wz.wrapping_sub(1);
+
+        // F3 and F5 come from A - (HL) - H instead of the result
+        let half_carry = self.cpu.registers.f.contains(Flags::HALF_CARRY) as u8;
+        self.set_block_transfer_flags(result.wrapping_sub(half_carry));
 
         if self.cpu.get_register_u16(Reg16::BC) == 0 || self.cpu.registers.f.contains(Flags::ZERO) {
             Ok(())
         } else {
-            Err(GgError::RepeatNotFulfilled)
+            self.repeat_block()
         }
     }
 
-    pub(crate) fn or(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn or(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = match instruction.opcode {
             Opcode::Or(Operand::Register(Register::Reg16(src_reg), true), _) => self.bus.read(self.cpu.get_register_u16(src_reg))?,
             Opcode::Or(Operand::Register(Register::Reg8(src_reg), false), _) => self.cpu.get_register_u8(src_reg),
             Opcode::Or(Operand::Immediate(Immediate::U8(imm), false), _) => imm,
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -620,6 +673,7 @@ impl<'a> Handlers<'a> {
 
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
@@ -628,33 +682,33 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn push(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn push(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Push(Register::Reg16(src_reg), _) => {
                 let src = self.cpu.get_register_u16(src_reg);
                 self.cpu.push_stack(self.bus, src)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn pop(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn pop(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Pop(Register::Reg16(dst_reg), _) => {
                 let dst = self.cpu.pop_stack(self.bus)?;
                 self.cpu.set_register_u16(dst_reg, dst);
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn increment(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn increment(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Increment(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let dst = self.cpu.get_register_u8(dst_reg);
@@ -663,6 +717,7 @@ impl<'a> Handlers<'a> {
 
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, result == 128);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, result & 0b0000_1111 == 0);
@@ -684,19 +739,20 @@ impl<'a> Handlers<'a> {
 
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, result == 128); // result overflowed from 127 to -128
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, result & 0b0000_1111 == 0);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn decrement(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn decrement(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Decrement(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let dst = self.cpu.get_register_u8(dst_reg);
@@ -705,6 +761,7 @@ impl<'a> Handlers<'a> {
 
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, result == 127);
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, result & 0b0000_1111 == 0b0000_1111);
@@ -719,6 +776,7 @@ impl<'a> Handlers<'a> {
 
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, result == 127);
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, result & 0b0000_1111 == 0b0000_1111);
@@ -732,13 +790,13 @@ impl<'a> Handlers<'a> {
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn reset_bit(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn reset_bit(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::ResetBit(Immediate::U8(bit), Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let dst = self.cpu.get_register_u8(dst_reg);
@@ -753,13 +811,13 @@ impl<'a> Handlers<'a> {
                 self.bus.write(dst, result)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn reset_bit_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn reset_bit_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::ResetBitStore(
                 Immediate::U8(bit),
@@ -775,13 +833,13 @@ impl<'a> Handlers<'a> {
                 self.bus.write(src, result)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn decrement_and_jump_relative(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn decrement_and_jump_relative(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::DecrementAndJumpRelative(Immediate::S8(imm), _) => {
                 let condition = self.cpu.get_register_u8(Reg8::B);
@@ -792,18 +850,19 @@ impl<'a> Handlers<'a> {
                     let pc = self.cpu.get_register_u16(Reg16::PC);
                     let dst = pc.wrapping_add_signed(imm.into());
                     self.cpu.set_register_u16(Reg16::PC, dst);
+                    self.cpu.registers.wz = dst;
                     Ok(())
                 } else {
-                    Err(GgError::JumpNotTaken)
+                    Err(CpuError::JumpNotTaken)
                 }
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn xor(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn xor(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let value = match instruction.opcode {
             Opcode::Xor(Operand::Register(Register::Reg8(src_reg), deref), _) => self.cpu.get_register_u8(src_reg),
             Opcode::Xor(Operand::Register(Register::Reg16(src_reg), true), _) => {
@@ -812,7 +871,7 @@ impl<'a> Handlers<'a> {
             }
             Opcode::Xor(Operand::Immediate(Immediate::U8(imm), false), _) => imm,
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -824,6 +883,7 @@ impl<'a> Handlers<'a> {
 
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
@@ -832,71 +892,44 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn outi(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let b = self.cpu.get_register_u8(Reg8::B);
-        let result = b.wrapping_sub(1);
-        self.cpu.set_register_u8(Reg8::B, result);
-
-        let hl = self.cpu.get_register_u16(Reg16::HL);
-        let value = self.bus.read(hl)?;
-
-        let port = self.cpu.get_register_u8(Reg8::C);
-        self.cpu.write_io(port, value, self.vdp, self.bus, self.psg)?;
-
-        self.cpu.set_register_u16(Reg16::HL, hl.wrapping_add(1));
-
-        self.cpu.registers.f.set(Flags::ZERO, result == 0);
-        self.cpu.registers.f.set(Flags::SUBTRACT, true);
-
+    pub(crate) fn outi(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        self.block_out(true)?;
         Ok(())
     }
 
-    pub(crate) fn outd(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let b = self.cpu.get_register_u8(Reg8::B);
-        let result = b.wrapping_sub(1);
-        self.cpu.set_register_u8(Reg8::B, result);
-
-        let hl = self.cpu.get_register_u16(Reg16::HL);
-        let value = self.bus.read(hl)?;
-
-        let port = self.cpu.get_register_u8(Reg8::C);
-        self.cpu.write_io(port, value, self.vdp, self.bus, self.psg)?;
-
-        self.cpu.set_register_u16(Reg16::HL, hl.wrapping_sub(1));
-
-        self.cpu.registers.f.set(Flags::ZERO, result == 0);
-        self.cpu.registers.f.set(Flags::SUBTRACT, true);
-
+    pub(crate) fn outd(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        self.block_out(false)?;
         Ok(())
     }
 
-    pub(crate) fn restart(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn restart(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Restart(Immediate::U8(imm), _) => {
                 let pc = self.cpu.get_register_u16(Reg16::PC);
                 self.cpu.push_stack(self.bus, pc.wrapping_add(1))?;
                 self.cpu.set_register_u16(Reg16::PC, imm as u16);
+                self.cpu.registers.wz = imm as u16;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn set_interrupt_mode(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn set_interrupt_mode(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::SetInterruptMode(Immediate::U8(mode), _) => {
                 self.cpu.interrupt_mode = InterruptMode::from(mode)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn and(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn and(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let src = match instruction.opcode {
             Opcode::And(Operand::Register(Register::Reg8(src_reg), false), _) => self.cpu.get_register_u8(src_reg),
             Opcode::And(Operand::Immediate(Immediate::U8(imm), false), _) => imm,
@@ -905,7 +938,7 @@ impl<'a> Handlers<'a> {
                 self.bus.read(src)?
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -918,6 +951,7 @@ impl<'a> Handlers<'a> {
 
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
         self.cpu.registers.f.set(Flags::CARRY, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, true);
@@ -926,7 +960,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn subtract(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn subtract(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let value = match instruction.opcode {
             Opcode::Subtract(Operand::Register(Register::Reg8(src_reg), false), _) => self.cpu.get_register_u8(src_reg),
             Opcode::Subtract(Operand::Immediate(Immediate::U8(imm), false), _) => imm,
@@ -935,7 +969,7 @@ impl<'a> Handlers<'a> {
                 self.bus.read(src)?
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -949,6 +983,7 @@ impl<'a> Handlers<'a> {
         let hc = self.detect_half_carry_u8(a, value, result);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu
             .registers
             .f
@@ -960,7 +995,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn add(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn add(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Add(Operand::Register(Register::Reg8(dst_reg), false), Operand::Register(Register::Reg8(src_reg), false), _) => {
                 let src = self.cpu.get_register_u8(src_reg);
@@ -975,6 +1010,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
@@ -991,6 +1027,8 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, result < dst);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, hc);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
+                self.set_undocumented_flags((result >> 8) as u8);
+                self.cpu.registers.wz = dst.wrapping_add(1);
             }
             Opcode::Add(Operand::Register(Register::Reg8(dst_reg), false), Operand::Immediate(Immediate::U8(imm), false), _) => {
                 let dst = self.cpu.get_register_u8(dst_reg);
@@ -1004,6 +1042,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::HALF_CARRY, hc);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
@@ -1023,13 +1062,14 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::HALF_CARRY, hc);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
                     .set(Flags::PARITY_OR_OVERFLOW, self.is_overflow(dst, src, result));
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -1038,7 +1078,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn subtract_carry(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn subtract_carry(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::SubtractCarry(
                 Operand::Register(Register::Reg16(dst_reg), false),
@@ -1061,6 +1101,8 @@ impl<'a> Handlers<'a> {
                     .registers
                     .f
                     .set(Flags::PARITY_OR_OVERFLOW, self.is_underflow_u16(dst, src, result));
+                self.set_undocumented_flags((result >> 8) as u8);
+                self.cpu.registers.wz = dst.wrapping_add(1);
 
                 Ok(())
             }
@@ -1081,6 +1123,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
@@ -1104,6 +1147,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
@@ -1129,6 +1173,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu
                     .registers
                     .f
@@ -1136,13 +1181,13 @@ impl<'a> Handlers<'a> {
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_decimal(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_decimal(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftDecimal(_) => {
                 let a = self.cpu.get_register_u8(Reg8::A);
@@ -1159,17 +1204,19 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
+                self.cpu.registers.wz = hl.wrapping_add(1);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_decimal(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_decimal(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightDecimal(_) => {
                 let a = self.cpu.get_register_u8(Reg8::A);
@@ -1186,17 +1233,19 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
+                self.cpu.registers.wz = hl.wrapping_add(1);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_carry_accumulator(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_carry_accumulator(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightCarryAccumulator(_) => {
                 let value = self.cpu.get_register_u8(Reg8::A);
@@ -1208,16 +1257,17 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+                self.set_undocumented_flags(value);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_accumulator(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_accumulator(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightAccumulator(_) => {
                 let value = self.cpu.get_register_u8(Reg8::A);
@@ -1231,16 +1281,17 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+                self.set_undocumented_flags(value);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_carry_accumulator(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_carry_accumulator(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftCarryAccumulator(_) => {
                 let value = self.cpu.get_register_u8(Reg8::A);
@@ -1252,16 +1303,17 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+                self.set_undocumented_flags(value);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_accumulator(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_accumulator(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftAccumulator(_) => {
                 let value = self.cpu.get_register_u8(Reg8::A);
@@ -1275,16 +1327,17 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+                self.set_undocumented_flags(value);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_carry(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_carry(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightCarry(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -1295,6 +1348,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1311,19 +1365,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRight(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let previous_carry = self.cpu.registers.f.contains(Flags::CARRY);
@@ -1335,6 +1390,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1352,19 +1408,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_carry(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_carry(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftCarry(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -1375,6 +1432,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1391,19 +1449,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeft(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let previous_carry = self.cpu.registers.f.contains(Flags::CARRY);
@@ -1415,6 +1474,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1436,19 +1496,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_carry_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_carry_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightCarryStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1465,19 +1526,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_right_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_right_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateRightStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1495,19 +1557,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_carry_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_carry_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftCarryStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1524,19 +1587,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn rotate_left_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn rotate_left_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::RotateLeftStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1554,6 +1618,7 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1575,19 +1640,20 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::CARRY, carry);
                 self.cpu.registers.f.set(Flags::ZERO, result == 0);
                 self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+                self.set_undocumented_flags(result);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, false);
                 self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn complement(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn complement(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Complement(_) => {
                 let a = self.cpu.get_register_u8(Reg8::A);
@@ -1596,16 +1662,17 @@ impl<'a> Handlers<'a> {
 
                 self.cpu.registers.f.set(Flags::SUBTRACT, true);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, true);
+                self.set_undocumented_flags(result);
 
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn set_bit(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn set_bit(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::SetBit(Immediate::U8(bit), Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let dst = self.cpu.get_register_u8(dst_reg);
@@ -1620,13 +1687,13 @@ impl<'a> Handlers<'a> {
                 self.bus.write(dst, result)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn set_bit_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn set_bit_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::SetBitStore(
                 Immediate::U8(bit),
@@ -1642,13 +1709,13 @@ impl<'a> Handlers<'a> {
                 self.bus.write(src, result)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn exchange(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn exchange(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::Exchange(Operand::Register(Register::Reg16(lhs_reg), false), Operand::Register(Register::Reg16(rhs_reg), false), _) => {
                 let rhs = self.cpu.get_register_u16(rhs_reg);
@@ -1663,6 +1730,7 @@ impl<'a> Handlers<'a> {
                 let data2 = self.cpu.get_register_u16(rhs_reg);
                 self.bus.write_word(src, data2)?;
                 self.cpu.set_register_u16(rhs_reg, data1);
+                self.cpu.registers.wz = data1;
                 Ok(())
             }
             Opcode::Exchange(Operand::Register(Register::Reg16(lhs_reg), true), Operand::Register(Register::Reg16(rhs_reg), true), _) => {
@@ -1675,13 +1743,13 @@ impl<'a> Handlers<'a> {
                 self.bus.write_word(src, data2)?;
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn exchange_all(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn exchange_all(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let de = self.cpu.get_register_u16(Reg16::DE);
         let hl = self.cpu.get_register_u16(Reg16::HL);
         let bc = self.cpu.get_register_u16(Reg16::BC);
@@ -1701,17 +1769,19 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn test_bit(&mut self, instruction: &Instruction) -> Result<(), GgError> {
-        let (src, bit) = match instruction.opcode {
+    pub(crate) fn test_bit(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        // F3 and F5 come from the tested register, or from the high byte of MEMPTR for memory operands
+        let (src, bit, undocumented) = match instruction.opcode {
             Opcode::TestBit(Immediate::U8(bit), Operand::Register(Register::Reg8(src_reg), false), _) => {
-                (self.cpu.get_register_u8(src_reg), bit)
+                let src = self.cpu.get_register_u8(src_reg);
+                (src, bit, src)
             }
             Opcode::TestBit(Immediate::U8(bit), Operand::Register(Register::Reg16(src_reg), true), _) => {
                 let src = self.cpu.get_register_u16(src_reg);
-                (self.bus.read(src)?, bit)
+                (self.bus.read(src)?, bit, (self.cpu.registers.wz >> 8) as u8)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 });
             }
@@ -1723,26 +1793,29 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(undocumented);
 
         Ok(())
     }
 
-    pub(crate) fn invert_carry(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn invert_carry(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         match instruction.opcode {
             Opcode::InvertCarry(_) => {
                 let carry = self.cpu.registers.f.contains(Flags::CARRY);
+                let undocumented = (self.cpu.q ^ self.cpu.registers.f.bits()) | self.cpu.registers.a;
                 self.cpu.registers.f.set(Flags::CARRY, !carry);
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, carry);
+                self.set_undocumented_flags(undocumented);
                 Ok(())
             }
-            _ => Err(GgError::InvalidOpcodeImplementation {
+            _ => Err(CpuError::InvalidOpcodeImplementation {
                 instruction: instruction.opcode,
             }),
         }
     }
 
-    pub(crate) fn add_carry(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn add_carry(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (src, dst, carry, dst_reg) = match instruction.opcode {
             Opcode::AddCarry(Operand::Register(Register::Reg8(dst_reg), false), Operand::Register(Register::Reg8(src_reg), false), _) => {
                 let src = self.cpu.get_register_u8(src_reg);
@@ -1779,11 +1852,13 @@ impl<'a> Handlers<'a> {
                 self.cpu.registers.f.set(Flags::SUBTRACT, false);
                 self.cpu.registers.f.set(Flags::HALF_CARRY, hc);
                 self.cpu.registers.f.set(Flags::CARRY, carry);
+                self.set_undocumented_flags((result >> 8) as u8);
+                self.cpu.registers.wz = dst.wrapping_add(1);
 
                 return Ok(());
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1795,6 +1870,7 @@ impl<'a> Handlers<'a> {
         let hc = self.detect_half_carry_u8(dst, src, result);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu
             .registers
             .f
@@ -1806,14 +1882,16 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn set_carry_flag(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn set_carry_flag(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
+        let undocumented = (self.cpu.q ^ self.cpu.registers.f.bits()) | self.cpu.registers.a;
         self.cpu.registers.f.set(Flags::CARRY, true);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
+        self.set_undocumented_flags(undocumented);
         Ok(())
     }
 
-    pub(crate) fn decimal_adjust_accumulator(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn decimal_adjust_accumulator(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let mut a = self.cpu.get_register_u8(Reg8::A);
         if self.cpu.registers.f.contains(Flags::CARRY) || a > 0x99 {
             let value = a.wrapping_add_signed(if self.cpu.registers.f.contains(Flags::SUBTRACT) {
@@ -1835,12 +1913,13 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::HALF_CARRY, (result ^ a) & 0x10 > 0);
 
         Ok(())
     }
 
-    pub(crate) fn shift_right_arithmetic(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_right_arithmetic(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftRightArithmetic(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -1858,7 +1937,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1867,6 +1946,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1874,7 +1954,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_right_logical(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_right_logical(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftRightLogical(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -1892,7 +1972,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1901,6 +1981,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1908,7 +1989,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_right_arithmetic_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_right_arithmetic_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftRightArithmeticStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1924,7 +2005,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1933,6 +2014,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1940,7 +2022,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_right_logical_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_right_logical_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftRightLogicalStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -1956,7 +2038,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1965,6 +2047,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -1972,7 +2055,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_left_arithmetic(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_left_arithmetic(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftLeftArithmetic(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -1990,7 +2073,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -1999,6 +2082,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -2006,7 +2090,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_left_logical(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_left_logical(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftLeftLogical(Operand::Register(Register::Reg8(dst_reg), false), _) => {
                 let value = self.cpu.get_register_u8(dst_reg);
@@ -2024,7 +2108,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -2033,6 +2117,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -2040,7 +2125,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_left_arithmetic_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_left_arithmetic_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftLeftArithmeticStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -2056,7 +2141,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -2065,6 +2150,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -2072,7 +2158,7 @@ impl<'a> Handlers<'a> {
         Ok(())
     }
 
-    pub(crate) fn shift_left_logical_store(&mut self, instruction: &Instruction) -> Result<(), GgError> {
+    pub(crate) fn shift_left_logical_store(&mut self, instruction: &Instruction) -> Result<(), CpuError<B::Error>> {
         let (result, carry) = match instruction.opcode {
             Opcode::ShiftLeftLogicalStore(
                 Operand::Register(Register::Reg16(src_reg), true),
@@ -2088,7 +2174,7 @@ impl<'a> Handlers<'a> {
                 (result, carry)
             }
             _ => {
-                return Err(GgError::InvalidOpcodeImplementation {
+                return Err(CpuError::InvalidOpcodeImplementation {
                     instruction: instruction.opcode,
                 })
             }
@@ -2097,6 +2183,7 @@ impl<'a> Handlers<'a> {
         self.cpu.registers.f.set(Flags::CARRY, carry);
         self.cpu.registers.f.set(Flags::ZERO, result == 0);
         self.cpu.registers.f.set(Flags::SIGN, result & 0b1000_0000 != 0);
+        self.set_undocumented_flags(result);
         self.cpu.registers.f.set(Flags::SUBTRACT, false);
         self.cpu.registers.f.set(Flags::HALF_CARRY, false);
         self.cpu.registers.f.set(Flags::PARITY_OR_OVERFLOW, self.check_parity(result));
@@ -2106,6 +2193,117 @@ impl<'a> Handlers<'a> {
 
     // Helpers
 
+    fn block_in(&mut self, increment: bool) -> Result<u8, CpuError<B::Error>> {
+        let c = self.cpu.get_register_u8(Reg8::C);
+        let hl = self.cpu.get_register_u16(Reg16::HL);
+        let bc = self.cpu.get_register_u16(Reg16::BC);
+        self.cpu.registers.wz = if increment { bc.wrapping_add(1) } else { bc.wrapping_sub(1) };
+
+        let value = self.bus.read_io(bc)?;
+        self.bus.write(hl, value)?;
+
+        let b = self.cpu.get_register_u8(Reg8::B).wrapping_sub(1);
+        self.cpu.set_register_u8(Reg8::B, b);
+
+        let (hl, c) = if increment {
+            (hl.wrapping_add(1), c.wrapping_add(1))
+        } else {
+            (hl.wrapping_sub(1), c.wrapping_sub(1))
+        };
+        self.cpu.set_register_u16(Reg16::HL, hl);
+
+        self.set_block_io_flags(value, value as u16 + c as u16);
+        Ok(value)
+    }
+
+    fn block_out(&mut self, increment: bool) -> Result<u8, CpuError<B::Error>> {
+        // B is decremented before the value is put on the bus
+        let b = self.cpu.get_register_u8(Reg8::B).wrapping_sub(1);
+        self.cpu.set_register_u8(Reg8::B, b);
+
+        let bc = self.cpu.get_register_u16(Reg16::BC);
+        self.cpu.registers.wz = if increment { bc.wrapping_add(1) } else { bc.wrapping_sub(1) };
+
+        let hl = self.cpu.get_register_u16(Reg16::HL);
+        let value = self.bus.read(hl)?;
+        self.bus.write_io(bc, value)?;
+
+        let hl = if increment { hl.wrapping_add(1) } else { hl.wrapping_sub(1) };
+        self.cpu.set_register_u16(Reg16::HL, hl);
+
+        let l = self.cpu.get_register_u8(Reg8::L);
+        self.set_block_io_flags(value, value as u16 + l as u16);
+        Ok(value)
+    }
+
+    fn set_block_io_flags(&mut self, value: u8, k: u16) {
+        // "The Undocumented Z80 Documented", 4.3: k is the transferred value plus C+1/C-1 for INI/IND
+        // or plus L (after the increment/decrement) for OUTI/OUTD
+        let b = self.cpu.get_register_u8(Reg8::B);
+        self.cpu.registers.f.set(Flags::SIGN, b & 0b1000_0000 > 0);
+        self.cpu.registers.f.set(Flags::ZERO, b == 0);
+        self.cpu.registers.f.set(Flags::HALF_CARRY, k > 0xff);
+        self.cpu.registers.f.set(Flags::CARRY, k > 0xff);
+        self.cpu
+            .registers
+            .f
+            .set(Flags::PARITY_OR_OVERFLOW, self.check_parity((k & 0b0000_0111) as u8 ^ b));
+        self.cpu.registers.f.set(Flags::SUBTRACT, value & 0b1000_0000 > 0);
+        self.set_undocumented_flags(b);
+    }
+
+    fn repeat_block_io(&mut self, value: u8) -> Result<(), CpuError<B::Error>> {
+        let b = self.cpu.get_register_u8(Reg8::B);
+        if b == 0 {
+            return Ok(());
+        }
+
+        // An interrupted repeat adjusts H and P/V once more, depending on the carry and the direction B would count into
+        let flags = self.cpu.registers.f;
+        let parity_source = if flags.contains(Flags::CARRY) {
+            let (adjusted, half_carry) = if value & 0b1000_0000 > 0 {
+                (b.wrapping_sub(1), b & 0x0f == 0x00)
+            } else {
+                (b.wrapping_add(1), b & 0x0f == 0x0f)
+            };
+            self.cpu.registers.f.set(Flags::HALF_CARRY, half_carry);
+            adjusted
+        } else {
+            b
+        };
+
+        let toggle = !self.check_parity(parity_source & 0b0000_0111);
+        self.cpu
+            .registers
+            .f
+            .set(Flags::PARITY_OR_OVERFLOW, flags.contains(Flags::PARITY_OR_OVERFLOW) ^ toggle);
+
+        let pc = self.cpu.get_register_u16(Reg16::PC);
+        self.set_undocumented_flags((pc >> 8) as u8);
+
+        Err(CpuError::RepeatNotFulfilled)
+    }
+
+    fn set_block_transfer_flags(&mut self, n: u8) {
+        // LDI/LDD and CPI/CPD take F3 from bit 3 and F5 from bit 1 of n
+        self.cpu.registers.f.set(Flags::F3, n & 0b0000_1000 != 0);
+        self.cpu.registers.f.set(Flags::F5, n & 0b0000_0010 != 0);
+    }
+
+    fn repeat_block(&mut self) -> Result<(), CpuError<B::Error>> {
+        // While LDxR/CPxR repeat, MEMPTR points past the prefix and F3/F5 leak the high byte of PC
+        let pc = self.cpu.get_register_u16(Reg16::PC);
+        self.cpu.registers.wz = pc.wrapping_add(1);
+        self.set_undocumented_flags((pc >> 8) as u8);
+
+        Err(CpuError::RepeatNotFulfilled)
+    }
+
+    fn set_undocumented_flags(&mut self, value: u8) {
+        self.cpu.registers.f.set(Flags::F3, value & 0b0000_1000 != 0);
+        self.cpu.registers.f.set(Flags::F5, value & 0b0010_0000 != 0);
+    }
+
     fn check_cpu_flag(&self, condition: Condition) -> bool {
         match condition {
             Condition::None => true,
@@ -1,5 +1,8 @@
 use std::fmt;
 
+use crate::metadata::{self, Encoding, OpcodeInfo};
+use crate::timing::Cycles;
+
 // todo: Rename this to Reg and create new enum Reg16 and Reg8?
 #[derive(PartialEq, Copy, Clone)]
 pub enum Register {
@@ -158,10 +161,24 @@ pub enum Opcode {
     Unknown(usize),
 }
 
+#[derive(Clone)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub length: usize,
     pub offset: usize,
+    pub encoding: Encoding,
+}
+
+impl Instruction {
+    /// Timing, dataflow and access kind of the opcode, shared by every instruction with the same encoding
+    pub fn info(&self) -> Option<&'static OpcodeInfo> {
+        metadata::lookup(self.encoding)
+    }
+
+    /// T-states of the instruction, the metadata table is the only place timing is kept
+    pub fn cycles(&self) -> Cycles {
+        self.info().expect("decoded instructions have metadata").cycles
+    }
 }
 
 impl fmt::Display for Instruction {
@@ -1,3 +1,12 @@
+pub mod assembler;
+pub mod bus;
+pub mod cpu;
 pub mod disassembler;
+mod handlers;
 pub mod instruction;
+pub mod metadata;
+pub mod timing;
 pub mod z80;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,586 @@
+use std::ops::BitOr;
+use std::sync::OnceLock;
+
+use crate::disassembler::Disassembler;
+use crate::instruction::{Condition, Opcode, Operand, Reg16, Reg8, Register};
+use crate::timing::{self, Cycles};
+
+// The table is generated from the decoder and the timing tables, so metadata and decoding can't disagree about
+// which encodings exist
+static TABLE: OnceLock<Vec<Option<OpcodeInfo>>> = OnceLock::new();
+
+// Bits of the F register
+pub const FLAG_S: u8 = 0b1000_0000;
+pub const FLAG_Z: u8 = 0b0100_0000;
+pub const FLAG_F5: u8 = 0b0010_0000;
+pub const FLAG_H: u8 = 0b0001_0000;
+pub const FLAG_F3: u8 = 0b0000_1000;
+pub const FLAG_PV: u8 = 0b0000_0100;
+pub const FLAG_N: u8 = 0b0000_0010;
+pub const FLAG_C: u8 = 0b0000_0001;
+pub const FLAGS_ALL: u8 = 0xff;
+
+// Flags touched by the different instruction groups
+const FLAGS_ALL_BUT_CARRY: u8 = FLAGS_ALL & !FLAG_C;
+const FLAGS_ACCUMULATOR_ROTATE: u8 = FLAG_F5 | FLAG_H | FLAG_F3 | FLAG_N | FLAG_C; // Also add hl, ss, ccf and scf
+const FLAGS_BLOCK_TRANSFER: u8 = FLAG_F5 | FLAG_H | FLAG_F3 | FLAG_PV | FLAG_N;
+const FLAGS_COMPLEMENT: u8 = FLAG_F5 | FLAG_H | FLAG_F3 | FLAG_N;
+
+// Bytes that start a prefixed encoding instead of being an opcode on their own
+const PREFIX_BYTES: [u8; 4] = [0xcb, 0xdd, 0xed, 0xfd];
+
+const PREFIXES: [Prefix; 7] = [
+    Prefix::None,
+    Prefix::Cb,
+    Prefix::Ed,
+    Prefix::Dd,
+    Prefix::Fd,
+    Prefix::DdCb,
+    Prefix::FdCb,
+];
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Prefix {
+    None,
+    Cb,
+    Ed,
+    Dd,
+    Fd,
+    DdCb,
+    FdCb,
+}
+
+/// Prefix and opcode byte, the DDCB/FDCB opcode is the byte after the displacement
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Encoding {
+    pub prefix: Prefix,
+    pub opcode: u8,
+}
+
+impl Encoding {
+    pub fn from_bytes(bytes: [u8; 4]) -> Encoding {
+        let (prefix, opcode) = match bytes {
+            [0xcb, opcode, _, _] => (Prefix::Cb, opcode),
+            [0xed, opcode, _, _] => (Prefix::Ed, opcode),
+            [0xdd, 0xcb, _, opcode] => (Prefix::DdCb, opcode),
+            [0xfd, 0xcb, _, opcode] => (Prefix::FdCb, opcode),
+            [0xdd, opcode, _, _] => (Prefix::Dd, opcode),
+            [0xfd, opcode, _, _] => (Prefix::Fd, opcode),
+            [opcode, _, _, _] => (Prefix::None, opcode),
+        };
+        Encoding { prefix, opcode }
+    }
+
+    /// Every prefix and opcode pair, prefix bytes only count as opcodes behind CB, ED and DDCB/FDCB
+    pub fn all() -> impl Iterator<Item = Encoding> {
+        PREFIXES
+            .into_iter()
+            .flat_map(|prefix| (0..=0xff).map(move |opcode| Encoding { prefix, opcode }))
+            .filter(|encoding| {
+                !matches!(encoding.prefix, Prefix::None | Prefix::Dd | Prefix::Fd) || !PREFIX_BYTES.contains(&encoding.opcode)
+            })
+    }
+
+    /// Opcode bytes of the encoding, `None` marks the bytes that can hold operands
+    pub fn pattern(&self) -> [Option<u8>; 4] {
+        let opcode = Some(self.opcode);
+        match self.prefix {
+            Prefix::None => [opcode, None, None, None],
+            Prefix::Cb => [Some(0xcb), opcode, None, None],
+            Prefix::Ed => [Some(0xed), opcode, None, None],
+            Prefix::Dd => [Some(0xdd), opcode, None, None],
+            Prefix::Fd => [Some(0xfd), opcode, None, None],
+            // The displacement comes before the opcode
+            Prefix::DdCb => [Some(0xdd), Some(0xcb), None, opcode],
+            Prefix::FdCb => [Some(0xfd), Some(0xcb), None, opcode],
+        }
+    }
+
+    fn index(&self) -> usize {
+        self.prefix as usize * 0x100 + self.opcode as usize
+    }
+}
+
+/// Set of CPU registers, 16-bit registers are the union of their halves
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct RegisterSet(u32);
+
+impl RegisterSet {
+    pub const NONE: RegisterSet = RegisterSet(0);
+    pub const A: RegisterSet = RegisterSet(1 << 0);
+    pub const F: RegisterSet = RegisterSet(1 << 1);
+    pub const B: RegisterSet = RegisterSet(1 << 2);
+    pub const C: RegisterSet = RegisterSet(1 << 3);
+    pub const D: RegisterSet = RegisterSet(1 << 4);
+    pub const E: RegisterSet = RegisterSet(1 << 5);
+    pub const H: RegisterSet = RegisterSet(1 << 6);
+    pub const L: RegisterSet = RegisterSet(1 << 7);
+    pub const IXH: RegisterSet = RegisterSet(1 << 8);
+    pub const IXL: RegisterSet = RegisterSet(1 << 9);
+    pub const IYH: RegisterSet = RegisterSet(1 << 10);
+    pub const IYL: RegisterSet = RegisterSet(1 << 11);
+    pub const SP: RegisterSet = RegisterSet(1 << 12);
+    pub const PC: RegisterSet = RegisterSet(1 << 13);
+    pub const I: RegisterSet = RegisterSet(1 << 14);
+    pub const R: RegisterSet = RegisterSet(1 << 15);
+    pub const AF_SHADOW: RegisterSet = RegisterSet(1 << 16);
+    pub const BC_SHADOW: RegisterSet = RegisterSet(1 << 17);
+    pub const DE_SHADOW: RegisterSet = RegisterSet(1 << 18);
+    pub const HL_SHADOW: RegisterSet = RegisterSet(1 << 19);
+
+    pub const BC: RegisterSet = RegisterSet(Self::B.0 | Self::C.0);
+    pub const DE: RegisterSet = RegisterSet(Self::D.0 | Self::E.0);
+    pub const HL: RegisterSet = RegisterSet(Self::H.0 | Self::L.0);
+    pub const IX: RegisterSet = RegisterSet(Self::IXH.0 | Self::IXL.0);
+    pub const IY: RegisterSet = RegisterSet(Self::IYH.0 | Self::IYL.0);
+
+    pub fn contains(&self, other: RegisterSet) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(&self, other: RegisterSet) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn of(register: &Register) -> RegisterSet {
+        match register {
+            Register::Reg8(reg) => match reg {
+                Reg8::A => Self::A,
+                Reg8::B => Self::B,
+                Reg8::C => Self::C,
+                Reg8::D => Self::D,
+                Reg8::E => Self::E,
+                Reg8::H => Self::H,
+                Reg8::L => Self::L,
+                Reg8::F => Self::F,
+                Reg8::AShadow | Reg8::FShadow => Self::AF_SHADOW,
+                Reg8::BShadow | Reg8::CShadow => Self::BC_SHADOW,
+                Reg8::DShadow | Reg8::EShadow => Self::DE_SHADOW,
+                Reg8::HShadow | Reg8::LShadow => Self::HL_SHADOW,
+                Reg8::IXH => Self::IXH,
+                Reg8::IXL => Self::IXL,
+                Reg8::IYH => Self::IYH,
+                Reg8::IYL => Self::IYL,
+                Reg8::I => Self::I,
+                Reg8::R => Self::R,
+            },
+            Register::Reg16(reg) => match reg {
+                Reg16::AF => Self::A | Self::F,
+                Reg16::BC => Self::BC,
+                Reg16::DE => Self::DE,
+                Reg16::HL => Self::HL,
+                Reg16::AFShadow => Self::AF_SHADOW,
+                Reg16::BCShadow => Self::BC_SHADOW,
+                Reg16::DEShadow => Self::DE_SHADOW,
+                Reg16::HLShadow => Self::HL_SHADOW,
+                Reg16::SP => Self::SP,
+                Reg16::PC => Self::PC,
+                Reg16::IX(_) => Self::IX,
+                Reg16::IY(_) => Self::IY,
+            },
+        }
+    }
+}
+
+impl BitOr for RegisterSet {
+    type Output = RegisterSet;
+
+    fn bitor(self, rhs: RegisterSet) -> RegisterSet {
+        RegisterSet(self.0 | rhs.0)
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum Access {
+    #[default]
+    None,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl BitOr for Access {
+    type Output = Access;
+
+    fn bitor(self, rhs: Access) -> Access {
+        match (self, rhs) {
+            (Access::None, access) | (access, Access::None) => access,
+            (a, b) if a == b => a,
+            _ => Access::ReadWrite,
+        }
+    }
+}
+
+/// Static facts about an opcode, independent of its operand bytes
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct OpcodeInfo {
+    pub encoding: Encoding,
+    pub length: usize,
+    pub cycles: Cycles,
+    pub flags_read: u8,
+    pub flags_written: u8,
+    pub registers_read: RegisterSet,
+    pub registers_written: RegisterSet,
+    pub memory: Access,
+    pub port: Access,
+    pub port_cycle: Option<usize>, // T-state of the instruction the port is accessed at
+}
+
+/// Metadata of an encoding, None if the decoder doesn't know it
+pub fn lookup(encoding: Encoding) -> Option<&'static OpcodeInfo> {
+    TABLE.get_or_init(build_table)[encoding.index()].as_ref()
+}
+
+/// Every known opcode
+pub fn table() -> impl Iterator<Item = &'static OpcodeInfo> {
+    TABLE.get_or_init(build_table).iter().flatten()
+}
+
+fn build_table() -> Vec<Option<OpcodeInfo>> {
+    let mut table = vec![None; PREFIXES.len() * 0x100];
+
+    for encoding in Encoding::all() {
+        let bytes = encoding.pattern().map(|byte| byte.unwrap_or(0));
+        let Ok(instruction) = Disassembler::new(&bytes).decode(0) else {
+            continue;
+        };
+
+        let sequence = (bytes[0], bytes[1], bytes[2], bytes[3]);
+        let effects = Effects::of(&instruction.opcode);
+        table[encoding.index()] = Some(OpcodeInfo {
+            encoding,
+            length: instruction.length,
+            cycles: timing::lookup(sequence),
+            flags_read: effects.flags_read,
+            flags_written: effects.flags_written,
+            registers_read: effects.registers_read,
+            registers_written: effects.registers_written,
+            memory: effects.memory,
+            port: effects.port,
+            port_cycle: timing::port_cycle(sequence),
+        });
+    }
+
+    table
+}
+
+#[derive(Default)]
+struct Effects {
+    flags_read: u8,
+    flags_written: u8,
+    registers_read: RegisterSet,
+    registers_written: RegisterSet,
+    memory: Access,
+    port: Access,
+}
+
+impl Effects {
+    fn of(opcode: &Opcode) -> Effects {
+        let mut effects = Effects::default();
+
+        match opcode {
+            Opcode::Load(dst, src, _) => {
+                effects.read(src);
+                effects.write(dst);
+                // ld a, i and ld a, r copy IFF2 to P/V
+                if matches!(src, Operand::Register(Register::Reg8(Reg8::I | Reg8::R), false)) {
+                    effects.flags_written = FLAGS_ALL_BUT_CARRY;
+                }
+            }
+            Opcode::LoadIncrement(_) | Opcode::LoadIncrementRepeat(_) | Opcode::LoadDecrement(_) | Opcode::LoadDecrementRepeat(_) => {
+                effects.registers(
+                    RegisterSet::BC | RegisterSet::DE | RegisterSet::HL,
+                    RegisterSet::BC | RegisterSet::DE | RegisterSet::HL,
+                );
+                effects.registers_read = effects.registers_read | RegisterSet::A; // F3/F5 come from A + (hl)
+                effects.memory = Access::ReadWrite;
+                effects.flags_written = FLAGS_BLOCK_TRANSFER;
+            }
+            Opcode::CompareIncrement(_)
+            | Opcode::CompareIncrementRepeat(_)
+            | Opcode::CompareDecrement(_)
+            | Opcode::CompareDecrementRepeat(_) => {
+                effects.registers(
+                    RegisterSet::A | RegisterSet::BC | RegisterSet::HL,
+                    RegisterSet::BC | RegisterSet::HL,
+                );
+                effects.memory = Access::Read;
+                effects.flags_written = FLAGS_ALL_BUT_CARRY;
+            }
+            Opcode::InIncrement(_) | Opcode::InIncrementRepeat(_) | Opcode::InDecrement(_) | Opcode::InDecrementRepeat(_) => {
+                effects.registers(RegisterSet::BC | RegisterSet::HL, RegisterSet::B | RegisterSet::HL);
+                effects.memory = Access::Write;
+                effects.port = Access::Read;
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::OutIncrement(_) | Opcode::OutIncrementRepeat(_) | Opcode::OutDecrement(_) | Opcode::OutDecrementRepeat(_) => {
+                effects.registers(RegisterSet::BC | RegisterSet::HL, RegisterSet::B | RegisterSet::HL);
+                effects.memory = Access::Read;
+                effects.port = Access::Write;
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::In(op1, op2, _) => {
+                // The operand order isn't consistent across the encodings, the indirect one is the port
+                let (dst, port) = if matches!(op1, Operand::Register(_, true) | Operand::Immediate(_, true)) {
+                    (op2, op1)
+                } else {
+                    (op1, op2)
+                };
+                effects.port_address(port);
+                effects.port = Access::Read;
+                effects.write(dst);
+                // in a, [n] leaves the flags alone, in r, [c] sets them
+                if matches!(port, Operand::Register(_, true)) {
+                    effects.flags_written = FLAGS_ALL_BUT_CARRY;
+                }
+            }
+            Opcode::Out(port, src, _) => {
+                effects.port_address(port);
+                effects.port = Access::Write;
+                effects.read(src);
+            }
+            Opcode::Compare(op, _) => effects.alu(op, false),
+            Opcode::And(op, _) | Opcode::Or(op, _) | Opcode::Xor(op, _) | Opcode::Subtract(op, _) => effects.alu(op, true),
+            Opcode::Add(dst, src, _) | Opcode::AddCarry(dst, src, _) | Opcode::SubtractCarry(dst, src, _) => {
+                let with_carry = !matches!(opcode, Opcode::Add(_, _, _));
+                effects.read(dst);
+                effects.read(src);
+                effects.write(dst);
+                effects.flags_written = match dst {
+                    Operand::Register(Register::Reg16(_), false) if !with_carry => FLAGS_ACCUMULATOR_ROTATE,
+                    _ => FLAGS_ALL,
+                };
+                if with_carry {
+                    effects.flags_read = FLAG_C;
+                }
+            }
+            Opcode::Increment(op, _) | Opcode::Decrement(op, _) => {
+                effects.read(op);
+                effects.write(op);
+                if !matches!(op, Operand::Register(Register::Reg16(_), false)) {
+                    effects.flags_written = FLAGS_ALL_BUT_CARRY;
+                }
+            }
+            Opcode::JumpRelative(condition, _, _) => {
+                effects.condition(condition);
+                effects.registers(RegisterSet::PC, RegisterSet::PC);
+            }
+            Opcode::DecrementAndJumpRelative(_, _) => {
+                effects.registers(RegisterSet::B | RegisterSet::PC, RegisterSet::B | RegisterSet::PC);
+            }
+            Opcode::Jump(condition, target, _) => {
+                effects.condition(condition);
+                // jp [hl] loads PC from hl, it doesn't dereference it
+                if let Operand::Register(register, _) = target {
+                    effects.registers_read = RegisterSet::of(register);
+                }
+                effects.registers_written = RegisterSet::PC;
+            }
+            Opcode::Call(condition, _, _) | Opcode::Return(condition, _) => {
+                effects.condition(condition);
+                effects.registers(RegisterSet::SP | RegisterSet::PC, RegisterSet::SP | RegisterSet::PC);
+                effects.memory = if matches!(opcode, Opcode::Call(_, _, _)) {
+                    Access::Write
+                } else {
+                    Access::Read
+                };
+            }
+            Opcode::Restart(_, _) => {
+                effects.registers(RegisterSet::SP | RegisterSet::PC, RegisterSet::SP | RegisterSet::PC);
+                effects.memory = Access::Write;
+            }
+            Opcode::ReturnFromIrq(_) | Opcode::ReturnFromNmi(_) => {
+                effects.registers(RegisterSet::SP, RegisterSet::SP | RegisterSet::PC);
+                effects.memory = Access::Read;
+            }
+            Opcode::Push(register, _) => {
+                effects.registers(RegisterSet::of(register) | RegisterSet::SP, RegisterSet::SP);
+                effects.memory = Access::Write;
+                if RegisterSet::of(register).contains(RegisterSet::F) {
+                    effects.flags_read = FLAGS_ALL;
+                }
+            }
+            Opcode::Pop(register, _) => {
+                effects.registers(RegisterSet::SP, RegisterSet::of(register) | RegisterSet::SP);
+                effects.memory = Access::Read;
+                if RegisterSet::of(register).contains(RegisterSet::F) {
+                    effects.flags_written = FLAGS_ALL;
+                }
+            }
+            Opcode::Exchange(op1, op2, _) => {
+                effects.read(op1);
+                effects.read(op2);
+                effects.write(op1);
+                effects.write(op2);
+                if effects.registers_read.contains(RegisterSet::F) {
+                    effects.flags_read = FLAGS_ALL;
+                    effects.flags_written = FLAGS_ALL;
+                }
+            }
+            Opcode::ExchangeAll(_) => {
+                let registers = RegisterSet::BC
+                    | RegisterSet::DE
+                    | RegisterSet::HL
+                    | RegisterSet::BC_SHADOW
+                    | RegisterSet::DE_SHADOW
+                    | RegisterSet::HL_SHADOW;
+                effects.registers(registers, registers);
+            }
+            Opcode::TestBit(_, op, _) => {
+                effects.read(op);
+                effects.flags_written = FLAGS_ALL_BUT_CARRY;
+            }
+            Opcode::ResetBit(_, op, _) | Opcode::SetBit(_, op, _) => {
+                effects.read(op);
+                effects.write(op);
+            }
+            Opcode::ResetBitStore(_, op, dst, _) | Opcode::SetBitStore(_, op, dst, _) => {
+                effects.read(op);
+                effects.write(op);
+                effects.write(dst);
+            }
+            Opcode::RotateLeftCarry(op, _)
+            | Opcode::RotateRightCarry(op, _)
+            | Opcode::ShiftLeftArithmetic(op, _)
+            | Opcode::ShiftRightArithmetic(op, _)
+            | Opcode::ShiftLeftLogical(op, _)
+            | Opcode::ShiftRightLogical(op, _) => {
+                effects.read(op);
+                effects.write(op);
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::RotateLeft(op, _) | Opcode::RotateRight(op, _) => {
+                effects.read(op);
+                effects.write(op);
+                effects.flags_read = FLAG_C;
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::RotateLeftCarryStore(op, dst, _)
+            | Opcode::RotateRightCarryStore(op, dst, _)
+            | Opcode::ShiftLeftArithmeticStore(op, dst, _)
+            | Opcode::ShiftRightArithmeticStore(op, dst, _)
+            | Opcode::ShiftLeftLogicalStore(op, dst, _)
+            | Opcode::ShiftRightLogicalStore(op, dst, _)
+            | Opcode::RotateLeftStore(op, dst, _)
+            | Opcode::RotateRightStore(op, dst, _) => {
+                effects.read(op);
+                effects.write(op);
+                effects.write(dst);
+                if matches!(opcode, Opcode::RotateLeftStore(_, _, _) | Opcode::RotateRightStore(_, _, _)) {
+                    effects.flags_read = FLAG_C;
+                }
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::RotateLeftCarryAccumulator(_) | Opcode::RotateRightCarryAccumulator(_) => {
+                effects.registers(RegisterSet::A, RegisterSet::A);
+                effects.flags_written = FLAGS_ACCUMULATOR_ROTATE;
+            }
+            Opcode::RotateLeftAccumulator(_) | Opcode::RotateRightAccumulator(_) => {
+                effects.registers(RegisterSet::A, RegisterSet::A);
+                effects.flags_read = FLAG_C;
+                effects.flags_written = FLAGS_ACCUMULATOR_ROTATE;
+            }
+            Opcode::RotateLeftDecimal(_) | Opcode::RotateRightDecimal(_) => {
+                effects.registers(RegisterSet::A | RegisterSet::HL, RegisterSet::A);
+                effects.memory = Access::ReadWrite;
+                effects.flags_written = FLAGS_ALL_BUT_CARRY;
+            }
+            Opcode::Complement(_) => {
+                effects.registers(RegisterSet::A, RegisterSet::A);
+                effects.flags_written = FLAGS_COMPLEMENT;
+            }
+            Opcode::Negate(_) => {
+                effects.registers(RegisterSet::A, RegisterSet::A);
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::DecimalAdjustAccumulator(_) => {
+                effects.registers(RegisterSet::A, RegisterSet::A);
+                effects.flags_read = FLAG_H | FLAG_N | FLAG_C;
+                effects.flags_written = FLAGS_ALL;
+            }
+            Opcode::InvertCarry(_) | Opcode::SetCarryFlag(_) => {
+                // F3/F5 are copied from A, merged with the previous F3/F5 unless the instruction before computed them
+                effects.registers_read = RegisterSet::A;
+                effects.flags_read = FLAG_F5 | FLAG_F3;
+                if matches!(opcode, Opcode::InvertCarry(_)) {
+                    effects.flags_read |= FLAG_C;
+                }
+                effects.flags_written = FLAGS_ACCUMULATOR_ROTATE;
+            }
+            Opcode::DisableInterrupts(_)
+            | Opcode::EnableInterrupts(_)
+            | Opcode::SetInterruptMode(_, _)
+            | Opcode::NoOperation(_)
+            | Opcode::Halt(_)
+            | Opcode::Unknown(_) => (),
+        }
+
+        effects
+    }
+
+    fn registers(&mut self, read: RegisterSet, written: RegisterSet) {
+        self.registers_read = self.registers_read | read;
+        self.registers_written = self.registers_written | written;
+    }
+
+    // A source operand, indirect operands read the address register and memory
+    fn read(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Register(register, indirect) => {
+                self.registers_read = self.registers_read | RegisterSet::of(register);
+                if *indirect {
+                    self.memory = self.memory | Access::Read;
+                }
+            }
+            Operand::Immediate(_, true) => self.memory = self.memory | Access::Read,
+            Operand::Immediate(_, false) => (),
+        }
+    }
+
+    // A destination operand, indirect operands still only read their address register
+    fn write(&mut self, operand: &Operand) {
+        match operand {
+            Operand::Register(register, true) => {
+                self.registers_read = self.registers_read | RegisterSet::of(register);
+                self.memory = self.memory | Access::Write;
+            }
+            Operand::Register(register, false) => {
+                self.registers_written = self.registers_written | RegisterSet::of(register);
+            }
+            Operand::Immediate(_, true) => self.memory = self.memory | Access::Write,
+            Operand::Immediate(_, false) => (),
+        }
+    }
+
+    // [c] puts bc on the address bus, [n] puts a in the upper half
+    fn port_address(&mut self, port: &Operand) {
+        self.registers_read = self.registers_read
+            | match port {
+                Operand::Register(_, _) => RegisterSet::BC,
+                Operand::Immediate(_, _) => RegisterSet::A,
+            };
+    }
+
+    fn alu(&mut self, operand: &Operand, writes_accumulator: bool) {
+        self.registers_read = self.registers_read | RegisterSet::A;
+        self.read(operand);
+        if writes_accumulator {
+            self.registers_written = self.registers_written | RegisterSet::A;
+        }
+        self.flags_written = FLAGS_ALL;
+    }
+
+    fn condition(&mut self, condition: &Condition) {
+        self.flags_read = match condition {
+            Condition::NotZero | Condition::Zero => FLAG_Z,
+            Condition::NotSign | Condition::Sign => FLAG_S,
+            Condition::NotCarry | Condition::Carry => FLAG_C,
+            Condition::NotParityOrOverflow | Condition::ParityOrOverflow => FLAG_PV,
+            Condition::None => 0,
+        };
+    }
+}
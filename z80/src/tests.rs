@@ -0,0 +1,303 @@
+use crate::assembler::Assembler;
+use crate::bus::Z80Bus;
+use crate::cpu::{Cpu, CpuError, Flags};
+use crate::disassembler::{DecodeError, Disassembler};
+use crate::instruction::{Immediate, Opcode};
+use crate::metadata::{self, Access, Encoding, RegisterSet, FLAGS_ALL, FLAG_C, FLAG_Z};
+
+#[test]
+fn test_assemble_program() {
+    let source = r#"
+                org #100
+        start:  ld a, 5         ; counter
+        loop:   dec a
+                jr nz, loop
+                jp start
+                ld hl, data+1
+                ld [ix-2], a
+        count   equ 3
+        data:   db 1, count * 2, "ab"
+                dw start, $
+    "#;
+
+    let program = Assembler::new(source).assemble().unwrap();
+    assert_eq!(program.origin, 0x100);
+    assert_eq!(
+        program.bytes,
+        vec![
+            0x3e, 0x05, // ld a, 5
+            0x3d, // dec a
+            0x20, 0xfd, // jr nz, loop
+            0xc3, 0x00, 0x01, // jp start
+            0x21, 0x0f, 0x01, // ld hl, data+1
+            0xdd, 0x77, 0xfe, // ld [ix-2], a
+            0x01, 0x06, 0x61, 0x62, // db
+            0x00, 0x01, 0x12, 0x01, // dw
+        ]
+    );
+    assert_eq!(program.symbols["data"], 0x10e);
+
+    // A number is a target address just like a label
+    assert_eq!(Assembler::assemble_instruction("jr #0105", 0x100), Ok(vec![0x18, 0x03]));
+    assert_eq!(Assembler::assemble_instruction("djnz #00fe", 0x100), Ok(vec![0x10, 0xfc]));
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert!(Assembler::new("ld a, 256").assemble().is_err());
+    assert!(Assembler::new("jr far\norg #1000\nfar: nop").assemble().is_err());
+    assert!(Assembler::new("jp nowhere").assemble().is_err());
+    assert!(Assembler::new("ld [bc], b").assemble().is_err());
+    assert!(Assembler::new("rst #39").assemble().is_err());
+    assert!(Assembler::new("here: nop\nhere: nop").assemble().is_err());
+    assert!(Assembler::new("jr #0100").assemble().is_err());
+}
+
+#[test]
+fn test_decode_errors() {
+    // jp #1234 cut off after the low byte
+    let data = [0x00, 0xc3, 0x34];
+    let disassembler = Disassembler::new(&data);
+    assert_eq!(disassembler.decode(0).unwrap().length, 1);
+    assert_eq!(disassembler.decode(1).err(), Some(DecodeError::Truncated { offset: 1 }));
+    assert_eq!(disassembler.decode(3).err(), Some(DecodeError::Truncated { offset: 3 }));
+    assert!(disassembler.disassemble().is_err());
+
+    // A prefix without the rest of the encoding
+    assert_eq!(
+        Disassembler::new(&[0xdd, 0xcb]).decode(0).err(),
+        Some(DecodeError::Truncated { offset: 0 })
+    );
+
+    let data = [0xed, 0xff, 0x00, 0x00];
+    assert_eq!(
+        Disassembler::new(&data).decode(0).err(),
+        Some(DecodeError::UnknownOpcode { offset: 0, bytes: data })
+    );
+
+    let instructions = Disassembler::new(&[0x3e, 0x01, 0xc9]).disassemble().unwrap();
+    assert_eq!(
+        instructions.iter().map(|instruction| instruction.offset).collect::<Vec<_>>(),
+        vec![0, 2]
+    );
+}
+
+#[test]
+fn test_opcode_metadata() {
+    let info = |bytes: &[u8]| *Disassembler::new(bytes).decode(0).unwrap().info().unwrap();
+
+    // ld a, [ix+#05]
+    let ld = info(&[0xdd, 0x7e, 0x05]);
+    assert_eq!((ld.length, ld.cycles.taken), (3, 19));
+    assert_eq!((ld.registers_read, ld.registers_written), (RegisterSet::IX, RegisterSet::A));
+    assert_eq!((ld.memory, ld.flags_written), (Access::Read, 0));
+
+    // jr nz, -2
+    let jr = info(&[0x20, 0xfe]);
+    assert_eq!((jr.cycles.taken, jr.cycles.not_taken), (12, 7));
+    assert_eq!((jr.flags_read, jr.flags_written), (FLAG_Z, 0));
+    assert!(jr.registers_written.contains(RegisterSet::PC));
+
+    // ldir, out [c], a and in a, [#01]
+    let ldir = info(&[0xed, 0xb0]);
+    assert_eq!((ldir.memory, ldir.port), (Access::ReadWrite, Access::None));
+    assert!(ldir
+        .registers_written
+        .contains(RegisterSet::BC | RegisterSet::DE | RegisterSet::HL));
+    let out = info(&[0xed, 0x79]);
+    assert_eq!(
+        (out.port, out.memory, out.registers_read),
+        (Access::Write, Access::None, RegisterSet::A | RegisterSet::BC)
+    );
+    let in_a = info(&[0xdb, 0x01]);
+    assert_eq!((in_a.port, in_a.flags_written), (Access::Read, 0));
+
+    // push af, sbc hl, de and rl [iy+#01], b
+    assert_eq!(info(&[0xf5]).flags_read, FLAGS_ALL);
+    assert_eq!(info(&[0xed, 0x52]).flags_read, FLAG_C);
+    let rl = info(&[0xfd, 0xcb, 0x01, 0x10]);
+    assert_eq!(
+        (rl.memory, rl.registers_written, rl.flags_read),
+        (Access::ReadWrite, RegisterSet::B, FLAG_C)
+    );
+
+    // The table covers every instruction the decoder knows whatever its operands, with the decoder's length
+    for encoding in Encoding::all() {
+        for filler in [0x00, 0xff] {
+            let bytes = encoding.pattern().map(|byte| byte.unwrap_or(filler));
+            if let Ok(instruction) = Disassembler::new(&bytes).decode(0) {
+                assert_eq!(instruction.info().map(|info| info.length), Some(instruction.length));
+            }
+        }
+    }
+    assert!(metadata::table().count() > 1200);
+}
+
+// Every encodable opcode must survive disassemble -> assemble -> disassemble, including the operands. The bytes
+// may differ where the disassembler maps undocumented duplicates to the same mnemonic.
+#[test]
+fn test_round_trip() {
+    let fillers = [[0x00, 0x00, 0x00], [0x12, 0x34, 0x56], [0x7f, 0x80, 0xff], [0xff, 0xfe, 0x81]];
+
+    for encoding in Encoding::all() {
+        let pattern = encoding.pattern();
+        for filler in fillers {
+            let mut data = [0; 8];
+            for idx in 0..4 {
+                data[idx] = pattern[idx].unwrap_or(filler[(idx + 2) % 3]);
+            }
+
+            let Ok(expected) = Disassembler::new(&data).decode(0) else {
+                continue;
+            };
+            let text = expected.opcode.to_string();
+
+            // jr and djnz print their displacement but assemble from the target address
+            let source = match expected.opcode {
+                Opcode::JumpRelative(_, Immediate::S8(displacement), _)
+                | Opcode::DecrementAndJumpRelative(Immediate::S8(displacement), _) => {
+                    let operand = text.rfind(' ').unwrap() + 1;
+                    format!("{}{}", &text[..operand], 0x1000 + 2 + displacement as i32)
+                }
+                _ => text.clone(),
+            };
+
+            let bytes = Assembler::assemble_instruction(&source, 0x1000).unwrap_or_else(|msg| panic!("{}: {}", source, msg));
+            let mut decoded = bytes.clone();
+            decoded.resize(8, 0);
+            let actual = Disassembler::new(&decoded).decode(0).unwrap();
+
+            assert_eq!(actual.opcode.to_string(), text, "{:02x?}", bytes);
+            assert_eq!(actual.length, bytes.len(), "{}", text);
+        }
+    }
+}
+
+// 64 KB of RAM and a port log, all a machine needs to run the CPU
+struct FlatBus {
+    memory: Vec<u8>,
+    writes: Vec<(u16, u8)>,
+    irq: bool,
+}
+
+impl Z80Bus for FlatBus {
+    type Error = ();
+
+    fn read(&mut self, address: u16) -> Result<u8, ()> {
+        Ok(self.memory[address as usize])
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), ()> {
+        self.memory[address as usize] = value;
+        Ok(())
+    }
+
+    fn read_io(&mut self, port: u16) -> Result<u8, ()> {
+        Ok((port >> 8) as u8)
+    }
+
+    fn write_io(&mut self, port: u16, value: u8) -> Result<(), ()> {
+        self.writes.push((port, value));
+        Ok(())
+    }
+
+    fn irq_pending(&mut self) -> bool {
+        self.irq
+    }
+}
+
+// Assembles a program at address 0 and runs it until it halts
+fn run_program(source: &str) -> (Cpu, FlatBus) {
+    let program = Assembler::new(source).assemble().unwrap();
+
+    let mut bus = FlatBus {
+        memory: vec![0; 0x10000],
+        writes: Vec::new(),
+        irq: false,
+    };
+    bus.memory[..program.bytes.len()].copy_from_slice(&program.bytes);
+
+    let mut cpu = Cpu::new();
+    while !cpu.halted {
+        match cpu.tick(&mut bus) {
+            Ok(_) | Err(CpuError::JumpNotTaken) => (),
+            Err(error) => panic!("{:?}", error),
+        }
+    }
+
+    (cpu, bus)
+}
+
+#[test]
+fn test_cpu_on_flat_memory() {
+    let source = r#"
+                ld sp, #f000
+                im 1
+                ei
+                ld b, 3
+        loop:   ld a, b
+                out [#7e], a
+                djnz loop
+                ld bc, #1234
+                in a, [c]
+                out [c], a
+                halt
+    "#;
+    let (mut cpu, mut bus) = run_program(source);
+
+    // The upper half of the port comes from A for `out [n], a` and from B for `out [c], a`
+    assert_eq!(bus.writes, vec![(0x037e, 3), (0x027e, 2), (0x017e, 1), (0x1234, 0x12)]);
+
+    // The interrupt leaves the HALT, IM 1 jumps to #0038 and returns behind it
+    let halt = cpu.registers.pc;
+    bus.irq = true;
+    cpu.tick(&mut bus).unwrap();
+    assert!(!cpu.halted);
+    assert_eq!(cpu.registers.pc, 0x0039);
+    assert_eq!(bus.read_word(0xeffe), Ok(halt + 1));
+}
+
+#[test]
+fn test_memptr() {
+    let wz = |source: &str| run_program(source).0.registers.wz;
+
+    assert_eq!(wz("ld a, [#1234] \n halt"), 0x1235);
+    // The high byte is A, the low byte (C + 1) without a carry into B
+    assert_eq!(wz("ld a, #56 \n ld bc, #12ff \n ld [bc], a \n halt"), 0x5600);
+    assert_eq!(wz("jp target \n nop \n target: halt"), 0x0004);
+    assert_eq!(wz("ld sp, #f000 \n call target \n halt \n target: halt"), 0x0007);
+}
+
+#[test]
+fn test_undocumented_flags() {
+    let xy = |source: &str| run_program(source).0.registers.f & (Flags::F3 | Flags::F5);
+
+    // bit n, [hl] takes F3/F5 from the high byte of MEMPTR instead of the tested byte
+    assert_eq!(xy("ld a, [#2800] \n ld hl, #0100 \n bit 0, [hl] \n halt"), Flags::F3 | Flags::F5);
+    assert_eq!(
+        xy("ld hl, #0100 \n ld [hl], #28 \n ld a, [#0000] \n bit 0, [hl] \n halt"),
+        Flags::empty()
+    );
+
+    // ldi uses bits 3 and 1 of A + [hl], cpi of A - [hl] - H
+    assert_eq!(
+        xy("ld hl, #0100 \n ld [hl], #02 \n ld a, #08 \n ldi \n halt"),
+        Flags::F3 | Flags::F5
+    );
+    assert_eq!(xy("ld hl, #0100 \n ld [hl], #02 \n ld a, #10 \n cpi \n halt"), Flags::F3);
+
+    // ini and outi copy them from B after the decrement
+    assert_eq!(xy("ld hl, #0100 \n ld b, #29 \n ini \n halt"), Flags::F3 | Flags::F5);
+    assert_eq!(xy("ld hl, #0100 \n ld b, #29 \n outi \n halt"), Flags::F3 | Flags::F5);
+
+    // scf and ccf merge A with the previous F3/F5, unless the instruction before computed the flags
+    assert_eq!(xy("ld a, #28 \n ccf \n halt"), Flags::F3 | Flags::F5);
+    assert_eq!(
+        xy("ld sp, #f000 \n ld bc, #0028 \n push bc \n pop af \n scf \n halt"),
+        Flags::F3 | Flags::F5
+    );
+    assert_eq!(
+        xy("ld sp, #f000 \n ld bc, #0000 \n push bc \n pop af \n cp #28 \n scf \n halt"),
+        Flags::empty()
+    );
+}
@@ -0,0 +1,113 @@
+// T-state costs as documented in "The Undocumented Z80 Documented" and the Zilog user manual.
+// A zero entry marks a prefix byte, its cost is resolved through the matching prefix table.
+
+#[rustfmt::skip]
+const UNPREFIXED: [usize; 256] = [
+//  0   1   2   3   4   5   6   7   8   9   a   b   c   d   e   f
+    4, 10,  7,  6,  4,  4,  7,  4,  4, 11,  7,  6,  4,  4,  7,  4, // 0x00
+    8, 10,  7,  6,  4,  4,  7,  4, 12, 11,  7,  6,  4,  4,  7,  4, // 0x10
+    7, 10, 16,  6,  4,  4,  7,  4,  7, 11, 16,  6,  4,  4,  7,  4, // 0x20
+    7, 10, 13,  6, 11, 11, 10,  4,  7, 11, 13,  6,  4,  4,  7,  4, // 0x30
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0x40
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0x50
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0x60
+    7,  7,  7,  7,  7,  7,  4,  7,  4,  4,  4,  4,  4,  4,  7,  4, // 0x70
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0x80
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0x90
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0xa0
+    4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4, // 0xb0
+    5, 10, 10, 10, 10, 11,  7, 11,  5, 10, 10,  0, 10, 17,  7, 11, // 0xc0
+    5, 10, 10, 11, 10, 11,  7, 11,  5,  4, 10, 11, 10,  0,  7, 11, // 0xd0
+    5, 10, 10, 19, 10, 11,  7, 11,  5,  4, 10,  4, 10,  0,  7, 11, // 0xe0
+    5, 10, 10,  4, 10, 11,  7, 11,  5,  6, 10,  4, 10,  0,  7, 11, // 0xf0
+];
+
+#[rustfmt::skip]
+const EXTENDED: [usize; 64] = [
+//  0   1   2   3   4   5   6   7   8   9   a   b   c   d   e   f
+   12, 12, 15, 20,  8, 14,  8,  9, 12, 12, 15, 20,  8, 14,  8,  9, // 0x40
+   12, 12, 15, 20,  8, 14,  8,  9, 12, 12, 15, 20,  8, 14,  8,  9, // 0x50
+   12, 12, 15, 20,  8, 14,  8, 18, 12, 12, 15, 20,  8, 14,  8, 18, // 0x60
+   12, 12, 15, 20,  8, 14,  8,  8, 12, 12, 15, 20,  8, 14,  8,  8, // 0x70
+];
+
+/// T-states consumed by an instruction. `taken` applies when a conditional branch is taken or
+/// a repeat instruction loops, `not_taken` otherwise. Both are equal for unconditional instructions.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Cycles {
+    pub taken: usize,
+    pub not_taken: usize,
+}
+
+impl Cycles {
+    fn new(not_taken: usize, extra_if_taken: usize) -> Cycles {
+        Cycles {
+            taken: not_taken + extra_if_taken,
+            not_taken,
+        }
+    }
+}
+
+pub(crate) fn lookup(sequence: (u8, u8, u8, u8)) -> Cycles {
+    match sequence {
+        (0xcb, opcode, _, _) if opcode & 0b0000_0111 != 6 => Cycles::new(8, 0),
+        (0xcb, opcode, _, _) => Cycles::new(if is_bit_test(opcode) { 12 } else { 15 }, 0),
+        (0xed, opcode, _, _) => extended(opcode),
+        // DDCB/FDCB opcodes always operate on (ix+d), the register field only selects the undocumented store target
+        (0xdd | 0xfd, 0xcb, _, opcode) => Cycles::new(if is_bit_test(opcode) { 20 } else { 23 }, 0),
+        (0xdd | 0xfd, opcode, _, _) => {
+            // The prefix costs 4 T-states. Replacing (hl) by (ix+d) adds the displacement fetch and address calculation.
+            let displacement = match opcode {
+                0x36 => 5,
+                0x34 | 0x35 => 8,
+                0x40..=0xbf if opcode != 0x76 && (opcode & 0b0000_0111 == 6 || (0x70..0x78).contains(&opcode)) => 8,
+                _ => 0,
+            };
+            Cycles::new(4 + UNPREFIXED[opcode as usize] + displacement, branch_penalty(opcode))
+        }
+        (opcode, _, _, _) => Cycles::new(UNPREFIXED[opcode as usize], branch_penalty(opcode)),
+    }
+}
+
+/// T-state at which the I/O machine cycle of an `in`/`out` starts, after the opcode fetches and anything that
+/// comes before the port access
+pub(crate) fn port_cycle(sequence: (u8, u8, u8, u8)) -> Option<usize> {
+    match sequence {
+        // in a, (n) and out (n), a
+        (0xd3 | 0xdb, _, _, _) => Some(7),
+        // in r, (c) and out (c), r
+        (0xed, opcode, _, _) if opcode & 0b1100_0110 == 0b0100_0000 => Some(8),
+        // ini, ind, inir, indr store the byte after the port read
+        (0xed, 0xa2 | 0xaa | 0xb2 | 0xba, _, _) => Some(9),
+        // outi, outd, otir, otdr read the byte from memory first
+        (0xed, 0xa3 | 0xab | 0xb3 | 0xbb, _, _) => Some(12),
+        _ => None,
+    }
+}
+
+fn is_bit_test(opcode: u8) -> bool {
+    (0x40..0x80).contains(&opcode)
+}
+
+fn extended(opcode: u8) -> Cycles {
+    match opcode {
+        0x40..=0x7f => Cycles::new(EXTENDED[(opcode - 0x40) as usize], 0),
+        // ldi, cpi, ini, outi, ldd, cpd, ind, outd
+        0xa0..=0xa3 | 0xa8..=0xab => Cycles::new(16, 0),
+        // ldir, cpir, inir, otir, lddr, cpdr, indr, otdr
+        0xb0..=0xb3 | 0xb8..=0xbb => Cycles::new(16, 5),
+        _ => Cycles::new(8, 0),
+    }
+}
+
+fn branch_penalty(opcode: u8) -> usize {
+    match opcode {
+        // djnz, jr cc
+        0x10 | 0x20 | 0x28 | 0x30 | 0x38 => 5,
+        // ret cc
+        0xc0 | 0xc8 | 0xd0 | 0xd8 | 0xe0 | 0xe8 | 0xf0 | 0xf8 => 6,
+        // call cc
+        0xc4 | 0xcc | 0xd4 | 0xdc | 0xe4 | 0xec | 0xf4 | 0xfc => 7,
+        _ => 0,
+    }
+}